@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS processes_selection_presets (
+    process_id INTEGER PRIMARY KEY,
+    preset TEXT NOT NULL
+);
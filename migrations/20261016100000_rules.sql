@@ -0,0 +1,35 @@
+CREATE TABLE IF NOT EXISTS rules (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    process_name TEXT NOT NULL,
+    spawner_name TEXT,
+    preset TEXT NOT NULL,
+    is_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    ordering INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS rules_selected_cpus (
+    rule_id INTEGER NOT NULL,
+    cpu_id INTEGER NOT NULL CHECK(cpu_id >= 0),
+    PRIMARY KEY (rule_id, cpu_id)
+);
+
+-- Carry the fixed simulator/spawner pair over as the built-in rule.
+INSERT OR IGNORE INTO rules (id, name, process_name, spawner_name, preset, is_enabled, ordering)
+SELECT
+    0,
+    'iRacing',
+    COALESCE((SELECT name FROM processes WHERE id = 0), 'iRacingSim64DX11.exe'),
+    COALESCE((SELECT name FROM processes WHERE id = 1), 'iRacingUI.exe'),
+    CASE
+        WHEN EXISTS (SELECT 1 FROM processes WHERE id = 0) THEN COALESCE(
+            (SELECT preset FROM processes_selection_presets WHERE process_id = 0),
+            '{"kind":"custom"}'
+        )
+        ELSE '{"kind":"all"}'
+    END,
+    TRUE,
+    0;
+
+INSERT OR IGNORE INTO rules_selected_cpus (rule_id, cpu_id)
+SELECT 0, cpu_id FROM processes_selected_cpus WHERE process_id = 0;
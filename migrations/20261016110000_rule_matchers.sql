@@ -0,0 +1,17 @@
+ALTER TABLE rules ADD COLUMN matcher TEXT NOT NULL DEFAULT '{"conditions":[]}';
+ALTER TABLE rules ADD COLUMN spawner_matcher TEXT;
+
+UPDATE rules SET matcher = json_object(
+    'conditions',
+    json_array(json_object('kind', 'exact_name', 'value', process_name))
+)
+WHERE process_name != '';
+
+UPDATE rules SET spawner_matcher = json_object(
+    'conditions',
+    json_array(json_object('kind', 'exact_name', 'value', spawner_name))
+)
+WHERE spawner_name IS NOT NULL;
+
+ALTER TABLE rules DROP COLUMN process_name;
+ALTER TABLE rules DROP COLUMN spawner_name;
@@ -0,0 +1,3 @@
+ALTER TABLE rules ADD COLUMN enforcement_strategy TEXT NOT NULL DEFAULT 'inherit_via_spawner';
+
+UPDATE rules SET enforcement_strategy = 'direct' WHERE spawner_matcher IS NULL;
@@ -0,0 +1 @@
+ALTER TABLE rules ADD COLUMN spawner_reset TEXT NOT NULL DEFAULT '{"kind":"all"}';
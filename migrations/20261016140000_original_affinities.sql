@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS original_affinities (
+    process_id INTEGER NOT NULL CHECK(process_id >= 0),
+    start_time INTEGER NOT NULL,
+    rule_id INTEGER NOT NULL,
+    cpus TEXT NOT NULL,
+    PRIMARY KEY (process_id, start_time)
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS worker_heartbeats (
+    id INTEGER PRIMARY KEY NOT NULL CHECK(id = 0),
+    value TEXT NOT NULL
+);
@@ -0,0 +1,45 @@
+CREATE TABLE IF NOT EXISTS profiles (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    is_active BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+-- Only one profile is active at a time.
+CREATE UNIQUE INDEX IF NOT EXISTS profiles_is_active ON profiles (is_active) WHERE is_active;
+
+INSERT OR IGNORE INTO profiles (id, name, is_active) VALUES (0, 'Default', TRUE);
+
+-- Rule IDs are per profile, so each can have the built-in rule.
+CREATE TABLE rules_by_profile (
+    profile_id INTEGER NOT NULL,
+    id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    preset TEXT NOT NULL,
+    is_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    ordering INTEGER NOT NULL,
+    matcher TEXT NOT NULL DEFAULT '{"conditions":[]}',
+    spawner_matcher TEXT,
+    enforcement_strategy TEXT NOT NULL DEFAULT 'inherit_via_spawner',
+    spawner_reset TEXT NOT NULL DEFAULT '{"kind":"all"}',
+    PRIMARY KEY (profile_id, id)
+);
+
+INSERT INTO rules_by_profile (profile_id, id, name, preset, is_enabled, ordering, matcher, spawner_matcher, enforcement_strategy, spawner_reset)
+SELECT 0, id, name, preset, is_enabled, ordering, matcher, spawner_matcher, enforcement_strategy, spawner_reset
+FROM rules;
+
+DROP TABLE rules;
+ALTER TABLE rules_by_profile RENAME TO rules;
+
+CREATE TABLE rules_selected_cpus_by_profile (
+    profile_id INTEGER NOT NULL,
+    rule_id INTEGER NOT NULL,
+    cpu_id INTEGER NOT NULL CHECK(cpu_id >= 0),
+    PRIMARY KEY (profile_id, rule_id, cpu_id)
+);
+
+INSERT INTO rules_selected_cpus_by_profile (profile_id, rule_id, cpu_id)
+SELECT 0, rule_id, cpu_id FROM rules_selected_cpus;
+
+DROP TABLE rules_selected_cpus;
+ALTER TABLE rules_selected_cpus_by_profile RENAME TO rules_selected_cpus;
@@ -0,0 +1,9 @@
+-- Activates a profile while a matching program runs, the highest priority
+-- one wins when several match.
+CREATE TABLE IF NOT EXISTS profile_triggers (
+    id INTEGER PRIMARY KEY NOT NULL,
+    profile_id INTEGER NOT NULL,
+    matcher TEXT NOT NULL,
+    priority INTEGER NOT NULL DEFAULT 0,
+    is_enabled BOOLEAN NOT NULL DEFAULT TRUE
+);
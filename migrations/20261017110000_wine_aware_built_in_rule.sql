@@ -0,0 +1,8 @@
+-- The built-in rule's programs run under Proton on Linux, where `comm` is
+-- truncated, so they're matched by their Windows image names. Nothing looks at
+-- those elsewhere.
+UPDATE rules SET matcher = json_set(matcher, '$.is_wine_aware', json('true'))
+WHERE id = 0;
+
+UPDATE rules SET spawner_matcher = json_set(spawner_matcher, '$.is_wine_aware', json('true'))
+WHERE id = 0 AND spawner_matcher IS NOT NULL;
@@ -0,0 +1,685 @@
+use backon::{ExponentialBuilder, Retryable};
+use clap::{Args, Parser, Subcommand};
+use sqlx::SqlitePool;
+use sysinfo::System;
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info};
+#[cfg(target_os = "windows")]
+use windows::Win32::System::Console::{ATTACH_PARENT_PROCESS, AttachConsole};
+
+#[cfg(target_os = "linux")]
+use crate::systemd::{notify_ready, notify_stopping, notify_watchdog};
+use crate::{
+    errors::ResultBtAny,
+    instance::{InstanceKind, InstanceLock},
+    ipc::{
+        IpcNotification,
+        client::IpcClient,
+        server::{IpcState, spawn_ipc_server},
+    },
+    logging::{LogLevel, Logging, init_logging},
+    matcher::{MatchCondition, MatchConditionKind, ProcessMatcher},
+    persistence::{CpuSelections, PersistentStore, ProfileTrigger, Rule},
+    scanner::ProcessScanner,
+    topology::{CpuTopology, parse_cpu_list},
+    worker::{WorkerCommand, WorkerHeartbeat, run_worker_once, spawn_worker_task},
+};
+
+/// How long the worker gets to put processes back once the daemon's asked to
+/// stop, before it's abandoned.
+const WORKER_SHUTDOWN_TIMEOUT_SECONDS: u64 = 5;
+
+/// Opens the window when run without a command, or runs the daemon if built
+/// without the `gui` feature.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Prints the last heartbeat of whichever process is running the worker.
+    Status,
+    /// Lists or changes the rules.
+    #[command(subcommand)]
+    Rules(RulesCommand),
+    /// Lists, switches or changes the profiles, each with its own rules.
+    #[command(subcommand)]
+    Profiles(ProfilesCommand),
+    /// Lists or changes what switches profiles while programs are running.
+    #[command(subcommand)]
+    Triggers(TriggersCommand),
+    /// Has the running daemon apply the rules now.
+    Apply {
+        /// Applies them once and exits, leaving processes as applied, with or
+        /// without a daemon.
+        #[arg(long)]
+        once: bool,
+    },
+    /// Runs the worker without the window until interrupted, and as a
+    /// `Type=notify` systemd service.
+    Daemon,
+    /// Previews which processes the enabled rules would match.
+    Processes {
+        /// Only this rule, by ID.
+        #[arg(long = "match", value_name = "RULE_ID")]
+        rule_id: Option<i64>,
+    },
+}
+
+impl CliCommand {
+    /// Those that exit straight away only log warnings and errors, so what
+    /// they print isn't buried.
+    fn get_log_level(&self) -> LogLevel {
+        match self {
+            Self::Daemon => LogLevel::Info,
+            _ => LogLevel::Warn,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RulesCommand {
+    /// In the order they're matched.
+    List,
+    /// Adds a rule matching processes on one condition, after the others.
+    Add(RuleArgs),
+    Remove {
+        id: i64,
+    },
+    Enable {
+        id: i64,
+    },
+    Disable {
+        id: i64,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfilesCommand {
+    List,
+    /// Makes it the active profile, whose rules are applied.
+    Switch {
+        id: i64,
+    },
+    /// Adds a profile with a copy of its rules.
+    Duplicate {
+        id: i64,
+        name: String,
+    },
+    Rename {
+        id: i64,
+        name: String,
+    },
+    /// Along with its rules, it can't be the active profile.
+    Delete {
+        id: i64,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TriggersCommand {
+    /// Highest priority first.
+    List,
+    /// Activates the profile while a process matching one condition runs.
+    Add(TriggerArgs),
+    Remove {
+        id: i64,
+    },
+    Enable {
+        id: i64,
+    },
+    Disable {
+        id: i64,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct TriggerArgs {
+    profile_id: i64,
+    /// What's looked for, as `--kind` says.
+    pattern: String,
+    #[arg(long, value_enum, default_value_t = MatchConditionKind::ExactName)]
+    kind: MatchConditionKind,
+    /// The highest wins when several match.
+    #[arg(long, default_value_t = 0)]
+    priority: i64,
+    /// Sees the Windows image name of Wine/Proton processes.
+    #[arg(long)]
+    wine: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RuleArgs {
+    name: String,
+    /// What's looked for, as `--kind` says.
+    pattern: String,
+    #[arg(long, value_enum, default_value_t = MatchConditionKind::ExactName)]
+    kind: MatchConditionKind,
+    /// Like `0-3,8`, every CPU if not given.
+    #[arg(long)]
+    cpus: Option<String>,
+    /// Sees the Windows image name of Wine/Proton processes.
+    #[arg(long)]
+    wine: bool,
+}
+
+pub fn run_cli(cli_command: CliCommand) -> ResultBtAny<()> {
+    let logging = init_logging(cli_command.get_log_level());
+    // Built for the Windows subsystem, so there's no console to print to
+    // unless the parent's is borrowed.
+    #[cfg(target_os = "windows")]
+    unsafe {
+        _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let sqlite_pool = PersistentStore::create_pool().await?;
+        (async || PersistentStore::create_ddl(&sqlite_pool).await)
+            .retry(ExponentialBuilder::default())
+            .await?;
+
+        let mut system_info = System::new();
+        system_info.refresh_all();
+        info!("Refreshed system info.");
+        let topology = CpuTopology::load(system_info.cpus().len());
+
+        match cli_command {
+            CliCommand::Status => match PersistentStore::load_heartbeat(&sqlite_pool).await? {
+                Some(worker_heartbeat) => print_heartbeat(&worker_heartbeat),
+                None => println!("The worker hasn't run yet."),
+            },
+            CliCommand::Rules(rules_command) => {
+                run_rules_command(rules_command, &topology, &sqlite_pool).await?;
+            }
+            CliCommand::Profiles(profiles_command) => {
+                run_profiles_command(profiles_command, &sqlite_pool).await?;
+            }
+            CliCommand::Triggers(triggers_command) => {
+                run_triggers_command(triggers_command, &sqlite_pool).await?;
+            }
+            CliCommand::Apply { once: true } => {
+                set_log_level(&logging, &topology, &sqlite_pool).await?;
+                let Some(_instance_lock) = InstanceLock::acquire(InstanceKind::Daemon)? else {
+                    println!("Handing off to the running daemon.");
+                    let worker_heartbeat = apply_with_daemon().await?;
+                    print_heartbeat(&worker_heartbeat);
+                    return Ok(());
+                };
+                let worker_heartbeat = run_worker_once(sqlite_pool).await?;
+                print_heartbeat(&worker_heartbeat);
+            }
+            CliCommand::Apply { once: false } => {
+                if InstanceLock::acquire(InstanceKind::Daemon)?.is_some() {
+                    Err("The daemon isn't running, start it with `daemon` or use `apply --once`.")?
+                }
+                let worker_heartbeat = apply_with_daemon().await?;
+                print_heartbeat(&worker_heartbeat);
+            }
+            CliCommand::Daemon => {
+                set_log_level(&logging, &topology, &sqlite_pool).await?;
+                let Some(_instance_lock) = InstanceLock::acquire(InstanceKind::Daemon)? else {
+                    println!("The daemon's already running.");
+                    match IpcClient::connect().await?.get_heartbeat().await? {
+                        Some(worker_heartbeat) => print_heartbeat(&worker_heartbeat),
+                        None => println!("The worker hasn't run yet."),
+                    }
+                    return Ok(());
+                };
+                run_daemon(sqlite_pool, topology).await?;
+            }
+            CliCommand::Processes { rule_id } => {
+                let persistent_store = PersistentStore::load(&topology, &sqlite_pool).await?;
+                let rules = match rule_id {
+                    Some(rule_id) => vec![get_rule(&persistent_store.rules, rule_id)?],
+                    None => persistent_store
+                        .rules
+                        .iter()
+                        .filter(|rule| rule.is_enabled)
+                        .collect(),
+                };
+                let mut process_scanner = ProcessScanner::new(system_info);
+                process_scanner.scan();
+                for rule in rules {
+                    println!("{} {}", rule.id, rule.name);
+                    print_matches("Matches", &rule.matcher, &process_scanner)?;
+                    if let Some(spawner_matcher) = &rule.spawner_matcher {
+                        print_matches("Spawners", spawner_matcher, &process_scanner)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// From settings, as the window would.
+async fn set_log_level(
+    logging: &Logging,
+    topology: &CpuTopology,
+    sqlite_pool: &SqlitePool,
+) -> ResultBtAny<()> {
+    let persistent_store = PersistentStore::load(topology, sqlite_pool).await?;
+    _ = logging
+        .set_level(persistent_store.settings.log_level)
+        .inspect_err(|e| error!("{:?}", e));
+    Ok(())
+}
+
+async fn run_daemon(sqlite_pool: SqlitePool, topology: CpuTopology) -> ResultBtAny<()> {
+    let (status_sender, mut status_receiver) = watch::channel(None);
+    let (command_sender, command_receiver) = mpsc::unbounded_channel();
+    let worker_task = spawn_worker_task(sqlite_pool.clone(), status_sender, command_receiver);
+    spawn_ipc_server(IpcState::new(
+        sqlite_pool,
+        topology,
+        status_receiver.clone(),
+        command_sender.clone(),
+    ));
+    #[cfg(target_os = "linux")]
+    {
+        _ = notify_ready().inspect_err(|e| error!("{:?}", e));
+    }
+
+    let shutdown_signal = wait_for_shutdown_signal();
+    tokio::pin!(shutdown_signal);
+    loop {
+        tokio::select! {
+            is_signaled = &mut shutdown_signal => {
+                is_signaled?;
+                break;
+            }
+            is_changed = status_receiver.changed() => {
+                if is_changed.is_err() {
+                    Err("Worker stopped unexpectedly.")?
+                }
+                #[cfg(target_os = "linux")]
+                {
+                    let worker_heartbeat = status_receiver.borrow_and_update().clone();
+                    _ = notify_watchdog(&get_heartbeat_summary(worker_heartbeat.as_ref()))
+                        .inspect_err(|e| error!("{:?}", e));
+                }
+            }
+        }
+    }
+    info!("Interrupted, shutting down.");
+    #[cfg(target_os = "linux")]
+    {
+        _ = notify_stopping().inspect_err(|e| error!("{:?}", e));
+    }
+    _ = command_sender.send(WorkerCommand::Shutdown);
+    let shutdown_period = std::time::Duration::from_secs(WORKER_SHUTDOWN_TIMEOUT_SECONDS);
+    match tokio::time::timeout(shutdown_period, worker_task).await {
+        Ok(worker_result) => worker_result??,
+        Err(_) => Err("Worker didn't shut down in time.")?,
+    }
+
+    Ok(())
+}
+
+/// Waits for the next run, rather than reporting the last one.
+async fn apply_with_daemon() -> ResultBtAny<WorkerHeartbeat> {
+    let mut ipc_client = IpcClient::connect().await?;
+    ipc_client.subscribe().await?;
+    ipc_client.send_command(WorkerCommand::Apply).await?;
+    loop {
+        match ipc_client.next_notification().await? {
+            Some(IpcNotification::Heartbeat(worker_heartbeat)) => return Ok(*worker_heartbeat),
+            Some(IpcNotification::FocusWindow) => continue,
+            None => Err("The daemon stopped before applying.")?,
+        }
+    }
+}
+
+/// Ctrl+C, or SIGTERM as sent by systemd.
+async fn wait_for_shutdown_signal() -> ResultBtAny<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate_signals = signal(SignalKind::terminate())?;
+        tokio::select! {
+            is_interrupted = tokio::signal::ctrl_c() => is_interrupted?,
+            _ = terminate_signals.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+/// One line, as shown by `systemctl status`.
+#[cfg(target_os = "linux")]
+fn get_heartbeat_summary(worker_heartbeat: Option<&WorkerHeartbeat>) -> String {
+    let Some(worker_heartbeat) = worker_heartbeat else {
+        return String::from("Starting");
+    };
+    let configuration_status = match worker_heartbeat.get_is_synced() {
+        _ if worker_heartbeat.get_is_paused() => "Paused",
+        Some(true) => "Synced",
+        Some(false) => "Unsynced",
+        None => "Nothing to sync",
+    };
+    match worker_heartbeat.get_error() {
+        Some(e) => format!("{configuration_status}, {e}"),
+        None => String::from(configuration_status),
+    }
+}
+
+async fn run_rules_command(
+    rules_command: RulesCommand,
+    topology: &CpuTopology,
+    sqlite_pool: &SqlitePool,
+) -> ResultBtAny<()> {
+    let mut persistent_store = PersistentStore::load(topology, sqlite_pool).await?;
+    let rules = &mut persistent_store.rules;
+    match rules_command {
+        RulesCommand::List => {
+            for rule in rules.iter() {
+                print_rule(rule);
+            }
+            return Ok(());
+        }
+        RulesCommand::Add(rule_args) => {
+            let rule_id = rules
+                .iter()
+                .map(|rule| rule.id)
+                .max()
+                .unwrap_or(Rule::BUILT_IN_ID)
+                + 1;
+            let mut rule = Rule::new_custom(rule_id, topology);
+            rule.name = rule_args.name;
+            rule.matcher = ProcessMatcher {
+                conditions: vec![MatchCondition::new(rule_args.kind, &rule_args.pattern)],
+                is_wine_aware: rule_args.wine,
+                ..ProcessMatcher::default()
+            };
+            if let Some(cpus) = rule_args.cpus {
+                let cpu_ids = parse_cpu_list(&cpus)?;
+                if cpu_ids.is_empty() {
+                    Err(format!("No CPUs in `{cpus}`."))?
+                }
+                let mut cpu_selections = CpuSelections::new(topology.get_cpu_count());
+                for cpu_id in cpu_ids {
+                    cpu_selections.toggle_selection(cpu_id, true)?;
+                }
+                rule.selections = cpu_selections;
+            }
+            print_rule(&rule);
+            rules.push(rule);
+        }
+        RulesCommand::Remove { id } => {
+            if get_rule(rules, id)?.get_is_built_in() {
+                Err("The built-in rule can't be removed, disable it instead.")?
+            }
+            rules.retain(|rule| rule.id != id);
+            println!("Removed rule {id}.");
+        }
+        RulesCommand::Enable { id } => {
+            get_rule_mut(rules, id)?.is_enabled = true;
+            println!("Enabled rule {id}.");
+        }
+        RulesCommand::Disable { id } => {
+            get_rule_mut(rules, id)?.is_enabled = false;
+            println!("Disabled rule {id}.");
+        }
+    }
+
+    persistent_store.save(sqlite_pool).await?;
+    info!("Saved rules.");
+
+    Ok(())
+}
+
+async fn run_profiles_command(
+    profiles_command: ProfilesCommand,
+    sqlite_pool: &SqlitePool,
+) -> ResultBtAny<()> {
+    match profiles_command {
+        ProfilesCommand::List => {
+            for profile in PersistentStore::load_profiles(sqlite_pool).await? {
+                let profile_status = if profile.is_active { " (active)" } else { "" };
+                println!("{} {}{profile_status}", profile.id, profile.name);
+            }
+        }
+        ProfilesCommand::Switch { id } => {
+            PersistentStore::activate_profile(id, sqlite_pool).await?;
+            println!("Switched to profile {id}.");
+            // Otherwise it's picked up whenever the worker next runs.
+            match IpcClient::connect().await {
+                Ok(mut ipc_client) => ipc_client.send_command(WorkerCommand::Apply).await?,
+                Err(_) => info!("No daemon to apply the profile now."),
+            }
+        }
+        ProfilesCommand::Duplicate { id, name } => {
+            let new_id = PersistentStore::duplicate_profile(id, &name, sqlite_pool).await?;
+            println!("Duplicated profile {id} as {new_id} {name}.");
+        }
+        ProfilesCommand::Rename { id, name } => {
+            PersistentStore::rename_profile(id, &name, sqlite_pool).await?;
+            println!("Renamed profile {id} to {name}.");
+        }
+        ProfilesCommand::Delete { id } => {
+            PersistentStore::delete_profile(id, sqlite_pool).await?;
+            println!("Deleted profile {id}.");
+        }
+    }
+
+    Ok(())
+}
+
+/// Picked up whenever the worker next runs.
+async fn run_triggers_command(
+    triggers_command: TriggersCommand,
+    sqlite_pool: &SqlitePool,
+) -> ResultBtAny<()> {
+    match triggers_command {
+        TriggersCommand::List => {
+            let profiles = PersistentStore::load_profiles(sqlite_pool).await?;
+            for profile_trigger in PersistentStore::load_profile_triggers(sqlite_pool).await? {
+                let profile_name = profiles
+                    .iter()
+                    .find(|profile| profile.id == profile_trigger.profile_id)
+                    .map_or("", |profile| &profile.name);
+                print_trigger(&profile_trigger, profile_name);
+            }
+        }
+        TriggersCommand::Add(trigger_args) => {
+            let matcher = ProcessMatcher {
+                conditions: vec![MatchCondition::new(
+                    trigger_args.kind,
+                    &trigger_args.pattern,
+                )],
+                is_wine_aware: trigger_args.wine,
+                ..ProcessMatcher::default()
+            };
+            let trigger_id = PersistentStore::add_profile_trigger(
+                trigger_args.profile_id,
+                &matcher,
+                trigger_args.priority,
+                sqlite_pool,
+            )
+            .await?;
+            println!(
+                "Added trigger {trigger_id} for profile {}.",
+                trigger_args.profile_id
+            );
+        }
+        TriggersCommand::Remove { id } => {
+            PersistentStore::remove_profile_trigger(id, sqlite_pool).await?;
+            println!("Removed trigger {id}.");
+        }
+        TriggersCommand::Enable { id } => {
+            PersistentStore::set_profile_trigger_enabled(id, true, sqlite_pool).await?;
+            println!("Enabled trigger {id}.");
+        }
+        TriggersCommand::Disable { id } => {
+            PersistentStore::set_profile_trigger_enabled(id, false, sqlite_pool).await?;
+            println!("Disabled trigger {id}.");
+        }
+    }
+
+    Ok(())
+}
+
+fn get_rule(rules: &[Rule], rule_id: i64) -> ResultBtAny<&Rule> {
+    Ok(rules
+        .iter()
+        .find(|rule| rule.id == rule_id)
+        .ok_or(format!("No rule with ID `{rule_id}`."))?)
+}
+
+fn get_rule_mut(rules: &mut [Rule], rule_id: i64) -> ResultBtAny<&mut Rule> {
+    Ok(rules
+        .iter_mut()
+        .find(|rule| rule.id == rule_id)
+        .ok_or(format!("No rule with ID `{rule_id}`."))?)
+}
+
+fn print_rule(rule: &Rule) {
+    let rule_status = if rule.is_enabled {
+        "enabled"
+    } else {
+        "disabled"
+    };
+    println!("{} {} ({rule_status})", rule.id, rule.name);
+    println!("    Matches: {}", rule.matcher);
+    if let Some(spawner_matcher) = &rule.spawner_matcher {
+        println!("    Spawners: {spawner_matcher}");
+    }
+    println!("    Enforced: {}", rule.enforcement_strategy);
+    println!("    {} ({:?})", rule.selections, rule.preset);
+}
+
+fn print_trigger(profile_trigger: &ProfileTrigger, profile_name: &str) {
+    let trigger_status = if profile_trigger.is_enabled {
+        "enabled"
+    } else {
+        "disabled"
+    };
+    println!(
+        "{} profile {} {profile_name} ({trigger_status})",
+        profile_trigger.id, profile_trigger.profile_id
+    );
+    println!("    Matches: {}", profile_trigger.matcher);
+    println!("    Priority: {}", profile_trigger.priority);
+}
+
+fn print_matches(
+    label: &str,
+    matcher: &ProcessMatcher,
+    process_scanner: &ProcessScanner,
+) -> ResultBtAny<()> {
+    let mut processes = matcher.get_matching_processes(process_scanner)?;
+    processes.sort_by_key(|process| process.pid());
+    if processes.is_empty() {
+        println!("    {label}: none");
+    }
+    for process in processes {
+        println!(
+            "    {label}: #{} {}",
+            process.pid(),
+            process.name().to_string_lossy()
+        );
+    }
+    Ok(())
+}
+
+fn print_heartbeat(worker_heartbeat: &WorkerHeartbeat) {
+    let format_time = |at: &chrono::DateTime<chrono::Utc>| {
+        at.with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    };
+
+    let staleness = if worker_heartbeat.get_is_stale() {
+        ", stale so the worker may have stopped"
+    } else {
+        ""
+    };
+    println!(
+        "Last heartbeat: {}{staleness}",
+        format_time(worker_heartbeat.get_at())
+    );
+    let configuration_status = match worker_heartbeat.get_is_synced() {
+        _ if worker_heartbeat.get_is_paused() => "paused",
+        Some(true) => "synced",
+        Some(false) => "unsynced",
+        None => "N/A",
+    };
+    println!("Config status: {configuration_status}");
+    if let Some(profile_report) = worker_heartbeat.get_profile() {
+        println!(
+            "Profile: {} ({})",
+            profile_report.name, profile_report.activation
+        );
+    }
+    if let Some(e) = worker_heartbeat.get_error() {
+        println!("Error: {e}");
+    }
+    for enforcement in worker_heartbeat.get_enforcements() {
+        println!(
+            "Enforced: {} ({})",
+            enforcement.rule_name, enforcement.strategy
+        );
+    }
+    let scan_stats = worker_heartbeat.get_scan_stats();
+    println!(
+        "Last scan: {} of {} processes in {:.1} ms",
+        scan_stats.refreshed_count,
+        scan_stats.process_count,
+        scan_stats.duration.as_secs_f64() * 1000.0
+    );
+
+    for rule_report in worker_heartbeat.get_rule_reports() {
+        let rule_status = match rule_report.is_synced {
+            Some(true) => "synced",
+            Some(false) => "unsynced",
+            None => "N/A",
+        };
+        println!("Rule {}: {rule_status}", rule_report.rule_name);
+        for process_report in &rule_report.process_reports {
+            let observed_selections = process_report
+                .observed_selections
+                .as_ref()
+                .map_or(String::from("not read"), ToString::to_string);
+            print!(
+                "    #{} {}: wants {}; had {observed_selections}",
+                process_report.process_id, process_report.action, process_report.desired_selections
+            );
+            match &process_report.failure {
+                Some(failure) => println!("; {}, {}", failure.kind, failure.message),
+                None => println!(),
+            }
+        }
+    }
+
+    for tracked_process in worker_heartbeat.get_tracked_processes() {
+        let applied_at = tracked_process
+            .applied_at
+            .as_ref()
+            .map_or(String::from("never"), format_time);
+        print!(
+            "Process {} #{}: seen {}, applied {applied_at}, drifted {}x",
+            tracked_process.rule_name,
+            tracked_process.process_id,
+            format_time(&tracked_process.first_seen_at),
+            tracked_process.drift_count
+        );
+        if let Some(failure) = &tracked_process.failure {
+            print!(", failed {}x ({})", failure.count, failure.kind);
+            if failure.get_is_quarantined() {
+                print!(", quarantined");
+            }
+        }
+        match &tracked_process.exited_at {
+            Some(exited_at) => println!(", exited {}", format_time(exited_at)),
+            None => println!(),
+        }
+    }
+}
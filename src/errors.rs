@@ -20,6 +20,9 @@ define_to_dyn!(serde_json::Error);
 define_to_dyn!(sqlx::Error);
 define_to_dyn!(sqlx::migrate::MigrateError);
 
+define_to_dyn!(tokio::task::JoinError);
+
+#[cfg(feature = "gui")]
 define_to_dyn!(iced::Error);
 
 #[cfg(target_os = "windows")]
@@ -0,0 +1,137 @@
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+#[cfg(target_os = "linux")]
+use tracing::{error, info, warn};
+
+use crate::errors::ResultBtAny;
+#[cfg(target_os = "linux")]
+use crate::wrappers::{ProcessConnector, get_is_overrun, get_is_timed_out};
+
+#[cfg(target_os = "linux")]
+const CONNECTOR_RECEIVE_TIMEOUT_SECONDS: u64 = 1;
+
+#[cfg(target_os = "linux")]
+const CONNECTOR_BUFFER_SIZE: usize = 4096;
+
+/// `nlmsghdr` then `cn_msg` come before the `proc_event`.
+const PROCESS_EVENT_OFFSET: usize = 16 + 20;
+
+/// `what`, `cpu` and `timestamp_ns` come before the event's data.
+const PROCESS_EVENT_DATA_OFFSET: usize = PROCESS_EVENT_OFFSET + 16;
+
+const PROC_EVENT_FORK: u32 = 0x00000001;
+
+const PROC_EVENT_EXEC: u32 = 0x00000002;
+
+const PROC_EVENT_EXIT: u32 = 0x80000000;
+
+/// IDs are of thread groups, so what userspace calls processes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessEvent {
+    /// New threads aren't reported, only new processes.
+    Fork {
+        parent_id: u32,
+        process_id: u32,
+    },
+    Exec {
+        process_id: u32,
+    },
+    Exit {
+        process_id: u32,
+    },
+}
+
+/// Read off the kernel on a thread of its own, which stops once this is
+/// dropped.
+pub struct ProcessEvents {
+    receiver: mpsc::UnboundedReceiver<ProcessEvent>,
+}
+
+impl ProcessEvents {
+    #[cfg(target_os = "linux")]
+    pub fn subscribe() -> ResultBtAny<Self> {
+        let receive_timeout = Duration::from_secs(CONNECTOR_RECEIVE_TIMEOUT_SECONDS);
+        let connector = ProcessConnector::open(receive_timeout)
+            .map_err(|e| format!("Couldn't subscribe to process events, {e}."))?;
+        info!("Subscribed to process events.");
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        std::thread::spawn(move || forward_process_events(connector, sender));
+        Ok(Self { receiver })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn subscribe() -> ResultBtAny<Self> {
+        Err("Process events are only supported on Linux.")?
+    }
+
+    /// `None` once the subscription is lost.
+    pub async fn next(&mut self) -> Option<ProcessEvent> {
+        self.receiver.recv().await
+    }
+
+    pub fn try_next(&mut self) -> Option<ProcessEvent> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn forward_process_events(
+    connector: ProcessConnector,
+    sender: mpsc::UnboundedSender<ProcessEvent>,
+) {
+    let mut buffer = [0; CONNECTOR_BUFFER_SIZE];
+    while !sender.is_closed() {
+        let received_length = match connector.receive(&mut buffer) {
+            Ok(received_length) => received_length,
+            Err(e) if get_is_timed_out(&e) => continue,
+            Err(e) if get_is_overrun(&e) => {
+                warn!("Missed process events.");
+                continue;
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                return;
+            }
+        };
+        let Some(process_event) = parse_process_event(&buffer[..received_length]) else {
+            continue;
+        };
+        if sender.send(process_event).is_err() {
+            break;
+        }
+    }
+    info!("Unsubscribed from process events.");
+}
+
+/// Reads one `proc_event` as sent by the process connector, anything else,
+/// including thread forks, is `None`.
+pub fn parse_process_event(datagram: &[u8]) -> Option<ProcessEvent> {
+    let get_u32 = |offset: usize| {
+        let bytes = datagram.get(offset..offset + 4)?;
+        Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+    };
+    let get_data = |index: usize| get_u32(PROCESS_EVENT_DATA_OFFSET + index * 4);
+
+    match get_u32(PROCESS_EVENT_OFFSET)? {
+        PROC_EVENT_FORK => {
+            let (child_id, child_group_id) = (get_data(2)?, get_data(3)?);
+            (child_id == child_group_id).then_some(ProcessEvent::Fork {
+                parent_id: get_data(1)?,
+                process_id: child_group_id,
+            })
+        }
+        PROC_EVENT_EXEC => Some(ProcessEvent::Exec {
+            process_id: get_data(1)?,
+        }),
+        PROC_EVENT_EXIT => {
+            let (process_id, group_id) = (get_data(0)?, get_data(1)?);
+            (process_id == group_id).then_some(ProcessEvent::Exit {
+                process_id: group_id,
+            })
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use tracing::{error, info};
+
+use crate::{errors::ResultBtAny, persistence::get_configuration_directory};
+
+/// What's kept to one per user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstanceKind {
+    /// Runs the worker, whether as `daemon` or `apply`.
+    Daemon,
+    Window,
+}
+
+impl InstanceKind {
+    fn get_lock_filename(&self) -> &'static str {
+        match self {
+            Self::Daemon => "daemon.lock",
+            Self::Window => "window.lock",
+        }
+    }
+}
+
+/// Holds an OS lock on the lock file until dropped, or the process exits. The
+/// PID in it is only for people to read.
+#[derive(Debug)]
+pub struct InstanceLock {
+    lock_file: File,
+}
+
+impl InstanceLock {
+    /// `None` while another running instance holds it.
+    pub fn acquire(instance_kind: InstanceKind) -> ResultBtAny<Option<Self>> {
+        let configuration_directory = get_configuration_directory()?;
+        fs::create_dir_all(&configuration_directory)?;
+        Self::acquire_in(&configuration_directory, instance_kind)
+    }
+
+    pub(crate) fn acquire_in(
+        directory: &Path,
+        instance_kind: InstanceKind,
+    ) -> ResultBtAny<Option<Self>> {
+        let path = directory.join(instance_kind.get_lock_filename());
+        // It's never removed, as another instance may be waiting on it.
+        let mut lock_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match lock_file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut process_id = String::new();
+                _ = lock_file.read_to_string(&mut process_id);
+                info!(
+                    "{:?} lock is held by #{}.",
+                    instance_kind,
+                    process_id.trim()
+                );
+                return Ok(None);
+            }
+            Err(TryLockError::Error(e)) => Err(e)?,
+        }
+
+        lock_file.set_len(0)?;
+        lock_file.seek(SeekFrom::Start(0))?;
+        write!(lock_file, "{}", std::process::id())?;
+        info!("Acquired {:?} lock.", instance_kind);
+
+        Ok(Some(Self { lock_file }))
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // The lock itself is released with the file.
+        _ = self.lock_file.set_len(0).inspect_err(|e| error!("{:?}", e));
+    }
+}
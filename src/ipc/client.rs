@@ -0,0 +1,219 @@
+use std::{collections::VecDeque, process::Stdio};
+
+use backon::{ExponentialBuilder, Retryable};
+use serde_json::Value;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf,
+};
+use tracing::info;
+#[cfg(target_os = "windows")]
+use windows::Win32::System::Threading::{CREATE_NEW_PROCESS_GROUP, DETACHED_PROCESS};
+
+#[cfg(target_os = "windows")]
+use crate::ipc::PIPE_NAME;
+#[cfg(unix)]
+use crate::ipc::get_socket_path;
+use crate::{
+    errors::ResultBtAny,
+    ipc::{
+        IpcMethod, IpcNotification, IpcRequest, IpcResponse, JSON_RPC_VERSION, ProcessKeyParams,
+        ProfileParams, RuleEnabledParams,
+    },
+    persistence::{Profile, Rule},
+    worker::{WorkerCommand, WorkerHeartbeat},
+};
+
+#[cfg(unix)]
+pub type IpcStream = tokio::net::UnixStream;
+
+#[cfg(target_os = "windows")]
+pub type IpcStream = tokio::net::windows::named_pipe::NamedPipeClient;
+
+/// One request at a time, waiting on each response.
+pub struct IpcClient<S> {
+    lines: Lines<BufReader<ReadHalf<S>>>,
+    writer: WriteHalf<S>,
+    next_id: u64,
+    /// Those that came in while waiting on a response.
+    notifications: VecDeque<IpcNotification>,
+}
+
+impl IpcClient<IpcStream> {
+    /// To whichever process is running the worker.
+    pub async fn connect() -> ResultBtAny<Self> {
+        #[cfg(unix)]
+        let stream = tokio::net::UnixStream::connect(get_socket_path()?).await?;
+        #[cfg(target_os = "windows")]
+        let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(PIPE_NAME)?;
+        info!("Connected to IPC.");
+
+        Ok(Self::new(stream))
+    }
+
+    /// Starts the daemon when there's none to connect to, then waits for it to
+    /// listen.
+    pub async fn connect_or_start_daemon() -> ResultBtAny<Self> {
+        if let Ok(ipc_client) = Self::connect().await {
+            return Ok(ipc_client);
+        }
+        start_daemon()?;
+        (async || Self::connect().await)
+            .retry(ExponentialBuilder::default())
+            .await
+    }
+}
+
+/// Detached, so it keeps enforcing after the window's closed.
+fn start_daemon() -> ResultBtAny<()> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .arg("daemon")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Out of the terminal's process group, so Ctrl+C only stops the window.
+        command.process_group(0);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(DETACHED_PROCESS.0 | CREATE_NEW_PROCESS_GROUP.0);
+    }
+    command.spawn()?;
+    info!("Started daemon.");
+
+    Ok(())
+}
+
+impl<S: AsyncRead + AsyncWrite> IpcClient<S> {
+    pub fn new(stream: S) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 0,
+            notifications: VecDeque::new(),
+        }
+    }
+
+    pub async fn get_heartbeat(&mut self) -> ResultBtAny<Option<WorkerHeartbeat>> {
+        let result = self.call(IpcMethod::GetHeartbeat, Value::Null).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    pub async fn get_rules(&mut self) -> ResultBtAny<Vec<Rule>> {
+        let result = self.call(IpcMethod::GetRules, Value::Null).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    pub async fn set_rule_enabled(&mut self, id: i64, is_enabled: bool) -> ResultBtAny<()> {
+        let params = serde_json::to_value(RuleEnabledParams { id, is_enabled })?;
+        self.call(IpcMethod::SetRuleEnabled, params).await?;
+        Ok(())
+    }
+
+    pub async fn get_profiles(&mut self) -> ResultBtAny<Vec<Profile>> {
+        let result = self.call(IpcMethod::GetProfiles, Value::Null).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    pub async fn set_active_profile(&mut self, id: i64) -> ResultBtAny<()> {
+        let params = serde_json::to_value(ProfileParams { id })?;
+        self.call(IpcMethod::SetActiveProfile, params).await?;
+        Ok(())
+    }
+
+    /// As sent from the UI, except for `Shutdown` which only the daemon does.
+    pub async fn send_command(&mut self, worker_command: WorkerCommand) -> ResultBtAny<()> {
+        let (ipc_method, params) = match worker_command {
+            WorkerCommand::RestoreOriginals => (IpcMethod::RestoreOriginals, Value::Null),
+            WorkerCommand::Resume => (IpcMethod::Resume, Value::Null),
+            WorkerCommand::RetryProcess((process_id, start_time)) => (
+                IpcMethod::RetryProcess,
+                serde_json::to_value(ProcessKeyParams {
+                    process_id,
+                    start_time,
+                })?,
+            ),
+            WorkerCommand::Apply => (IpcMethod::Apply, Value::Null),
+            WorkerCommand::Shutdown => Err("The daemon can't be shut down over IPC.")?,
+        };
+        self.call(ipc_method, params).await?;
+        Ok(())
+    }
+
+    /// Each heartbeat after is then read with `next_notification`.
+    pub async fn subscribe(&mut self) -> ResultBtAny<()> {
+        self.call(IpcMethod::Subscribe, Value::Null).await?;
+        Ok(())
+    }
+
+    pub async fn focus_window(&mut self) -> ResultBtAny<()> {
+        self.call(IpcMethod::FocusWindow, Value::Null).await?;
+        Ok(())
+    }
+
+    /// `None` once the server's gone.
+    pub async fn next_notification(&mut self) -> ResultBtAny<Option<IpcNotification>> {
+        if let Some(ipc_notification) = self.notifications.pop_front() {
+            return Ok(Some(ipc_notification));
+        }
+        while let Some(ipc_response) = self.read_response().await? {
+            if let Some(ipc_notification) = IpcNotification::from_response(ipc_response)? {
+                return Ok(Some(ipc_notification));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn call(&mut self, ipc_method: IpcMethod, params: Value) -> ResultBtAny<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let ipc_request = IpcRequest {
+            jsonrpc: String::from(JSON_RPC_VERSION),
+            id: Some(Value::from(id)),
+            method: String::from(ipc_method.as_str()),
+            params,
+        };
+        let mut ipc_request = serde_json::to_string(&ipc_request)?;
+        ipc_request.push('\n');
+        self.writer.write_all(ipc_request.as_bytes()).await?;
+        self.writer.flush().await?;
+
+        loop {
+            let ipc_response = self
+                .read_response()
+                .await?
+                .ok_or("Disconnected before a response.")?;
+            if ipc_response.get_is_notification() {
+                if let Some(ipc_notification) = IpcNotification::from_response(ipc_response)? {
+                    self.notifications.push_back(ipc_notification);
+                }
+                continue;
+            }
+            if ipc_response.id != id {
+                continue;
+            }
+
+            if let Some(e) = ipc_response.error {
+                Err(format!(
+                    "`{}` failed, {} ({}).",
+                    ipc_method.as_str(),
+                    e.message,
+                    e.code
+                ))?
+            }
+            return Ok(ipc_response.result.unwrap_or_default());
+        }
+    }
+
+    async fn read_response(&mut self) -> ResultBtAny<Option<IpcResponse>> {
+        let Some(line) = self.lines.next_line().await? else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{errors::ResultBtAny, worker::WorkerHeartbeat};
+
+pub mod client;
+pub mod server;
+
+pub const JSON_RPC_VERSION: &str = "2.0";
+
+#[cfg(target_os = "windows")]
+pub const PIPE_NAME: &str = r"\\.\pipe\ir_affinity";
+
+#[cfg(unix)]
+const SOCKET_FILENAME: &str = "ir_affinity.sock";
+
+pub const PARSE_ERROR_CODE: i64 = -32700;
+pub const METHOD_NOT_FOUND_CODE: i64 = -32601;
+pub const INVALID_PARAMS_CODE: i64 = -32602;
+pub const SERVER_ERROR_CODE: i64 = -32000;
+
+/// Sent to subscribers as a notification, with the heartbeat as its params.
+pub const HEARTBEAT_NOTIFICATION: &str = "heartbeat";
+
+/// Sent to subscribers when another launch hands off to the open window.
+pub const FOCUS_WINDOW_NOTIFICATION: &str = "focus_window";
+
+/// Known to the server, the names are what's sent as `method`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcMethod {
+    /// The worker's last heartbeat, `null` before its first run.
+    GetHeartbeat,
+    /// In the order they're matched.
+    GetRules,
+    /// Takes `id` and `is_enabled`.
+    SetRuleEnabled,
+    GetProfiles,
+    /// Takes `id`, and runs the worker with it straight away.
+    SetActiveProfile,
+    /// Runs the worker without waiting out its cooldown.
+    Apply,
+    /// Puts every changed process back and pauses enforcement.
+    RestoreOriginals,
+    Resume,
+    /// Takes `process_id` and `start_time`.
+    RetryProcess,
+    /// Sends each heartbeat after as a notification, until disconnected.
+    Subscribe,
+    /// Has subscribers bring their window forward.
+    FocusWindow,
+}
+
+impl IpcMethod {
+    pub fn get_all() -> Vec<Self> {
+        vec![
+            Self::GetHeartbeat,
+            Self::GetRules,
+            Self::SetRuleEnabled,
+            Self::GetProfiles,
+            Self::SetActiveProfile,
+            Self::Apply,
+            Self::RestoreOriginals,
+            Self::Resume,
+            Self::RetryProcess,
+            Self::Subscribe,
+            Self::FocusWindow,
+        ]
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GetHeartbeat => "get_heartbeat",
+            Self::GetRules => "get_rules",
+            Self::SetRuleEnabled => "set_rule_enabled",
+            Self::GetProfiles => "get_profiles",
+            Self::SetActiveProfile => "set_active_profile",
+            Self::Apply => "apply",
+            Self::RestoreOriginals => "restore_originals",
+            Self::Resume => "resume",
+            Self::RetryProcess => "retry_process",
+            Self::Subscribe => "subscribe",
+            Self::FocusWindow => "focus_window",
+        }
+    }
+}
+
+impl FromStr for IpcMethod {
+    type Err = String;
+
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        Self::get_all()
+            .into_iter()
+            .find(|ipc_method| ipc_method.as_str() == method)
+            .ok_or_else(|| format!("Unknown method `{method}`."))
+    }
+}
+
+/// One per line, JSON-RPC 2.0. Without an `id` it's a notification, and gets
+/// no response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpcRequest {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+/// Has either `result` or `error`, or `method` and `params` when it's a
+/// notification rather than a response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpcResponse {
+    pub jsonrpc: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<IpcError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+impl IpcResponse {
+    pub fn new_result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: String::from(JSON_RPC_VERSION),
+            id,
+            result: Some(result),
+            error: None,
+            method: None,
+            params: Value::Null,
+        }
+    }
+
+    pub fn new_error(id: Value, code: i64, message: String) -> Self {
+        Self {
+            jsonrpc: String::from(JSON_RPC_VERSION),
+            id,
+            result: None,
+            error: Some(IpcError { code, message }),
+            method: None,
+            params: Value::Null,
+        }
+    }
+
+    pub fn new_notification(method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: String::from(JSON_RPC_VERSION),
+            id: Value::Null,
+            result: None,
+            error: None,
+            method: Some(String::from(method)),
+            params,
+        }
+    }
+
+    pub fn get_is_notification(&self) -> bool {
+        self.method.is_some()
+    }
+}
+
+/// What's sent to subscribers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IpcNotification {
+    Heartbeat(Box<WorkerHeartbeat>),
+    FocusWindow,
+}
+
+impl IpcNotification {
+    pub fn to_response(&self) -> ResultBtAny<IpcResponse> {
+        Ok(match self {
+            Self::Heartbeat(worker_heartbeat) => IpcResponse::new_notification(
+                HEARTBEAT_NOTIFICATION,
+                serde_json::to_value(worker_heartbeat)?,
+            ),
+            Self::FocusWindow => {
+                IpcResponse::new_notification(FOCUS_WINDOW_NOTIFICATION, Value::Null)
+            }
+        })
+    }
+
+    /// `None` for responses and notifications this doesn't know of.
+    pub fn from_response(ipc_response: IpcResponse) -> ResultBtAny<Option<Self>> {
+        Ok(match ipc_response.method.as_deref() {
+            Some(HEARTBEAT_NOTIFICATION) => Some(Self::Heartbeat(Box::new(
+                serde_json::from_value(ipc_response.params)?,
+            ))),
+            Some(FOCUS_WINDOW_NOTIFICATION) => Some(Self::FocusWindow),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleEnabledParams {
+    pub id: i64,
+    pub is_enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileParams {
+    pub id: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessKeyParams {
+    pub process_id: u32,
+    pub start_time: u64,
+}
+
+/// In the runtime directory where there is one, which only the user can get
+/// into.
+#[cfg(unix)]
+pub fn get_socket_path() -> ResultBtAny<std::path::PathBuf> {
+    let project_directories = directories::ProjectDirs::from("com", "jxo", "ir_affinity")
+        .ok_or("Could not get `ProjectDirs`.")?;
+    let socket_directory = match project_directories.runtime_dir() {
+        Some(runtime_directory) => runtime_directory.to_path_buf(),
+        None => crate::persistence::get_configuration_directory()?,
+    };
+    Ok(socket_directory.join(SOCKET_FILENAME))
+}
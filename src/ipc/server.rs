@@ -0,0 +1,321 @@
+use serde_json::Value;
+use sqlx::SqlitePool;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{broadcast, mpsc, watch},
+    task::JoinHandle,
+};
+use tracing::{error, info};
+
+#[cfg(target_os = "windows")]
+use crate::ipc::PIPE_NAME;
+#[cfg(unix)]
+use crate::ipc::get_socket_path;
+use crate::{
+    errors::ResultBtAny,
+    ipc::{
+        INVALID_PARAMS_CODE, IpcMethod, IpcNotification, IpcRequest, IpcResponse,
+        METHOD_NOT_FOUND_CODE, PARSE_ERROR_CODE, ProcessKeyParams, ProfileParams,
+        RuleEnabledParams, SERVER_ERROR_CODE,
+    },
+    persistence::PersistentStore,
+    topology::CpuTopology,
+    worker::{WorkerCommand, WorkerHeartbeat},
+};
+
+/// What requests are served from, shared by every connection.
+#[derive(Debug, Clone)]
+pub struct IpcState {
+    pub sqlite_pool: SqlitePool,
+    pub topology: CpuTopology,
+    pub worker_status: watch::Receiver<Option<WorkerHeartbeat>>,
+    pub worker_commands: mpsc::UnboundedSender<WorkerCommand>,
+    /// Passed on to subscribers as `focus_window` notifications.
+    pub focus_requests: broadcast::Sender<()>,
+}
+
+impl IpcState {
+    pub fn new(
+        sqlite_pool: SqlitePool,
+        topology: CpuTopology,
+        worker_status: watch::Receiver<Option<WorkerHeartbeat>>,
+        worker_commands: mpsc::UnboundedSender<WorkerCommand>,
+    ) -> Self {
+        Self {
+            sqlite_pool,
+            topology,
+            worker_status,
+            worker_commands,
+            focus_requests: broadcast::channel(1).0,
+        }
+    }
+}
+
+/// Made by `subscribe`, for the rest of the connection.
+struct IpcSubscription {
+    heartbeats: watch::Receiver<Option<WorkerHeartbeat>>,
+    focus_requests: broadcast::Receiver<()>,
+}
+
+/// Listens until the runtime's shut down, alongside the worker.
+pub fn spawn_ipc_server(ipc_state: IpcState) -> JoinHandle<ResultBtAny<()>> {
+    tokio::task::spawn(async move { listen(ipc_state).await.inspect_err(|e| error!("{:?}", e)) })
+}
+
+#[cfg(unix)]
+async fn listen(ipc_state: IpcState) -> ResultBtAny<()> {
+    let socket_path = get_socket_path()?;
+    if let Some(parent) = socket_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Left behind by an instance that didn't shut down cleanly.
+    if socket_path.exists() {
+        std::fs::remove_file(&socket_path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(&socket_path)?;
+    info!("Listening on `{}`.", socket_path.to_string_lossy());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::task::spawn(serve_connection(stream, ipc_state.clone()));
+    }
+}
+
+#[cfg(target_os = "windows")]
+async fn listen(ipc_state: IpcState) -> ResultBtAny<()> {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut pipe_server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(PIPE_NAME)?;
+    info!("Listening on `{PIPE_NAME}`.");
+
+    loop {
+        pipe_server.connect().await?;
+        // The next client connects to a new instance.
+        let stream = pipe_server;
+        pipe_server = ServerOptions::new().create(PIPE_NAME)?;
+        tokio::task::spawn(serve_connection(stream, ipc_state.clone()));
+    }
+}
+
+/// Until the client disconnects, errors are only logged.
+pub async fn serve_connection<S: AsyncRead + AsyncWrite>(stream: S, ipc_state: IpcState) {
+    info!("Accepted IPC connection.");
+    _ = handle_connection(stream, ipc_state)
+        .await
+        .inspect_err(|e| error!("{:?}", e));
+    info!("Closed IPC connection.");
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    ipc_state: IpcState,
+) -> ResultBtAny<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut ipc_subscription: Option<IpcSubscription> = None;
+    loop {
+        let ipc_response = tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let Some(ipc_response) =
+                    handle_request(&line, &ipc_state, &mut ipc_subscription).await
+                else {
+                    continue;
+                };
+                ipc_response
+            }
+            Some(ipc_notification) = wait_for_notification(ipc_subscription.as_mut()) => {
+                ipc_notification.to_response()?
+            }
+        };
+        let mut ipc_response = serde_json::to_string(&ipc_response)?;
+        ipc_response.push('\n');
+        writer.write_all(ipc_response.as_bytes()).await?;
+        writer.flush().await?;
+    }
+}
+
+/// Never resolves without a subscription.
+async fn wait_for_notification(
+    ipc_subscription: Option<&mut IpcSubscription>,
+) -> Option<IpcNotification> {
+    let Some(ipc_subscription) = ipc_subscription else {
+        return std::future::pending().await;
+    };
+    loop {
+        tokio::select! {
+            is_changed = ipc_subscription.heartbeats.changed() => {
+                is_changed.ok()?;
+                let worker_heartbeat = ipc_subscription.heartbeats.borrow_and_update().clone();
+                if let Some(worker_heartbeat) = worker_heartbeat {
+                    return Some(IpcNotification::Heartbeat(Box::new(worker_heartbeat)));
+                }
+            }
+            focus_request = ipc_subscription.focus_requests.recv() => {
+                // Lagging behind still means there was one.
+                if let Err(broadcast::error::RecvError::Closed) = focus_request {
+                    return None;
+                }
+                return Some(IpcNotification::FocusWindow);
+            }
+        }
+    }
+}
+
+/// Notifications are handled the same, but get no response.
+async fn handle_request(
+    line: &str,
+    ipc_state: &IpcState,
+    ipc_subscription: &mut Option<IpcSubscription>,
+) -> Option<IpcResponse> {
+    let ipc_request: IpcRequest = match serde_json::from_str(line) {
+        Ok(ipc_request) => ipc_request,
+        Err(e) => {
+            return Some(IpcResponse::new_error(
+                Value::Null,
+                PARSE_ERROR_CODE,
+                e.to_string(),
+            ));
+        }
+    };
+    let is_notification = ipc_request.id.is_none();
+    let ipc_response = respond(ipc_request, ipc_state, ipc_subscription).await;
+    (!is_notification).then_some(ipc_response)
+}
+
+async fn respond(
+    ipc_request: IpcRequest,
+    ipc_state: &IpcState,
+    ipc_subscription: &mut Option<IpcSubscription>,
+) -> IpcResponse {
+    let id = ipc_request.id.unwrap_or_default();
+    let ipc_method = match ipc_request.method.parse::<IpcMethod>() {
+        Ok(ipc_method) => ipc_method,
+        Err(e) => return IpcResponse::new_error(id, METHOD_NOT_FOUND_CODE, e),
+    };
+
+    let result = match ipc_method {
+        IpcMethod::GetHeartbeat => get_heartbeat(ipc_state),
+        IpcMethod::GetRules => get_rules(ipc_state).await,
+        IpcMethod::SetRuleEnabled => {
+            match serde_json::from_value::<RuleEnabledParams>(ipc_request.params) {
+                Ok(rule_enabled_params) => set_rule_enabled(ipc_state, &rule_enabled_params)
+                    .await
+                    .map(|_| Value::Null),
+                Err(e) => {
+                    return IpcResponse::new_error(id, INVALID_PARAMS_CODE, e.to_string());
+                }
+            }
+        }
+        IpcMethod::GetProfiles => get_profiles(ipc_state).await,
+        IpcMethod::SetActiveProfile => {
+            match serde_json::from_value::<ProfileParams>(ipc_request.params) {
+                Ok(profile_params) => set_active_profile(ipc_state, &profile_params)
+                    .await
+                    .map(|_| Value::Null),
+                Err(e) => {
+                    return IpcResponse::new_error(id, INVALID_PARAMS_CODE, e.to_string());
+                }
+            }
+        }
+        IpcMethod::Apply => send_command(ipc_state, WorkerCommand::Apply).map(|_| Value::Null),
+        IpcMethod::RestoreOriginals => {
+            send_command(ipc_state, WorkerCommand::RestoreOriginals).map(|_| Value::Null)
+        }
+        IpcMethod::Resume => send_command(ipc_state, WorkerCommand::Resume).map(|_| Value::Null),
+        IpcMethod::RetryProcess => {
+            match serde_json::from_value::<ProcessKeyParams>(ipc_request.params) {
+                Ok(process_key_params) => {
+                    let process_key =
+                        (process_key_params.process_id, process_key_params.start_time);
+                    send_command(ipc_state, WorkerCommand::RetryProcess(process_key))
+                        .map(|_| Value::Null)
+                }
+                Err(e) => {
+                    return IpcResponse::new_error(id, INVALID_PARAMS_CODE, e.to_string());
+                }
+            }
+        }
+        IpcMethod::Subscribe => {
+            let mut heartbeats = ipc_state.worker_status.clone();
+            heartbeats.mark_unchanged();
+            *ipc_subscription = Some(IpcSubscription {
+                heartbeats,
+                focus_requests: ipc_state.focus_requests.subscribe(),
+            });
+            Ok(Value::Null)
+        }
+        IpcMethod::FocusWindow => focus_window(ipc_state).map(|_| Value::Null),
+    };
+    info!("Handled IPC request `{}`.", ipc_method.as_str());
+
+    match result {
+        Ok(result) => IpcResponse::new_result(id, result),
+        Err(e) => {
+            error!("{:?}", e);
+            IpcResponse::new_error(id, SERVER_ERROR_CODE, e.get().to_string())
+        }
+    }
+}
+
+fn get_heartbeat(ipc_state: &IpcState) -> ResultBtAny<Value> {
+    let worker_heartbeat = ipc_state.worker_status.borrow().clone();
+    Ok(serde_json::to_value(worker_heartbeat)?)
+}
+
+async fn get_rules(ipc_state: &IpcState) -> ResultBtAny<Value> {
+    let persistent_store =
+        PersistentStore::load(&ipc_state.topology, &ipc_state.sqlite_pool).await?;
+    Ok(serde_json::to_value(persistent_store.rules)?)
+}
+
+async fn get_profiles(ipc_state: &IpcState) -> ResultBtAny<Value> {
+    let profiles = PersistentStore::load_profiles(&ipc_state.sqlite_pool).await?;
+    Ok(serde_json::to_value(profiles)?)
+}
+
+async fn set_active_profile(
+    ipc_state: &IpcState,
+    profile_params: &ProfileParams,
+) -> ResultBtAny<()> {
+    PersistentStore::activate_profile(profile_params.id, &ipc_state.sqlite_pool).await?;
+    send_command(ipc_state, WorkerCommand::Apply)
+}
+
+fn focus_window(ipc_state: &IpcState) -> ResultBtAny<()> {
+    ipc_state
+        .focus_requests
+        .send(())
+        .map_err(|_| "No window to bring forward.")?;
+    Ok(())
+}
+
+fn send_command(ipc_state: &IpcState, worker_command: WorkerCommand) -> ResultBtAny<()> {
+    ipc_state
+        .worker_commands
+        .send(worker_command)
+        .map_err(|e| format!("Couldn't reach the worker, {e}."))?;
+    Ok(())
+}
+
+async fn set_rule_enabled(
+    ipc_state: &IpcState,
+    rule_enabled_params: &RuleEnabledParams,
+) -> ResultBtAny<()> {
+    PersistentStore::set_rule_enabled(
+        rule_enabled_params.id,
+        rule_enabled_params.is_enabled,
+        &ipc_state.sqlite_pool,
+    )
+    .await?;
+    info!("Set rule enabled.");
+
+    Ok(())
+}
@@ -0,0 +1,246 @@
+use std::{
+    fmt::{Debug, Display},
+    fs,
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::{
+    Event, Level, Subscriber, error,
+    field::{Field, Visit},
+    info,
+    level_filters::LevelFilter,
+};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, fmt, layer::Context, layer::SubscriberExt, reload,
+    util::SubscriberInitExt,
+};
+
+use crate::{errors::ResultBtAny, persistence::get_configuration_directory};
+
+/// Records sent while the UI lags further behind than this are dropped.
+const LOG_CHANNEL_CAPACITY: usize = 1024;
+
+/// Anything less severe isn't forwarded to the UI.
+const LOG_FORWARD_LEVEL: LevelFilter = LevelFilter::INFO;
+
+const LOGS_DIRECTORY_NAME: &str = "logs";
+
+const LOG_FILE_PREFIX: &str = "ir_affinity";
+
+const LOG_FILE_SUFFIX: &str = "log";
+
+/// A day per file, so about a week is kept.
+const LOG_FILE_COUNT: usize = 7;
+
+/// How much goes to stdout and the log files, `RUST_LOG` overrides it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn get_all() -> Vec<Self> {
+        vec![
+            Self::Error,
+            Self::Warn,
+            Self::Info,
+            Self::Debug,
+            Self::Trace,
+        ]
+    }
+
+    fn to_level_filter(self) -> LevelFilter {
+        match self {
+            Self::Error => LevelFilter::ERROR,
+            Self::Warn => LevelFilter::WARN,
+            Self::Info => LevelFilter::INFO,
+            Self::Debug => LevelFilter::DEBUG,
+            Self::Trace => LevelFilter::TRACE,
+        }
+    }
+
+    fn to_env_filter(self) -> EnvFilter {
+        EnvFilter::builder()
+            .with_default_directive(self.to_level_filter().into())
+            .from_env_lossy()
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Error => write!(f, "Errors"),
+            Self::Warn => write!(f, "Warnings"),
+            Self::Info => write!(f, "Info"),
+            Self::Debug => write!(f, "Debug"),
+            Self::Trace => write!(f, "Trace"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub at: DateTime<Utc>,
+    pub level: Level,
+    pub target: String,
+    /// With any other fields after it, as `name=value`.
+    pub message: String,
+}
+
+impl LogRecord {
+    /// Whether it's at least as severe as `level`, its target starts with
+    /// `target`, and its message contains `search` regardless of case.
+    pub fn get_is_match(&self, level: Level, target: &str, search: &str) -> bool {
+        self.level <= level
+            && self.target.starts_with(target)
+            && self.message.to_lowercase().contains(&search.to_lowercase())
+    }
+}
+
+/// Sends every event to whoever's subscribed, as the UI is once it's running.
+pub struct BroadcastLayer {
+    sender: broadcast::Sender<LogRecord>,
+}
+
+impl BroadcastLayer {
+    pub fn new(sender: broadcast::Sender<LogRecord>) -> Self {
+        Self { sender }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for BroadcastLayer {
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        let mut message_visitor = MessageVisitor::default();
+        event.record(&mut message_visitor);
+        let metadata = event.metadata();
+        // Nobody's subscribed before the UI starts, or after it closes.
+        _ = self.sender.send(LogRecord {
+            at: Utc::now(),
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: message_visitor.message,
+        });
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+}
+
+impl MessageVisitor {
+    fn push(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.insert_str(0, value);
+        } else {
+            self.message
+                .push_str(&format!(" {}={}", field.name(), value));
+        }
+    }
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.push(field, &format!("{value:?}"));
+    }
+}
+
+/// Handed to the UI, which shows what's logged and changes how much is.
+#[derive(Debug, Clone)]
+pub struct Logging {
+    log_sender: broadcast::Sender<LogRecord>,
+    env_filter: reload::Handle<EnvFilter, Registry>,
+}
+
+impl Logging {
+    pub fn get_log_sender(&self) -> &broadcast::Sender<LogRecord> {
+        &self.log_sender
+    }
+
+    pub fn set_level(&self, log_level: LogLevel) -> ResultBtAny<()> {
+        self.env_filter
+            .reload(log_level.to_env_filter())
+            .map_err(|e| format!("Couldn't change the log level, {e}."))?;
+        info!("Set log level to {log_level}.");
+
+        Ok(())
+    }
+}
+
+/// Logs to stderr, to daily files in the logs directory, and to the UI. The
+/// level starts at `log_level` until it's set from settings.
+pub fn init_logging(log_level: LogLevel) -> Logging {
+    let (log_sender, _) = broadcast::channel(LOG_CHANNEL_CAPACITY);
+    let (env_filter, env_filter_handle) = reload::Layer::new(log_level.to_env_filter());
+    let log_files = get_logs_directory().and_then(|logs_directory| {
+        fs::create_dir_all(&logs_directory)?;
+        RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(LOG_FILE_PREFIX)
+            .filename_suffix(LOG_FILE_SUFFIX)
+            .max_log_files(LOG_FILE_COUNT)
+            .build(logs_directory)
+            .map_err(|e| format!("Couldn't open log files, {e}.").into())
+    });
+    let (log_files, log_files_error) = match log_files {
+        Ok(log_files) => (Some(log_files), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_writer(std::io::stderr)
+                .and_then(
+                    log_files.map(|log_files| fmt::layer().with_writer(log_files).with_ansi(false)),
+                )
+                .with_filter(env_filter),
+        )
+        .with(BroadcastLayer::new(log_sender.clone()).with_filter(LOG_FORWARD_LEVEL))
+        .init();
+    if let Some(e) = log_files_error {
+        error!("{:?}", e);
+    }
+
+    Logging {
+        log_sender,
+        env_filter: env_filter_handle,
+    }
+}
+
+pub fn get_logs_directory() -> ResultBtAny<PathBuf> {
+    Ok(get_configuration_directory()?.join(LOGS_DIRECTORY_NAME))
+}
+
+/// In the platform's file manager.
+pub fn open_logs_directory() -> ResultBtAny<()> {
+    let logs_directory = get_logs_directory()?;
+    fs::create_dir_all(&logs_directory)?;
+
+    #[cfg(target_os = "windows")]
+    let file_manager = "explorer";
+    #[cfg(target_os = "macos")]
+    let file_manager = "open";
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let file_manager = "xdg-open";
+    std::process::Command::new(file_manager)
+        .arg(&logs_directory)
+        .spawn()
+        .map_err(|e| format!("Couldn't open `{}`, {e}.", logs_directory.display()))?;
+    info!("Opened logs directory.");
+
+    Ok(())
+}
@@ -1,33 +1,68 @@
 #![cfg_attr(test, allow(unused))]
-#![cfg_attr(all(not(test), windows), windows_subsystem = "windows")]
+#![cfg_attr(
+    all(not(test), windows, feature = "gui"),
+    windows_subsystem = "windows"
+)]
 
-use std::env::set_var;
-
-use backon::{ExponentialBuilder, Retryable};
 use bt_error::define_with_backtrace;
-use tokio::sync::watch;
+use clap::Parser;
+#[cfg(feature = "gui")]
+use backon::{ExponentialBuilder, Retryable};
+#[cfg(feature = "gui")]
 use tracing::{error, info};
 
+use crate::cli::{Cli, run_cli};
+#[cfg(feature = "gui")]
 use crate::{
     errors::ResultBtAny,
+    instance::{InstanceKind, InstanceLock},
+    ipc::client::IpcClient,
+    logging::{LogLevel, init_logging},
     persistence::PersistentStore,
+    topology::CpuTopology,
     ui::{run_error_ui, run_initialized_ui},
-    worker::spawn_worker_task,
 };
 
 define_with_backtrace!();
 
+pub mod cli;
 pub mod errors;
+pub mod events;
+pub mod instance;
+pub mod ipc;
 pub mod ir;
+pub mod logging;
+pub mod matcher;
 pub mod persistence;
+pub mod scanner;
 pub mod selections;
+#[cfg(target_os = "linux")]
+pub mod systemd;
 #[cfg(test)]
 pub mod tests;
+pub mod topology;
+pub mod tracking;
+pub mod triggers;
+#[cfg(feature = "gui")]
 pub mod ui;
 pub mod worker;
 pub mod wrappers;
 
 fn main() {
+    let cli = Cli::parse();
+    #[cfg(not(feature = "gui"))]
+    let cli = Cli {
+        command: Some(cli.command.unwrap_or(cli::CliCommand::Daemon)),
+    };
+    if let Some(cli_command) = cli.command {
+        if let Err(e) = run_cli(cli_command) {
+            eprintln!("{}", e.get());
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+
+    #[cfg(feature = "gui")]
     if let Err(e) = main_() {
         error!("{:?}", e);
         if let Err(e) = run_error_ui(e.get().to_string()) {
@@ -39,36 +74,48 @@ fn main() {
     }
 }
 
+#[cfg(feature = "gui")]
 fn main_() -> ResultBtAny<()> {
-    tracing_subscriber::fmt::init();
+    let logging = init_logging(LogLevel::Info);
 
+    #[cfg(target_os = "windows")]
     unsafe {
-        set_var("WGPU_BACKEND", "dx11");
+        std::env::set_var("WGPU_BACKEND", "dx11");
     }
 
-    let (status_sender, status_receiver) = watch::channel(None);
-
     let other_runtime = tokio::runtime::Runtime::new()?;
 
+    let Some(instance_lock) = InstanceLock::acquire(InstanceKind::Window)? else {
+        other_runtime.block_on(async {
+            IpcClient::connect_or_start_daemon().await?.focus_window().await
+        })?;
+        info!("Handed off to the open window.");
+        return Ok(());
+    };
+
     let sqlite_pool = other_runtime.block_on(PersistentStore::create_pool())?;
-    let sqlite_pool_2 = sqlite_pool.clone();
-    let sqlite_pool_3 = sqlite_pool.clone();
 
-    let persistent_store = other_runtime.block_on(async {
-        let mut system_info = sysinfo::System::new();
-        system_info.refresh_all();
-        info!("Refreshed system info.");
-        PersistentStore::load(system_info.cpus().len(), &sqlite_pool).await
-    })?;
+    let mut system_info = sysinfo::System::new();
+    system_info.refresh_all();
+    info!("Refreshed system info.");
+    let topology = CpuTopology::load(system_info.cpus().len());
 
     other_runtime.block_on(
         (async || PersistentStore::create_ddl(&sqlite_pool).await)
             .retry(ExponentialBuilder::default()),
     )?;
 
-    other_runtime.spawn_blocking(|| spawn_worker_task(sqlite_pool_2, status_sender));
+    let persistent_store =
+        other_runtime.block_on(PersistentStore::load(&topology, &sqlite_pool))?;
+    _ = logging
+        .set_level(persistent_store.settings.log_level)
+        .inspect_err(|e| error!("{:?}", e));
+
+    let profiles = other_runtime.block_on(PersistentStore::load_profiles(&sqlite_pool))?;
 
-    run_initialized_ui(persistent_store, sqlite_pool_3, status_receiver)?;
+    // The worker runs in the daemon, which the window connects to or starts.
+    run_initialized_ui(persistent_store, profiles, topology, sqlite_pool, logging)?;
+    drop(instance_lock);
 
     Ok(())
 }
@@ -0,0 +1,338 @@
+use std::{ffi::OsString, fmt::Display, path::Path};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sysinfo::{Process, System, Users};
+
+#[cfg(target_os = "linux")]
+use crate::wrappers::get_environment_variable;
+use crate::{
+    errors::ResultBtAny,
+    scanner::{ProcessScanner, ScannedProcess},
+};
+
+/// `comm` is cut to `TASK_COMM_LEN - 1` bytes.
+const COMM_LENGTH: usize = 15;
+
+#[cfg(target_os = "linux")]
+const STEAM_APP_ID_VARIABLE: &str = "SteamAppId";
+
+/// Conditions are AND-ed, a matcher without any never matches.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessMatcher {
+    pub conditions: Vec<MatchCondition>,
+    /// Name conditions see the Windows image name of Wine/Proton processes
+    /// rather than the loader's.
+    #[serde(default)]
+    pub is_wine_aware: bool,
+    /// Only matches processes launched by Steam for this app, Linux only.
+    #[serde(default)]
+    pub steam_app_id: Option<String>,
+}
+
+impl ProcessMatcher {
+    pub fn new_exact_name(name: &str) -> Self {
+        Self {
+            conditions: vec![MatchCondition::new(MatchConditionKind::ExactName, name)],
+            ..Self::default()
+        }
+    }
+
+    /// For Windows programs, which may be running under Wine on Linux.
+    pub fn new_exact_image_name(name: &str) -> Self {
+        Self {
+            is_wine_aware: cfg!(target_os = "linux"),
+            ..Self::new_exact_name(name)
+        }
+    }
+
+    pub fn get_matching_processes<'a>(
+        &self,
+        process_scanner: &'a ProcessScanner,
+    ) -> ResultBtAny<Vec<&'a Process>> {
+        if self.conditions.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let compiled_conditions = self
+            .conditions
+            .iter()
+            .map(CompiledCondition::new)
+            .collect::<ResultBtAny<Vec<_>>>()?;
+        let is_user_needed = self
+            .conditions
+            .iter()
+            .any(|condition| condition.kind == MatchConditionKind::User);
+        let users = is_user_needed.then(Users::new_with_refreshed_list);
+
+        let system_info = process_scanner.get_system();
+        Ok(process_scanner
+            .get_scanned_processes()
+            .filter(|(process, scanned_process)| {
+                compiled_conditions.iter().all(|condition| {
+                    condition.get_is_match(
+                        process,
+                        scanned_process,
+                        self,
+                        system_info,
+                        users.as_ref(),
+                    )
+                }) && self.get_is_steam_app_matched(process)
+            })
+            .map(|(process, _)| process)
+            .collect())
+    }
+
+    fn get_process_name<'a>(&self, scanned_process: &'a ScannedProcess) -> &'a ProcessName {
+        let windows_image_name = self
+            .is_wine_aware
+            .then_some(scanned_process.windows_image_name.as_ref())
+            .flatten();
+        windows_image_name.unwrap_or(&scanned_process.name)
+    }
+
+    fn get_is_steam_app_matched(&self, process: &Process) -> bool {
+        let Some(steam_app_id) = &self.steam_app_id else {
+            return true;
+        };
+
+        #[cfg(target_os = "linux")]
+        {
+            get_environment_variable(process.pid().as_u32(), STEAM_APP_ID_VARIABLE)
+                .ok()
+                .flatten()
+                .is_some_and(|process_app_id| process_app_id == *steam_app_id)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            _ = (process, steam_app_id);
+            false
+        }
+    }
+}
+
+impl Display for ProcessMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let conditions = self
+            .conditions
+            .iter()
+            .map(|condition| format!("{} `{}`", condition.kind, condition.value))
+            .collect::<Vec<_>>();
+        write!(f, "{}", conditions.join(" and "))?;
+        if self.is_wine_aware {
+            write!(f, " (Wine)")?;
+        }
+        if let Some(steam_app_id) = &self.steam_app_id {
+            write!(f, " in Steam app {steam_app_id}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchCondition {
+    pub kind: MatchConditionKind,
+    pub value: String,
+}
+
+impl MatchCondition {
+    pub fn new(kind: MatchConditionKind, value: &str) -> Self {
+        Self {
+            kind,
+            value: value.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchConditionKind {
+    ExactName,
+    NameCaseInsensitive,
+    NameGlob,
+    NameRegex,
+    ExePathPrefix,
+    CommandLineContains,
+    ParentName,
+    User,
+}
+
+impl MatchConditionKind {
+    pub fn get_all() -> Vec<Self> {
+        vec![
+            Self::ExactName,
+            Self::NameCaseInsensitive,
+            Self::NameGlob,
+            Self::NameRegex,
+            Self::ExePathPrefix,
+            Self::CommandLineContains,
+            Self::ParentName,
+            Self::User,
+        ]
+    }
+}
+
+impl Display for MatchConditionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ExactName => write!(f, "Name is"),
+            Self::NameCaseInsensitive => write!(f, "Name is (any case)"),
+            Self::NameGlob => write!(f, "Name glob"),
+            Self::NameRegex => write!(f, "Name regex"),
+            Self::ExePathPrefix => write!(f, "Path starts with"),
+            Self::CommandLineContains => write!(f, "Command line has"),
+            Self::ParentName => write!(f, "Parent is"),
+            Self::User => write!(f, "User is"),
+        }
+    }
+}
+
+/// Patterns are compiled once per run rather than per process.
+enum CompiledCondition<'a> {
+    ExactName(&'a str),
+    NameCaseInsensitive(String),
+    NameGlob(&'a str),
+    NameRegex(Regex),
+    ExePathPrefix(&'a str),
+    CommandLineContains(&'a str),
+    ParentName(&'a str),
+    User(&'a str),
+}
+
+impl<'a> CompiledCondition<'a> {
+    fn new(condition: &'a MatchCondition) -> ResultBtAny<Self> {
+        let value = condition.value.as_str();
+        Ok(match condition.kind {
+            MatchConditionKind::ExactName => Self::ExactName(value),
+            MatchConditionKind::NameCaseInsensitive => {
+                Self::NameCaseInsensitive(value.to_lowercase())
+            }
+            MatchConditionKind::NameGlob => Self::NameGlob(value),
+            MatchConditionKind::NameRegex => Self::NameRegex(
+                Regex::new(value).map_err(|e| format!("Invalid regex `{value}`, {e}."))?,
+            ),
+            MatchConditionKind::ExePathPrefix => Self::ExePathPrefix(value),
+            MatchConditionKind::CommandLineContains => Self::CommandLineContains(value),
+            MatchConditionKind::ParentName => Self::ParentName(value),
+            MatchConditionKind::User => Self::User(value),
+        })
+    }
+
+    fn get_is_match(
+        &self,
+        process: &Process,
+        scanned_process: &ScannedProcess,
+        matcher: &ProcessMatcher,
+        system_info: &System,
+        users: Option<&Users>,
+    ) -> bool {
+        let process_name = matcher.get_process_name(scanned_process);
+        match self {
+            Self::ExactName(name) => process_name.get_is_name(name),
+            Self::NameCaseInsensitive(name) => ProcessName {
+                name: process_name.name.to_lowercase(),
+                ..*process_name
+            }
+            .get_is_name(name),
+            Self::NameGlob(pattern) => get_is_glob_match(pattern, &process_name.name),
+            Self::NameRegex(regex) => regex.is_match(&process_name.name),
+            Self::ExePathPrefix(prefix) => process
+                .exe()
+                .is_some_and(|exe| exe.to_string_lossy().starts_with(prefix)),
+            Self::CommandLineContains(substring) => {
+                scanned_process.command_line.contains(substring)
+            }
+            Self::ParentName(name) => process
+                .parent()
+                .and_then(|parent_id| system_info.process(parent_id))
+                .is_some_and(|parent| parent.name().to_string_lossy() == *name),
+            Self::User(name) => process
+                .user_id()
+                .and_then(|user_id| users?.get_user_by_id(user_id))
+                .is_some_and(|user| user.name() == *name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessName {
+    pub name: String,
+    /// Only a prefix of the real name, as with `comm`.
+    pub is_truncated: bool,
+}
+
+impl ProcessName {
+    fn get_is_name(&self, name: &str) -> bool {
+        if self.is_truncated {
+            name.starts_with(&self.name)
+        } else {
+            name == self.name
+        }
+    }
+}
+
+/// Wine rewrites `argv[0]` to the Windows path of the image, while loader
+/// launches like `wine64-preloader wine64 C:\...\app.exe` carry it later on.
+/// Failing that, Wine still names `comm` after the image.
+pub fn get_windows_image_name(
+    process_name: &str,
+    exe: Option<&Path>,
+    command_line: &[OsString],
+) -> Option<ProcessName> {
+    let get_is_wine = |name: &str| name.to_lowercase().contains("wine");
+
+    for argument in command_line {
+        let argument = argument.to_string_lossy();
+        let base_name = argument.rsplit(['\\', '/']).next().unwrap_or_default();
+        if base_name.to_lowercase().ends_with(".exe") {
+            return Some(ProcessName {
+                name: base_name.to_string(),
+                is_truncated: false,
+            });
+        }
+        if !get_is_wine(base_name) {
+            break;
+        }
+    }
+
+    let is_wine_loader = exe
+        .and_then(|exe| exe.file_name())
+        .is_some_and(|exe_name| get_is_wine(&exe_name.to_string_lossy()));
+    (is_wine_loader && !get_is_wine(process_name)).then(|| ProcessName {
+        name: process_name.to_string(),
+        is_truncated: process_name.len() >= COMM_LENGTH,
+    })
+}
+
+/// `*` matches any run of characters and `?` any one character.
+pub fn get_is_glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut pattern_index, mut text_index) = (0, 0);
+    let mut backtrack = None;
+    while text_index < text.len() {
+        match pattern.get(pattern_index) {
+            Some('*') => {
+                backtrack = Some((pattern_index, text_index));
+                pattern_index += 1;
+            }
+            Some(&character) if character == '?' || character == text[text_index] => {
+                pattern_index += 1;
+                text_index += 1;
+            }
+            _ => match backtrack {
+                Some((star_index, star_text_index)) => {
+                    pattern_index = star_index + 1;
+                    text_index = star_text_index + 1;
+                    backtrack = Some((star_index, star_text_index + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[pattern_index..]
+        .iter()
+        .all(|&character| character == '*')
+}
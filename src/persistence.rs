@@ -1,58 +1,64 @@
-use std::{collections::HashSet, fmt::Display, fs, path::PathBuf, str::FromStr};
+use std::{collections::{BTreeSet, HashMap, HashSet}, fmt::Display, fs, path::PathBuf, str::FromStr};
 
 use directories::ProjectDirs;
-use iced::futures::TryStreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::{
     SqlitePool,
     sqlite::{SqliteConnectOptions, SqliteJournalMode},
 };
+use tokio_stream::StreamExt;
 use tracing::info;
 
-use crate::{errors::ResultBtAny, ir::{DEFAULT_IRACING_SIMULATOR, DEFAULT_SIMULATOR_SPAWNERS}, selections::hashset_to_mask};
+use crate::{errors::ResultBtAny, ir::{DEFAULT_IRACING_SIMULATOR, DEFAULT_SIMULATOR_SPAWNERS}, logging::LogLevel, matcher::ProcessMatcher, selections::{CpuMask, SelectionPreset, hashset_to_mask}, topology::CpuTopology, tracking::ProcessKey, worker::WorkerHeartbeat};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PersistentStore {
-    pub spawner: String,
-    pub simulator: String,
-    pub selections: CpuSelections,
+    /// What `rules` belong to, the active profile when loaded.
+    pub profile_id: i64,
+    /// In the order they're matched, earlier rules claim a process first.
+    pub rules: Vec<Rule>,
+    /// As last loaded or saved, so saving only writes over the rules changed since.
+    pub loaded_rules: Vec<Rule>,
+    pub settings: Settings,
+    /// Likewise for `settings`, which other instances change too.
+    pub loaded_settings: Settings,
 }
 
 impl PersistentStore {
     const CONFIGURATION_FILENAME: &str = "store.sqlite";
 
-    const SPAWNER_PROCESS_ID: u32 = 1;
-    const SIMULATOR_PROCESS_ID: u32 = 0;
-
     pub fn get_configuration_file() -> ResultBtAny<PathBuf> {
         Ok(get_configuration_directory()?.join(Self::CONFIGURATION_FILENAME))
     }
 
-    pub async fn load(cpu_count: usize, sqlite_pool: &SqlitePool) -> ResultBtAny<PersistentStore> {
-        let simulator_process = sqlx::query!(
-            "SELECT name FROM processes WHERE id = ?1",
-            Self::SIMULATOR_PROCESS_ID
-        )
-        .fetch_optional(sqlite_pool)
-        .await?;
-        info!("Queried simulation process name.");
+    pub async fn load(
+        topology: &CpuTopology,
+        sqlite_pool: &SqlitePool,
+    ) -> ResultBtAny<PersistentStore> {
+        let cpu_count = topology.get_cpu_count();
+        let profile_id = Self::load_active_profile_id(sqlite_pool).await?;
 
-        let spawner_name = sqlx::query!(
-            "SELECT name FROM processes WHERE id = ?1",
-            Self::SPAWNER_PROCESS_ID
+        let rule_rows = sqlx::query!(
+            r#"
+            SELECT id, name, matcher, spawner_matcher, enforcement_strategy, spawner_reset, preset, is_enabled
+            FROM rules
+            WHERE profile_id = ?1
+            ORDER BY ordering, id;
+            "#,
+            profile_id
         )
-        .fetch_optional(sqlite_pool)
-        .await?
-        .map(|process| process.name)
-        .unwrap_or(DEFAULT_SIMULATOR_SPAWNERS.to_string());
-        info!("Queried spawner process name.");
+        .fetch_all(sqlite_pool)
+        .await?;
+        info!("Queried rules.");
 
-        let self_ = if let Some(simulator_process) = simulator_process {
+        let mut rules = vec![];
+        for rule_row in rule_rows {
             let mut cpu_selections = HashSet::new();
 
             let mut relations = sqlx::query!(
-                "SELECT cpu_id FROM processes_selected_cpus WHERE process_id = ?1",
-                Self::SIMULATOR_PROCESS_ID
+                "SELECT cpu_id FROM rules_selected_cpus WHERE profile_id = ?1 AND rule_id = ?2",
+                profile_id,
+                rule_row.id
             )
             .fetch(sqlite_pool);
             info!("Queried selected CPUs.");
@@ -60,20 +66,34 @@ impl PersistentStore {
                 cpu_selections.insert(relation.cpu_id.try_into()?);
             }
 
-            Self {
-                spawner: spawner_name,
-                simulator: simulator_process.name,
+            let mut rule = Rule {
+                id: rule_row.id,
+                name: rule_row.name,
+                matcher: serde_json::from_str(&rule_row.matcher)?,
+                spawner_matcher: rule_row
+                    .spawner_matcher
+                    .map(|spawner_matcher| serde_json::from_str(&spawner_matcher))
+                    .transpose()?,
+                enforcement_strategy: rule_row.enforcement_strategy.parse()?,
+                spawner_reset: serde_json::from_str(&rule_row.spawner_reset)?,
+                preset: serde_json::from_str(&rule_row.preset)?,
                 selections: CpuSelections::new_preselected(cpu_selections, cpu_count),
-            }
-        } else {
-            Self {
-                spawner: spawner_name,
-                simulator: DEFAULT_IRACING_SIMULATOR.to_string(),
-                selections: CpuSelections::new_all_selected(cpu_count),
-            }
-        };
+                is_enabled: rule_row.is_enabled,
+            };
+            rule.resolve_preset(topology);
+            rules.push(rule);
+        }
+
+        let loaded_rules = rules.clone();
+        let is_built_in_missing = !rules.iter().any(Rule::get_is_built_in);
+        if is_built_in_missing {
+            rules.insert(0, Rule::new_built_in(topology));
+        }
+
+        let settings = Settings::load(sqlite_pool).await?;
+        let loaded_settings = settings.clone();
 
-        Ok(self_)
+        Ok(Self { profile_id, rules, loaded_rules, settings, loaded_settings })
     }
 
     pub async fn create_pool() -> ResultBtAny<SqlitePool> {
@@ -85,7 +105,8 @@ impl PersistentStore {
         let connection_options = SqliteConnectOptions::from_str(
             format!("sqlite://{}", to_sqlite.to_string_lossy()).as_str(),
         )?
-        .journal_mode(SqliteJournalMode::Wal);
+        .journal_mode(SqliteJournalMode::Wal)
+        .create_if_missing(true);
         let sqlite_pool = SqlitePool::connect_with(connection_options).await?;
         info!("Connected to SQLite.");
 
@@ -98,76 +119,769 @@ impl PersistentStore {
         Ok(())
     }
 
-    pub async fn save(&self, sqlite_pool: &SqlitePool) -> ResultBtAny<()> {
+    /// Rules changed since they were loaded are written over, and those removed since are
+    /// deleted. The rest are left as they are, but for their ordering.
+    pub async fn save(&mut self, sqlite_pool: &SqlitePool) -> ResultBtAny<()> {
+        let mut transaction = sqlite_pool.begin().await?;
+
+        let profile_row = sqlx::query!("SELECT id FROM profiles WHERE id = ?1;", self.profile_id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        if profile_row.is_none() {
+            Err(format!("No profile with ID `{}`, it may have been deleted.", self.profile_id))?
+        }
+
+        let rule_ids: HashSet<_> = self.rules.iter().map(|rule| rule.id).collect();
+        for loaded_rule in self.loaded_rules.iter().filter(|loaded_rule| !rule_ids.contains(&loaded_rule.id)) {
+            sqlx::query!(
+                "DELETE FROM rules_selected_cpus WHERE profile_id = ?1 AND rule_id = ?2;",
+                self.profile_id,
+                loaded_rule.id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            sqlx::query!("DELETE FROM rules WHERE profile_id = ?1 AND id = ?2;", self.profile_id, loaded_rule.id)
+                .execute(&mut *transaction)
+                .await?;
+            info!("Deleted rule.");
+        }
+
+        let loaded_rules: HashMap<_, _> = self.loaded_rules.iter().map(|loaded_rule| (loaded_rule.id, loaded_rule)).collect();
+        for (ordering, rule) in self.rules.iter().enumerate() {
+            let ordering = i64::try_from(ordering)?;
+            match loaded_rules.get(&rule.id) {
+                Some(&loaded_rule) if loaded_rule == rule => {
+                    sqlx::query!(
+                        "UPDATE rules SET ordering = ?1 WHERE profile_id = ?2 AND id = ?3;",
+                        ordering,
+                        self.profile_id,
+                        rule.id
+                    )
+                    .execute(&mut *transaction)
+                    .await?;
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    let rule_row = sqlx::query!(
+                        "SELECT id FROM rules WHERE profile_id = ?1 AND id = ?2;",
+                        self.profile_id,
+                        rule.id
+                    )
+                    .fetch_optional(&mut *transaction)
+                    .await?;
+                    if rule_row.is_some() {
+                        Err(format!("Rule ID `{}` was taken since the rules were loaded, reload them.", rule.id))?
+                    }
+                }
+            }
+
+            let matcher = serde_json::to_string(&rule.matcher)?;
+            let spawner_matcher = rule.spawner_matcher.as_ref().map(serde_json::to_string).transpose()?;
+            let enforcement_strategy = rule.enforcement_strategy.as_str();
+            let spawner_reset = serde_json::to_string(&rule.spawner_reset)?;
+            let preset = serde_json::to_string(&rule.preset)?;
+            sqlx::query!(
+                r#"
+                INSERT INTO rules (profile_id, id, name, matcher, spawner_matcher, enforcement_strategy, spawner_reset, preset, is_enabled, ordering)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                ON CONFLICT (profile_id, id) DO UPDATE SET
+                    name = excluded.name,
+                    matcher = excluded.matcher,
+                    spawner_matcher = excluded.spawner_matcher,
+                    enforcement_strategy = excluded.enforcement_strategy,
+                    spawner_reset = excluded.spawner_reset,
+                    preset = excluded.preset,
+                    is_enabled = excluded.is_enabled,
+                    ordering = excluded.ordering;
+                "#,
+                self.profile_id,
+                rule.id,
+                rule.name,
+                matcher,
+                spawner_matcher,
+                enforcement_strategy,
+                spawner_reset,
+                preset,
+                rule.is_enabled,
+                ordering
+            )
+            .execute(&mut *transaction)
+            .await?;
+            info!("Saved rule.");
+
+            sqlx::query!(
+                "DELETE FROM rules_selected_cpus WHERE profile_id = ?1 AND rule_id = ?2;",
+                self.profile_id,
+                rule.id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            for &cpu_selection in rule.selections.inner.iter() {
+                let cpu_selection = u32::try_from(cpu_selection)?;
+
+                sqlx::query!(
+                    r#"
+                    INSERT OR REPLACE INTO rules_selected_cpus (profile_id, rule_id, cpu_id)
+                    VALUES (?1, ?2, ?3);
+                    "#,
+                    self.profile_id,
+                    rule.id,
+                    cpu_selection,
+                )
+                .execute(&mut *transaction)
+                .await?;
+            }
+            info!("Created selected CPUs relationship.");
+        }
+
+        self.settings.save(&self.loaded_settings, &mut transaction).await?;
+
+        transaction.commit().await?;
+        self.loaded_rules = self.rules.clone();
+        self.loaded_settings = self.settings.clone();
+
+        Ok(())
+    }
+
+    /// Of the active profile's rule, without saving the rest over changes made since.
+    pub async fn set_rule_enabled(rule_id: i64, is_enabled: bool, sqlite_pool: &SqlitePool) -> ResultBtAny<()> {
+        let is_updated = sqlx::query!(
+            r#"
+            UPDATE rules SET is_enabled = ?1
+            WHERE id = ?2 AND profile_id = (SELECT id FROM profiles ORDER BY is_active DESC, id LIMIT 1);
+            "#,
+            is_enabled,
+            rule_id
+        )
+        .execute(sqlite_pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if !is_updated {
+            Err(format!("No rule with ID `{rule_id}`."))?
+        }
+        info!("Updated rule.");
+
+        Ok(())
+    }
+
+    /// Falls back to the first profile if none is marked active.
+    async fn load_active_profile_id(sqlite_pool: &SqlitePool) -> ResultBtAny<i64> {
+        let profile_row = sqlx::query!("SELECT id FROM profiles ORDER BY is_active DESC, id LIMIT 1;")
+            .fetch_optional(sqlite_pool)
+            .await?;
+        info!("Queried active profile.");
+
+        Ok(profile_row.ok_or("There are no profiles.")?.id)
+    }
+
+    pub async fn load_profiles(sqlite_pool: &SqlitePool) -> ResultBtAny<Vec<Profile>> {
+        let active_profile_id = Self::load_active_profile_id(sqlite_pool).await?;
+        let profile_rows = sqlx::query!("SELECT id, name FROM profiles ORDER BY id;")
+            .fetch_all(sqlite_pool)
+            .await?;
+        info!("Queried profiles.");
+
+        Ok(profile_rows
+            .into_iter()
+            .map(|profile_row| Profile {
+                id: profile_row.id,
+                name: profile_row.name,
+                is_active: profile_row.id == active_profile_id,
+            })
+            .collect())
+    }
+
+    /// The worker picks it up on its next run.
+    pub async fn activate_profile(profile_id: i64, sqlite_pool: &SqlitePool) -> ResultBtAny<()> {
+        let mut transaction = sqlite_pool.begin().await?;
+
+        sqlx::query!("UPDATE profiles SET is_active = FALSE WHERE is_active;")
+            .execute(&mut *transaction)
+            .await?;
+        let is_activated = sqlx::query!("UPDATE profiles SET is_active = TRUE WHERE id = ?1;", profile_id)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected()
+            > 0;
+        if !is_activated {
+            Err(format!("No profile with ID `{profile_id}`."))?
+        }
+
+        transaction.commit().await?;
+        info!("Activated profile.");
+
+        Ok(())
+    }
+
+    /// With a copy of every rule, returning the new profile's ID.
+    pub async fn duplicate_profile(
+        profile_id: i64,
+        name: &str,
+        sqlite_pool: &SqlitePool,
+    ) -> ResultBtAny<i64> {
         let mut transaction = sqlite_pool.begin().await?;
 
+        let profile_row = sqlx::query!("SELECT id FROM profiles WHERE id = ?1;", profile_id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        if profile_row.is_none() {
+            Err(format!("No profile with ID `{profile_id}`."))?
+        }
+        let new_profile_id = sqlx::query!("INSERT INTO profiles (name) VALUES (?1);", name)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| format!("Couldn't add profile `{name}`, {e}."))?
+            .last_insert_rowid();
         sqlx::query!(
             r#"
-            DELETE FROM processes_selected_cpus
-            WHERE process_id = ?1;
+            INSERT INTO rules (profile_id, id, name, matcher, spawner_matcher, enforcement_strategy, spawner_reset, preset, is_enabled, ordering)
+            SELECT ?1, id, name, matcher, spawner_matcher, enforcement_strategy, spawner_reset, preset, is_enabled, ordering
+            FROM rules
+            WHERE profile_id = ?2;
             "#,
-            Self::SIMULATOR_PROCESS_ID,
+            new_profile_id,
+            profile_id
         )
         .execute(&mut *transaction)
         .await?;
-        info!("Deleted selected CPUs.");
-
         sqlx::query!(
             r#"
-            INSERT OR REPLACE INTO processes (id, name)
-            VALUES (?1, ?2);
+            INSERT INTO rules_selected_cpus (profile_id, rule_id, cpu_id)
+            SELECT ?1, rule_id, cpu_id
+            FROM rules_selected_cpus
+            WHERE profile_id = ?2;
             "#,
-            Self::SPAWNER_PROCESS_ID,
-            self.spawner
+            new_profile_id,
+            profile_id
         )
         .execute(&mut *transaction)
         .await?;
-        info!("Inserted spawner process name.");
 
-        sqlx::query!(
+        transaction.commit().await?;
+        info!("Duplicated profile.");
+
+        Ok(new_profile_id)
+    }
+
+    pub async fn rename_profile(profile_id: i64, name: &str, sqlite_pool: &SqlitePool) -> ResultBtAny<()> {
+        let is_renamed = sqlx::query!("UPDATE profiles SET name = ?1 WHERE id = ?2;", name, profile_id)
+            .execute(sqlite_pool)
+            .await
+            .map_err(|e| format!("Couldn't rename profile to `{name}`, {e}."))?
+            .rows_affected()
+            > 0;
+        if !is_renamed {
+            Err(format!("No profile with ID `{profile_id}`."))?
+        }
+        info!("Renamed profile.");
+
+        Ok(())
+    }
+
+    /// Along with its rules and triggers, the active profile can't be deleted.
+    pub async fn delete_profile(profile_id: i64, sqlite_pool: &SqlitePool) -> ResultBtAny<()> {
+        let mut transaction = sqlite_pool.begin().await?;
+
+        let profile_row = sqlx::query!("SELECT is_active FROM profiles WHERE id = ?1;", profile_id)
+            .fetch_optional(&mut *transaction)
+            .await?
+            .ok_or(format!("No profile with ID `{profile_id}`."))?;
+        if profile_row.is_active {
+            Err("The active profile can't be deleted, switch to another first.")?
+        }
+        sqlx::query!("DELETE FROM rules_selected_cpus WHERE profile_id = ?1;", profile_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query!("DELETE FROM rules WHERE profile_id = ?1;", profile_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query!("DELETE FROM profile_triggers WHERE profile_id = ?1;", profile_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query!("DELETE FROM profiles WHERE id = ?1;", profile_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+        info!("Deleted profile.");
+
+        Ok(())
+    }
+
+    /// Every profile's, highest priority first.
+    pub async fn load_profile_triggers(sqlite_pool: &SqlitePool) -> ResultBtAny<Vec<ProfileTrigger>> {
+        let trigger_rows = sqlx::query!(
             r#"
-            INSERT OR REPLACE INTO processes (id, name)
-            VALUES (?1, ?2);
-            "#,
-            Self::SIMULATOR_PROCESS_ID,
-            self.simulator
+            SELECT id, profile_id, matcher, priority, is_enabled
+            FROM profile_triggers
+            ORDER BY priority DESC, id;
+            "#
+        )
+        .fetch_all(sqlite_pool)
+        .await?;
+        info!("Queried profile triggers.");
+
+        let mut profile_triggers = vec![];
+        for trigger_row in trigger_rows {
+            profile_triggers.push(ProfileTrigger {
+                id: trigger_row.id,
+                profile_id: trigger_row.profile_id,
+                matcher: serde_json::from_str(&trigger_row.matcher)?,
+                priority: trigger_row.priority,
+                is_enabled: trigger_row.is_enabled,
+            });
+        }
+        Ok(profile_triggers)
+    }
+
+    /// Returning the new trigger's ID.
+    pub async fn add_profile_trigger(
+        profile_id: i64,
+        matcher: &ProcessMatcher,
+        priority: i64,
+        sqlite_pool: &SqlitePool,
+    ) -> ResultBtAny<i64> {
+        let mut transaction = sqlite_pool.begin().await?;
+
+        let profile_row = sqlx::query!("SELECT id FROM profiles WHERE id = ?1;", profile_id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        if profile_row.is_none() {
+            Err(format!("No profile with ID `{profile_id}`."))?
+        }
+        let matcher = serde_json::to_string(matcher)?;
+        let trigger_id = sqlx::query!(
+            "INSERT INTO profile_triggers (profile_id, matcher, priority) VALUES (?1, ?2, ?3);",
+            profile_id,
+            matcher,
+            priority
         )
         .execute(&mut *transaction)
+        .await?
+        .last_insert_rowid();
+
+        transaction.commit().await?;
+        info!("Added profile trigger.");
+
+        Ok(trigger_id)
+    }
+
+    pub async fn set_profile_trigger_enabled(
+        trigger_id: i64,
+        is_enabled: bool,
+        sqlite_pool: &SqlitePool,
+    ) -> ResultBtAny<()> {
+        let is_updated = sqlx::query!(
+            "UPDATE profile_triggers SET is_enabled = ?1 WHERE id = ?2;",
+            is_enabled,
+            trigger_id
+        )
+        .execute(sqlite_pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if !is_updated {
+            Err(format!("No profile trigger with ID `{trigger_id}`."))?
+        }
+        info!("Updated profile trigger.");
+
+        Ok(())
+    }
+
+    pub async fn remove_profile_trigger(trigger_id: i64, sqlite_pool: &SqlitePool) -> ResultBtAny<()> {
+        let is_removed = sqlx::query!("DELETE FROM profile_triggers WHERE id = ?1;", trigger_id)
+            .execute(sqlite_pool)
+            .await?
+            .rows_affected()
+            > 0;
+        if !is_removed {
+            Err(format!("No profile trigger with ID `{trigger_id}`."))?
+        }
+        info!("Removed profile trigger.");
+
+        Ok(())
+    }
+
+    pub async fn load_original_affinities(
+        cpu_count: usize,
+        sqlite_pool: &SqlitePool,
+    ) -> ResultBtAny<Vec<OriginalAffinity>> {
+        let original_rows = sqlx::query!(
+            "SELECT process_id, start_time, rule_id, cpus FROM original_affinities;"
+        )
+        .fetch_all(sqlite_pool)
         .await?;
-        info!("Inserted simulator process name.");
-        for &cpu_selection in self.selections.inner.iter() {
-            let cpu_selection = u32::try_from(cpu_selection)?;
+        info!("Queried original affinities.");
+
+        let mut original_affinities = vec![];
+        for original_row in original_rows {
+            let cpu_selections: HashSet<usize> = serde_json::from_str(&original_row.cpus)?;
+            original_affinities.push(OriginalAffinity {
+                process_id: original_row.process_id.try_into()?,
+                start_time: original_row.start_time.try_into()?,
+                rule_id: original_row.rule_id,
+                selections: CpuSelections::new_preselected(cpu_selections, cpu_count),
+            });
+        }
+        Ok(original_affinities)
+    }
+
+    /// Only touches the processes given, as another process may be keeping originals in the
+    /// same store.
+    pub async fn save_original_affinities(
+        original_affinities: &[OriginalAffinity],
+        restored_keys: &[ProcessKey],
+        sqlite_pool: &SqlitePool,
+    ) -> ResultBtAny<()> {
+        let mut transaction = sqlite_pool.begin().await?;
 
+        for &(process_id, start_time) in restored_keys {
+            let start_time = i64::try_from(start_time)?;
             sqlx::query!(
-                r#"
-                INSERT OR REPLACE INTO cpus (id)
-                VALUES (?1);
-                "#,
-                cpu_selection
+                "DELETE FROM original_affinities WHERE process_id = ?1 AND start_time = ?2;",
+                process_id,
+                start_time
             )
             .execute(&mut *transaction)
             .await?;
-            info!("Inserted existing CPUs.");
+        }
 
+        for original_affinity in original_affinities {
+            let start_time = i64::try_from(original_affinity.start_time)?;
+            let mut cpus: Vec<_> = original_affinity.selections.inner.iter().collect();
+            cpus.sort();
+            let cpus = serde_json::to_string(&cpus)?;
             sqlx::query!(
                 r#"
-                INSERT OR REPLACE INTO processes_selected_cpus (process_id, cpu_id)
-                VALUES (?1, ?2);
+                INSERT OR REPLACE INTO original_affinities (process_id, start_time, rule_id, cpus)
+                VALUES (?1, ?2, ?3, ?4);
                 "#,
-                Self::SIMULATOR_PROCESS_ID,
-                cpu_selection,
+                original_affinity.process_id,
+                start_time,
+                original_affinity.rule_id,
+                cpus
             )
             .execute(&mut *transaction)
             .await?;
-            info!("Created selected CPUs relationship.");
         }
 
         transaction.commit().await?;
+        info!("Saved original affinities.");
+
+        Ok(())
+    }
+
+    /// The last one from whichever process is running the worker, `None` if
+    /// it's never run.
+    pub async fn load_heartbeat(sqlite_pool: &SqlitePool) -> ResultBtAny<Option<WorkerHeartbeat>> {
+        let heartbeat_row = sqlx::query!("SELECT value FROM worker_heartbeats WHERE id = 0;")
+            .fetch_optional(sqlite_pool)
+            .await?;
+        info!("Queried worker heartbeat.");
+
+        Ok(heartbeat_row
+            .map(|heartbeat_row| serde_json::from_str(&heartbeat_row.value))
+            .transpose()?)
+    }
+
+    pub async fn save_heartbeat(
+        worker_heartbeat: &WorkerHeartbeat,
+        sqlite_pool: &SqlitePool,
+    ) -> ResultBtAny<()> {
+        let value = serde_json::to_string(worker_heartbeat)?;
+        sqlx::query!(
+            "INSERT OR REPLACE INTO worker_heartbeats (id, value) VALUES (0, ?1);",
+            value
+        )
+        .execute(sqlite_pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// A named set of rules, only the active one's are applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: i64,
+    pub name: String,
+    pub is_active: bool,
+}
+
+impl Profile {
+    /// What the rules from before profiles were carried over to.
+    pub const DEFAULT_ID: i64 = 0;
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Activates its profile while a process it matches is running, see
+/// `ProfileSwitcher`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileTrigger {
+    pub id: i64,
+    pub profile_id: i64,
+    pub matcher: ProcessMatcher,
+    /// The highest wins when several match, then the lowest ID.
+    pub priority: i64,
+    pub is_enabled: bool,
+}
+
+/// App-wide options, kept as one row per key with JSON values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Wakes the worker as processes start rather than only polling, Linux
+    /// only and falls back to polling when unavailable.
+    pub is_process_events_enabled: bool,
+    pub log_level: LogLevel,
+}
+
+impl Settings {
+    const IS_PROCESS_EVENTS_ENABLED_KEY: &str = "is_process_events_enabled";
+    const LOG_LEVEL_KEY: &str = "log_level";
+
+    async fn load(sqlite_pool: &SqlitePool) -> ResultBtAny<Self> {
+        let setting_rows = sqlx::query!("SELECT key, value FROM settings;")
+            .fetch_all(sqlite_pool)
+            .await?;
+        info!("Queried settings.");
+        let values: HashMap<_, _> = setting_rows
+            .into_iter()
+            .map(|setting_row| (setting_row.key, setting_row.value))
+            .collect();
+
+        let mut settings = Self::default();
+        if let Some(value) = values.get(Self::IS_PROCESS_EVENTS_ENABLED_KEY) {
+            settings.is_process_events_enabled = serde_json::from_str(value)?;
+        }
+        if let Some(value) = values.get(Self::LOG_LEVEL_KEY) {
+            settings.log_level = serde_json::from_str(value)?;
+        }
+        Ok(settings)
+    }
+
+    fn get_values(&self) -> ResultBtAny<[(&'static str, String); 2]> {
+        Ok([
+            (
+                Self::IS_PROCESS_EVENTS_ENABLED_KEY,
+                serde_json::to_string(&self.is_process_events_enabled)?,
+            ),
+            (Self::LOG_LEVEL_KEY, serde_json::to_string(&self.log_level)?),
+        ])
+    }
+
+    /// Only the keys changed from `loaded_settings`.
+    async fn save(
+        &self,
+        loaded_settings: &Self,
+        transaction: &mut sqlx::SqliteConnection,
+    ) -> ResultBtAny<()> {
+        let changed_values = self
+            .get_values()?
+            .into_iter()
+            .zip(loaded_settings.get_values()?)
+            .filter(|((_, value), (_, loaded_value))| value != loaded_value)
+            .map(|(key_value, _)| key_value);
+        for (key, value) in changed_values {
+            sqlx::query!(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2);",
+                key,
+                value
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+        info!("Saved settings.");
 
         Ok(())
     }
 }
 
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            is_process_events_enabled: true,
+            log_level: LogLevel::Info,
+        }
+    }
+}
+
+/// A process's affinity from before it was first changed, persisted so it can
+/// still be put back after a crash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OriginalAffinity {
+    pub process_id: u32,
+    /// Tells a reused PID apart from the original process.
+    pub start_time: u64,
+    pub rule_id: i64,
+    pub selections: CpuSelections,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub id: i64,
+    pub name: String,
+    pub matcher: ProcessMatcher,
+    /// Set only for the built-in rule, whose target is launched by a spawner
+    /// and inherits its affinity.
+    pub spawner_matcher: Option<ProcessMatcher>,
+    pub enforcement_strategy: EnforcementStrategy,
+    pub spawner_reset: SpawnerReset,
+    pub preset: SelectionPreset,
+    /// Resolved from `preset` on load, unless it's `Custom`.
+    pub selections: CpuSelections,
+    pub is_enabled: bool,
+}
+
+impl Rule {
+    pub const BUILT_IN_ID: i64 = 0;
+
+    pub fn new_built_in(topology: &CpuTopology) -> Self {
+        let mut rule = Self {
+            id: Self::BUILT_IN_ID,
+            name: String::from("iRacing"),
+            matcher: ProcessMatcher::new_exact_image_name(DEFAULT_IRACING_SIMULATOR),
+            spawner_matcher: Some(ProcessMatcher::new_exact_image_name(DEFAULT_SIMULATOR_SPAWNERS)),
+            enforcement_strategy: EnforcementStrategy::InheritViaSpawner,
+            spawner_reset: SpawnerReset::All,
+            preset: SelectionPreset::All,
+            selections: CpuSelections::new(topology.get_cpu_count()),
+            is_enabled: true,
+        };
+        rule.resolve_preset(topology);
+        rule
+    }
+
+    pub fn new_custom(id: i64, topology: &CpuTopology) -> Self {
+        Self {
+            id,
+            name: format!("Rule {id}"),
+            matcher: ProcessMatcher::default(),
+            spawner_matcher: None,
+            enforcement_strategy: EnforcementStrategy::Direct,
+            spawner_reset: SpawnerReset::All,
+            preset: SelectionPreset::Custom,
+            selections: CpuSelections::new_all_selected(topology.get_cpu_count()),
+            is_enabled: true,
+        }
+    }
+
+    pub fn get_is_built_in(&self) -> bool {
+        self.id == Self::BUILT_IN_ID
+    }
+
+    pub fn resolve_preset(&mut self, topology: &CpuTopology) {
+        if let Some(cpu_selections) = self.preset.resolve(topology) {
+            self.selections = CpuSelections::new_preselected(cpu_selections, topology.get_cpu_count());
+        }
+    }
+}
+
+/// How a rule's CPUs reach its target, only rules with a spawner can inherit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnforcementStrategy {
+    /// The target picks up the spawner's affinity when launched and is never
+    /// touched itself, e.g. for anti-cheat.
+    InheritViaSpawner,
+    Direct,
+    Both,
+}
+
+impl EnforcementStrategy {
+    pub fn get_all() -> Vec<Self> {
+        vec![Self::InheritViaSpawner, Self::Direct, Self::Both]
+    }
+
+    pub fn get_is_inheriting(&self) -> bool {
+        matches!(self, Self::InheritViaSpawner | Self::Both)
+    }
+
+    pub fn get_is_direct(&self) -> bool {
+        matches!(self, Self::Direct | Self::Both)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::InheritViaSpawner => "inherit_via_spawner",
+            Self::Direct => "direct",
+            Self::Both => "both",
+        }
+    }
+}
+
+impl FromStr for EnforcementStrategy {
+    type Err = String;
+
+    fn from_str(enforcement_strategy: &str) -> Result<Self, Self::Err> {
+        Self::get_all()
+            .into_iter()
+            .find(|strategy| strategy.as_str() == enforcement_strategy)
+            .ok_or_else(|| format!("Unknown enforcement strategy `{enforcement_strategy}`."))
+    }
+}
+
+impl Display for EnforcementStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InheritViaSpawner => write!(f, "Via spawner"),
+            Self::Direct => write!(f, "Directly"),
+            Self::Both => write!(f, "Via spawner and directly"),
+        }
+    }
+}
+
+/// What spawners are set to once the target has inherited their affinity.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SpawnerReset {
+    /// Whatever the spawner had before it was first changed.
+    Original,
+    All,
+    /// The CPUs the rule doesn't select.
+    Complement,
+    Custom { cpus: BTreeSet<usize> },
+}
+
+impl SpawnerReset {
+    pub fn get_all() -> Vec<Self> {
+        vec![Self::Original, Self::All, Self::Complement, Self::Custom { cpus: BTreeSet::new() }]
+    }
+
+    /// Falls back to all CPUs rather than leave a spawner with none.
+    pub fn resolve(&self, rule_selections: &CpuSelections, original: Option<&CpuSelections>) -> CpuSelections {
+        let cpu_count = rule_selections.cpu_count;
+        let cpu_selections = match self {
+            Self::Original => original.map(|original| original.inner.clone()).unwrap_or_default(),
+            Self::All => HashSet::new(),
+            Self::Complement => (0..cpu_count).filter(|cpu_id| !rule_selections.get_is_selected(cpu_id)).collect(),
+            Self::Custom { cpus } => cpus.iter().copied().filter(|&cpu_id| cpu_id < cpu_count).collect(),
+        };
+        if cpu_selections.is_empty() {
+            CpuSelections::new_all_selected(cpu_count)
+        } else {
+            CpuSelections::new_preselected(cpu_selections, cpu_count)
+        }
+    }
+}
+
+impl Display for SpawnerReset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Original => write!(f, "Original"),
+            Self::All => write!(f, "All"),
+            Self::Complement => write!(f, "Unselected CPUs"),
+            Self::Custom { .. } => write!(f, "Custom"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq)]
 pub struct CpuSelections {
     inner: HashSet<usize>,
@@ -243,7 +957,7 @@ impl CpuSelections {
         Ok(())
     }
 
-    pub fn to_mask(&self) -> usize {
+    pub fn to_mask(&self) -> CpuMask {
         hashset_to_mask(&self.inner)
     }
 }
@@ -254,8 +968,8 @@ impl PartialEq for CpuSelections {
     }
 }
 
-impl<'a> From<&'a PersistentStore> for &'a CpuSelections {
-    fn from(value: &'a PersistentStore) -> Self {
+impl<'a> From<&'a Rule> for &'a CpuSelections {
+    fn from(value: &'a Rule) -> Self {
         &value.selections
     }
 }
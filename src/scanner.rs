@@ -0,0 +1,272 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use sysinfo::{Pid, Process, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+use tracing::{error, info};
+
+use crate::matcher::{ProcessName, get_windows_image_name};
+#[cfg(target_os = "linux")]
+use crate::wrappers::get_process_ids;
+
+/// Catches what incremental scans can miss, like processes whose details
+/// changed without an exec being seen.
+const FULL_SCAN_PERIOD_SECONDS: u64 = 60;
+
+/// New processes are looked at again for this many scans, as a fork only gets
+/// its own name once it execs.
+const RECENT_SCAN_COUNT: u64 = 2;
+
+/// Worked out once per process rather than per rule every run.
+#[derive(Debug, Clone)]
+pub struct ScannedProcess {
+    pub start_time: u64,
+    pub name: ProcessName,
+    /// For Wine/Proton processes, see `get_windows_image_name`.
+    pub windows_image_name: Option<ProcessName>,
+    pub command_line: String,
+}
+
+impl ScannedProcess {
+    fn new(process: &Process) -> Self {
+        let name = process.name().to_string_lossy().into_owned();
+        let windows_image_name = get_windows_image_name(&name, process.exe(), process.cmd());
+        let command_line = process
+            .cmd()
+            .iter()
+            .map(|argument| argument.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            start_time: process.start_time(),
+            name: ProcessName {
+                name,
+                is_truncated: false,
+            },
+            windows_image_name,
+            command_line,
+        }
+    }
+}
+
+/// What the last scan cost.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ScanStats {
+    pub duration: Duration,
+    pub process_count: usize,
+    pub refreshed_count: usize,
+    pub is_full: bool,
+}
+
+/// Keeps processes between runs, refreshing only those that are new or gone
+/// rather than every process on the system.
+pub struct ProcessScanner {
+    system_info: System,
+    /// By PID, checked against the start time each scan so a reused PID is
+    /// scanned again.
+    pub(crate) scanned_processes: HashMap<Pid, ScannedProcess>,
+    /// By PID, how many more scans new processes are refreshed in.
+    recent_scans_left: HashMap<Pid, u64>,
+    last_full_at: Option<Instant>,
+}
+
+impl ProcessScanner {
+    pub fn new(system_info: System) -> Self {
+        Self {
+            system_info,
+            scanned_processes: HashMap::new(),
+            recent_scans_left: HashMap::new(),
+            last_full_at: None,
+        }
+    }
+
+    pub fn get_system(&self) -> &System {
+        &self.system_info
+    }
+
+    pub fn get_scanned_processes(&self) -> impl Iterator<Item = (&Process, &ScannedProcess)> {
+        self.system_info
+            .processes()
+            .iter()
+            .filter_map(|(process_id, process)| {
+                let scanned_process = self.scanned_processes.get(process_id)?;
+                Some((process, scanned_process))
+            })
+    }
+
+    pub fn scan(&mut self) -> ScanStats {
+        let started_at = Instant::now();
+
+        let is_full = self.last_full_at.is_none_or(|last_full_at| {
+            last_full_at.elapsed() >= Duration::from_secs(FULL_SCAN_PERIOD_SECONDS)
+        });
+        let refreshed_ids = if is_full {
+            self.system_info.refresh_processes_specifics(
+                ProcessesToUpdate::All,
+                true,
+                Self::get_refresh_kind(),
+            );
+            self.last_full_at = Some(started_at);
+            self.system_info.processes().keys().copied().collect()
+        } else {
+            self.refresh_incrementally()
+        };
+
+        self.scanned_processes
+            .retain(|process_id, _| self.system_info.process(*process_id).is_some());
+        self.recent_scans_left
+            .retain(|process_id, _| self.system_info.process(*process_id).is_some());
+        let mut refreshed_count = 0;
+        for process_id in &refreshed_ids {
+            // Threads are listed as processes on Linux, but rules only target
+            // whole processes.
+            let process = self
+                .system_info
+                .process(*process_id)
+                .filter(|process| process.thread_kind().is_none());
+            let Some(process) = process else {
+                continue;
+            };
+            self.scanned_processes
+                .insert(*process_id, ScannedProcess::new(process));
+            refreshed_count += 1;
+        }
+
+        let scan_stats = ScanStats {
+            duration: started_at.elapsed(),
+            process_count: self.scanned_processes.len(),
+            refreshed_count,
+            is_full,
+        };
+        info!(
+            "Scanned {} of {} processes in {:?}.",
+            scan_stats.refreshed_count, scan_stats.process_count, scan_stats.duration
+        );
+        scan_stats
+    }
+
+    /// Returns the new and recently new processes that got refreshed.
+    fn refresh_incrementally(&mut self) -> Vec<Pid> {
+        let process_ids = self.list_process_ids();
+
+        let gone_ids: Vec<_> = self
+            .scanned_processes
+            .keys()
+            .filter(|process_id| !process_ids.contains(process_id))
+            .copied()
+            .collect();
+        if !gone_ids.is_empty() {
+            self.system_info.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&gone_ids),
+                true,
+                ProcessRefreshKind::nothing(),
+            );
+        }
+
+        let known_ids: Vec<_> = process_ids
+            .iter()
+            .filter(|process_id| self.scanned_processes.contains_key(process_id))
+            .copied()
+            .collect();
+        self.refresh_start_times(&known_ids);
+        let reused_ids: Vec<_> = known_ids
+            .into_iter()
+            .filter(|process_id| {
+                let start_time = self
+                    .system_info
+                    .process(*process_id)
+                    .map(Process::start_time);
+                let scanned_start_time = self
+                    .scanned_processes
+                    .get(process_id)
+                    .map(|scanned_process| scanned_process.start_time);
+                start_time != scanned_start_time
+            })
+            .collect();
+        for process_id in &reused_ids {
+            self.scanned_processes.remove(process_id);
+            self.recent_scans_left.remove(process_id);
+        }
+        if !reused_ids.is_empty() {
+            info!("Evicted {} reused PIDs.", reused_ids.len());
+        }
+
+        let refreshed_ids: Vec<_> = process_ids
+            .into_iter()
+            .filter(|process_id| {
+                !self.scanned_processes.contains_key(process_id)
+                    || self.recent_scans_left.contains_key(process_id)
+            })
+            .collect();
+        for process_id in &refreshed_ids {
+            if !self.scanned_processes.contains_key(process_id) {
+                self.recent_scans_left
+                    .insert(*process_id, RECENT_SCAN_COUNT);
+            } else if let Some(scans_left) = self.recent_scans_left.get_mut(process_id) {
+                *scans_left -= 1;
+                if *scans_left == 0 {
+                    self.recent_scans_left.remove(process_id);
+                }
+            }
+        }
+        if !refreshed_ids.is_empty() {
+            self.system_info.refresh_processes_specifics(
+                ProcessesToUpdate::Some(&refreshed_ids),
+                true,
+                Self::get_refresh_kind(),
+            );
+        }
+        refreshed_ids
+    }
+
+    /// `/proc` can be listed without reading anything about each process.
+    #[cfg(target_os = "linux")]
+    fn list_process_ids(&mut self) -> HashSet<Pid> {
+        match get_process_ids() {
+            Ok(process_ids) => process_ids.into_iter().map(Pid::from_u32).collect(),
+            Err(e) => {
+                error!("{:?}", e);
+                self.scanned_processes.keys().copied().collect()
+            }
+        }
+    }
+
+    /// Windows lists every process, names included, in one call anyway.
+    #[cfg(not(target_os = "linux"))]
+    fn list_process_ids(&mut self) -> HashSet<Pid> {
+        self.system_info.refresh_processes_specifics(
+            ProcessesToUpdate::All,
+            true,
+            ProcessRefreshKind::nothing(),
+        );
+        self.system_info.processes().keys().copied().collect()
+    }
+
+    /// A process found at a PID with another start time replaces the one
+    /// there.
+    #[cfg(target_os = "linux")]
+    fn refresh_start_times(&mut self, process_ids: &[Pid]) {
+        if process_ids.is_empty() {
+            return;
+        }
+        self.system_info.refresh_processes_specifics(
+            ProcessesToUpdate::Some(process_ids),
+            true,
+            ProcessRefreshKind::nothing(),
+        );
+    }
+
+    /// Already refreshed along with the list.
+    #[cfg(not(target_os = "linux"))]
+    fn refresh_start_times(&mut self, _process_ids: &[Pid]) {}
+
+    /// Always, as a PID that execs keeps its ID but not its command line.
+    fn get_refresh_kind() -> ProcessRefreshKind {
+        ProcessRefreshKind::nothing()
+            .with_exe(UpdateKind::Always)
+            .with_cmd(UpdateKind::Always)
+            .with_user(UpdateKind::OnlyIfNotSet)
+    }
+}
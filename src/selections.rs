@@ -1,20 +1,193 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt::Display};
 
-pub fn hashset_to_mask(selections: &HashSet<usize>) -> usize {
-    let mut mask = 0usize;
-    for selection in selections {
-        mask |= 1usize << selection;
+use serde::{Deserialize, Serialize};
+
+use crate::topology::CpuTopology;
+
+/// Bitset of logical CPU indices that grows to fit, so masks aren't capped at
+/// `usize::BITS` CPUs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CpuMask {
+    words: Vec<u64>,
+}
+
+impl CpuMask {
+    const WORD_BITS: usize = u64::BITS as usize;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, cpu_id: usize) {
+        let word_index = cpu_id / Self::WORD_BITS;
+        if word_index >= self.words.len() {
+            self.words.resize(word_index + 1, 0);
+        }
+        self.words[word_index] |= 1u64 << (cpu_id % Self::WORD_BITS);
+    }
+
+    pub fn get_is_set(&self, cpu_id: usize) -> bool {
+        self.words
+            .get(cpu_id / Self::WORD_BITS)
+            .is_some_and(|word| word & (1u64 << (cpu_id % Self::WORD_BITS)) != 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .flat_map(|(word_index, word)| {
+                (0..Self::WORD_BITS)
+                    .filter(move |bit_index| word & (1u64 << bit_index) != 0)
+                    .map(move |bit_index| word_index * Self::WORD_BITS + bit_index)
+            })
+    }
+
+    pub fn union_with(&mut self, other: &CpuMask) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other_word) in self.words.iter_mut().zip(other.words.iter()) {
+            *word |= other_word;
+        }
+    }
+
+    /// Reads up to `count` bits starting at `offset`, e.g. one Windows
+    /// processor group.
+    pub fn get_range(&self, offset: usize, count: usize) -> u64 {
+        let mut range = 0u64;
+        for bit_index in 0..count.min(Self::WORD_BITS) {
+            if self.get_is_set(offset + bit_index) {
+                range |= 1u64 << bit_index;
+            }
+        }
+        range
+    }
+
+    pub fn set_range(&mut self, offset: usize, range: u64) {
+        for bit_index in 0..Self::WORD_BITS {
+            let is_selected = range & (1u64 << bit_index) != 0;
+            if is_selected {
+                self.set(offset + bit_index);
+            }
+        }
+    }
+}
+
+impl FromIterator<usize> for CpuMask {
+    fn from_iter<T: IntoIterator<Item = usize>>(cpu_ids: T) -> Self {
+        let mut mask = Self::new();
+        for cpu_id in cpu_ids {
+            mask.set(cpu_id);
+        }
+        mask
+    }
+}
+
+pub fn hashset_to_mask(selections: &HashSet<usize>) -> CpuMask {
+    selections.iter().copied().collect()
+}
+
+pub fn mask_to_hashset(mask: &CpuMask) -> HashSet<usize> {
+    mask.iter().collect()
+}
+
+/// Named selection that's re-resolved against the current machine's topology
+/// instead of freezing a list of CPU indices.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SelectionPreset {
+    Custom,
+    All,
+    OnePerPhysicalCore,
+    AllButFirstCore,
+    LargestCache,
+    PerformanceCores,
+    LastPhysicalCores { count: usize },
+}
+
+impl SelectionPreset {
+    pub const DEFAULT_LAST_CORE_COUNT: usize = 4;
+
+    pub fn get_all() -> Vec<Self> {
+        vec![
+            Self::Custom,
+            Self::All,
+            Self::OnePerPhysicalCore,
+            Self::AllButFirstCore,
+            Self::LargestCache,
+            Self::PerformanceCores,
+            Self::LastPhysicalCores {
+                count: Self::DEFAULT_LAST_CORE_COUNT,
+            },
+        ]
+    }
+
+    /// `None` for `Custom`, whose CPUs are whatever was last ticked.
+    pub fn resolve(&self, topology: &CpuTopology) -> Option<HashSet<usize>> {
+        let all_cpus = || topology.get_cpus().iter().map(|cpu| cpu.id);
+        let cpu_selections = match self {
+            Self::Custom => return None,
+            Self::All => all_cpus().collect(),
+            Self::OnePerPhysicalCore => topology
+                .get_physical_cores()
+                .iter()
+                .filter_map(|siblings| siblings.first().copied())
+                .collect(),
+            Self::AllButFirstCore => {
+                let first_siblings = topology.get_siblings(0);
+                all_cpus()
+                    .filter(|cpu_id| !first_siblings.contains(cpu_id))
+                    .collect()
+            }
+            Self::LargestCache => {
+                // Ties, including caches of unknown size, go to the first CCD.
+                let cache_groups = topology.get_cache_groups();
+                let largest_size = cache_groups.iter().map(|cache| cache.size).max().flatten();
+                cache_groups
+                    .into_iter()
+                    .find(|cache| cache.size == largest_size)
+                    .map(|cache| cache.cpus.into_iter().collect())
+                    .unwrap_or_default()
+            }
+            Self::PerformanceCores => {
+                let performance_class = topology
+                    .get_cpus()
+                    .iter()
+                    .map(|cpu| cpu.efficiency_class)
+                    .max()
+                    .unwrap_or_default();
+                topology
+                    .get_cpus()
+                    .iter()
+                    .filter(|cpu| cpu.efficiency_class == performance_class)
+                    .map(|cpu| cpu.id)
+                    .collect()
+            }
+            Self::LastPhysicalCores { count } => {
+                let physical_cores = topology.get_physical_cores();
+                let skipped_count = physical_cores.len().saturating_sub(*count);
+                physical_cores
+                    .into_iter()
+                    .skip(skipped_count)
+                    .flatten()
+                    .collect()
+            }
+        };
+        Some(cpu_selections)
     }
-    mask
 }
 
-pub fn mask_to_hashset(mask: &usize) -> HashSet<usize> {
-    let mut hashset = HashSet::new();
-    for bit_index in 0..usize::BITS {
-        let is_selected = (1usize << bit_index) & mask != 0;
-        if is_selected {
-            hashset.insert(bit_index as usize);
+impl Display for SelectionPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Custom => write!(f, "Custom"),
+            Self::All => write!(f, "All"),
+            Self::OnePerPhysicalCore => write!(f, "One thread per core"),
+            Self::AllButFirstCore => write!(f, "All but core 0"),
+            Self::LargestCache => write!(f, "Largest cache (V-Cache)"),
+            Self::PerformanceCores => write!(f, "P-cores only"),
+            Self::LastPhysicalCores { count } => write!(f, "Last {count} cores"),
         }
     }
-    hashset
 }
@@ -0,0 +1,24 @@
+use sd_notify::NotifyState;
+use tracing::info;
+
+use crate::errors::ResultBtAny;
+
+/// For `Type=notify` units, does nothing when not started by systemd.
+pub fn notify_ready() -> ResultBtAny<()> {
+    sd_notify::notify(false, &[NotifyState::Ready])?;
+    info!("Notified systemd of readiness.");
+    Ok(())
+}
+
+pub fn notify_stopping() -> ResultBtAny<()> {
+    sd_notify::notify(false, &[NotifyState::Stopping])?;
+    info!("Notified systemd of stopping.");
+    Ok(())
+}
+
+/// Sent per heartbeat, so systemd restarts a worker that's stopped running
+/// once `WatchdogSec` passes without one.
+pub fn notify_watchdog(status: &str) -> ResultBtAny<()> {
+    sd_notify::notify(false, &[NotifyState::Watchdog, NotifyState::Status(status)])?;
+    Ok(())
+}
@@ -1,15 +1,54 @@
-use std::process::Command;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    path::Path,
+    process::Command,
+};
 
 use sysinfo::System;
-use tokio::sync::watch;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::watch,
+};
 
 use crate::{
     errors::ResultBtAny,
+    events::{ProcessEvent, parse_process_event},
+    instance::{InstanceKind, InstanceLock},
+    ipc::{
+        IpcNotification, IpcResponse,
+        client::IpcClient,
+        server::{IpcState, serve_connection},
+    },
     ir::{DEFAULT_IRACING_SIMULATOR, DEFAULT_SIMULATOR_SPAWNERS},
-    persistence::{CpuSelections, PersistentStore},
-    worker::{IrAProcess, WorkerHeartbeat, WorkerOperations_, run_worker_logic},
+    logging::{BroadcastLayer, LogLevel},
+    matcher::{
+        MatchCondition, MatchConditionKind, ProcessMatcher, ProcessName, get_is_glob_match,
+        get_windows_image_name,
+    },
+    persistence::{
+        CpuSelections, EnforcementStrategy, OriginalAffinity, PersistentStore, Profile,
+        ProfileTrigger, Rule, Settings, SpawnerReset,
+    },
+    scanner::{ProcessScanner, ScanStats},
+    selections::{CpuMask, SelectionPreset, hashset_to_mask, mask_to_hashset},
+    topology::CpuTopology,
+    tracking::{FailureKind, ProcessKey, ProcessTracker},
+    triggers::{ProfileActivation, ProfileReport, ProfileSwitcher},
+    worker::{
+        IrAProcess, ProcessAction, RuleEnforcement, WorkerCommand, WorkerHeartbeat,
+        WorkerOperations_, WorkerState, get_cpu_affinity_of_process, run_worker_logic,
+        set_cpu_affinity_of_process,
+    },
 };
 
+fn get_built_in_rule() -> Rule {
+    let mut built_in_rule = Rule::new_built_in(&CpuTopology::new_flat(12));
+    built_in_rule.preset = SelectionPreset::Custom;
+    built_in_rule.selections = CpuSelections::new_evens_selected(12);
+    built_in_rule
+}
+
 #[tokio::test]
 async fn running_worker_logic_when_only_spawners_exist() {
     struct TestOperations {
@@ -18,39 +57,48 @@ async fn running_worker_logic_when_only_spawners_exist() {
         simulations: Option<CpuSelections>,
     };
     impl WorkerOperations_ for TestOperations {
-        async fn sleep(&mut self) {
+        async fn sleep(&mut self, watched_ids: &HashSet<u32>) -> Option<WorkerCommand> {
             self.slept += 1;
+            None
         }
 
+        fn get_cooldown_period(&self) -> std::time::Duration {
+            std::time::Duration::from_secs(5)
+        }
+
+        fn set_is_event_driven(&mut self, is_event_driven: bool) {}
+
         async fn load_store(&mut self, system_info: &System) -> ResultBtAny<PersistentStore> {
             Ok(PersistentStore {
-                simulator: String::from(DEFAULT_IRACING_SIMULATOR),
-                selections: CpuSelections::new_evens_selected(12),
+                profile_id: Profile::DEFAULT_ID,
+                rules: vec![get_built_in_rule()],
+                loaded_rules: vec![],
+                settings: Settings::default(),
+                loaded_settings: Settings::default(),
             })
         }
 
-        fn get_processes_by_exact_name(
-            &mut self,
-            system_info: &System,
-            name: &str,
-        ) -> Vec<IrAProcess> {
-            match name {
-                DEFAULT_SIMULATOR_SPAWNERS => vec![IrAProcess { id: 7 }],
-                DEFAULT_IRACING_SIMULATOR => vec![],
-                _ => vec![],
-            }
+        async fn load_profiles(&mut self) -> ResultBtAny<(Vec<Profile>, Vec<ProfileTrigger>)> {
+            Ok((vec![], vec![]))
         }
 
-        async fn get_are_processes_synced(
+        async fn activate_profile(&mut self, profile_id: i64) -> ResultBtAny<()> {
+            Ok(())
+        }
+
+        fn get_processes_by_matcher(
             &mut self,
-            candidate_processes: &[IrAProcess],
-            cpu_selections: &CpuSelections,
-            system_info: &System,
-        ) -> ResultBtAny<bool> {
-            match candidate_processes[0].id {
-                7 => Ok(self.spawners.as_ref() == Some(cpu_selections)),
-                _ => panic!(),
-            }
+            process_scanner: &ProcessScanner,
+            matcher: &ProcessMatcher,
+        ) -> ResultBtAny<Vec<IrAProcess>> {
+            Ok(match matcher.conditions[0].value.as_str() {
+                DEFAULT_SIMULATOR_SPAWNERS => vec![IrAProcess {
+                    id: 7,
+                    start_time: 0,
+                }],
+                DEFAULT_IRACING_SIMULATOR => vec![],
+                _ => vec![],
+            })
         }
 
         async fn set_processes_affinity(
@@ -64,6 +112,33 @@ async fn running_worker_logic_when_only_spawners_exist() {
             };
             Ok(())
         }
+
+        async fn get_process_affinity(
+            &mut self,
+            candidate_process: &IrAProcess,
+            system_info: &System,
+        ) -> ResultBtAny<CpuSelections> {
+            Ok(self
+                .spawners
+                .clone()
+                .unwrap_or(CpuSelections::new_all_selected(12)))
+        }
+
+        fn get_is_process_running(&mut self, process: &IrAProcess, system_info: &System) -> bool {
+            true
+        }
+
+        async fn load_original_affinities(&mut self) -> ResultBtAny<Vec<OriginalAffinity>> {
+            Ok(vec![])
+        }
+
+        async fn save_original_affinities(
+            &mut self,
+            original_affinities: &[OriginalAffinity],
+            restored_keys: &[ProcessKey],
+        ) -> ResultBtAny<()> {
+            Ok(())
+        }
     }
 
     let mut worker_operations = TestOperations {
@@ -71,10 +146,16 @@ async fn running_worker_logic_when_only_spawners_exist() {
         spawners: None,
         simulations: None,
     };
-    let mut system_info = System::new();
+    let mut process_scanner = ProcessScanner::new(System::new());
     let (status_tx, mut status_rx) = watch::channel(None);
 
-    run_worker_logic(&mut worker_operations, &mut system_info, &status_tx).await;
+    run_worker_logic(
+        &mut worker_operations,
+        &mut WorkerState::default(),
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
     assert_eq!(worker_operations.slept, 1);
     assert_eq!(
         worker_operations.spawners,
@@ -88,7 +169,13 @@ async fn running_worker_logic_when_only_spawners_exist() {
         .clone();
     assert!(first_beat.is_some());
 
-    run_worker_logic(&mut worker_operations, &mut system_info, &status_tx).await;
+    run_worker_logic(
+        &mut worker_operations,
+        &mut WorkerState::default(),
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
     assert_eq!(worker_operations.slept, 2);
     assert_eq!(
         worker_operations.spawners,
@@ -105,44 +192,60 @@ async fn running_worker_logic_when_only_spawners_exist() {
 async fn running_worker_logic_when_both_processes_exist() {
     struct TestOperations {
         slept: usize,
+        enforcement_strategy: EnforcementStrategy,
+        spawner_reset: SpawnerReset,
         spawners: Option<CpuSelections>,
         simulations: Option<CpuSelections>,
     };
     impl WorkerOperations_ for TestOperations {
-        async fn sleep(&mut self) {
+        async fn sleep(&mut self, watched_ids: &HashSet<u32>) -> Option<WorkerCommand> {
             self.slept += 1;
+            None
+        }
+
+        fn get_cooldown_period(&self) -> std::time::Duration {
+            std::time::Duration::from_secs(5)
         }
 
+        fn set_is_event_driven(&mut self, is_event_driven: bool) {}
+
         async fn load_store(&mut self, system_info: &System) -> ResultBtAny<PersistentStore> {
+            let mut built_in_rule = get_built_in_rule();
+            built_in_rule.enforcement_strategy = self.enforcement_strategy;
+            built_in_rule.spawner_reset = self.spawner_reset.clone();
             Ok(PersistentStore {
-                simulator: String::from(DEFAULT_IRACING_SIMULATOR),
-                selections: CpuSelections::new_evens_selected(12),
+                profile_id: Profile::DEFAULT_ID,
+                rules: vec![built_in_rule],
+                loaded_rules: vec![],
+                settings: Settings::default(),
+                loaded_settings: Settings::default(),
             })
         }
 
-        fn get_processes_by_exact_name(
-            &mut self,
-            system_info: &System,
-            name: &str,
-        ) -> Vec<IrAProcess> {
-            match name {
-                DEFAULT_SIMULATOR_SPAWNERS => vec![IrAProcess { id: 7 }],
-                DEFAULT_IRACING_SIMULATOR => vec![IrAProcess { id: 13 }],
-                _ => vec![],
-            }
+        async fn load_profiles(&mut self) -> ResultBtAny<(Vec<Profile>, Vec<ProfileTrigger>)> {
+            Ok((vec![], vec![]))
         }
 
-        async fn get_are_processes_synced(
+        async fn activate_profile(&mut self, profile_id: i64) -> ResultBtAny<()> {
+            Ok(())
+        }
+
+        fn get_processes_by_matcher(
             &mut self,
-            candidate_processes: &[IrAProcess],
-            cpu_selections: &CpuSelections,
-            system_info: &System,
-        ) -> ResultBtAny<bool> {
-            match candidate_processes[0].id {
-                7 => Ok(self.spawners.as_ref() == Some(cpu_selections)),
-                13 => Ok(self.simulations.as_ref() == Some(cpu_selections)),
-                _ => panic!(),
-            }
+            process_scanner: &ProcessScanner,
+            matcher: &ProcessMatcher,
+        ) -> ResultBtAny<Vec<IrAProcess>> {
+            Ok(match matcher.conditions[0].value.as_str() {
+                DEFAULT_SIMULATOR_SPAWNERS => vec![IrAProcess {
+                    id: 7,
+                    start_time: 0,
+                }],
+                DEFAULT_IRACING_SIMULATOR => vec![IrAProcess {
+                    id: 13,
+                    start_time: 0,
+                }],
+                _ => vec![],
+            })
         }
 
         async fn set_processes_affinity(
@@ -157,39 +260,1804 @@ async fn running_worker_logic_when_both_processes_exist() {
             };
             Ok(())
         }
+
+        async fn get_process_affinity(
+            &mut self,
+            candidate_process: &IrAProcess,
+            system_info: &System,
+        ) -> ResultBtAny<CpuSelections> {
+            let cpu_selections = match candidate_process.id {
+                7 => &self.spawners,
+                13 => &self.simulations,
+                _ => panic!(),
+            };
+            Ok(cpu_selections
+                .clone()
+                .unwrap_or(CpuSelections::new_all_selected(12)))
+        }
+
+        fn get_is_process_running(&mut self, process: &IrAProcess, system_info: &System) -> bool {
+            true
+        }
+
+        async fn load_original_affinities(&mut self) -> ResultBtAny<Vec<OriginalAffinity>> {
+            Ok(vec![])
+        }
+
+        async fn save_original_affinities(
+            &mut self,
+            original_affinities: &[OriginalAffinity],
+            restored_keys: &[ProcessKey],
+        ) -> ResultBtAny<()> {
+            Ok(())
+        }
     }
 
     let mut worker_operations = TestOperations {
         slept: 0,
+        enforcement_strategy: EnforcementStrategy::InheritViaSpawner,
+        spawner_reset: SpawnerReset::All,
         spawners: None,
         simulations: None,
     };
-    let mut system_info = System::new();
+    let mut worker_state = WorkerState::default();
+    let mut process_scanner = ProcessScanner::new(System::new());
     let (status_tx, mut status_rx) = watch::channel(None);
 
-    run_worker_logic(&mut worker_operations, &mut system_info, &status_tx).await;
+    // The simulator was launched elsewhere, so inheriting can't fix it.
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
     assert_eq!(worker_operations.slept, 1);
-    assert_eq!(
-        worker_operations.spawners,
-        Some(CpuSelections::new_all_selected(12))
-    );
+    assert_eq!(worker_operations.spawners, None);
     assert_eq!(worker_operations.simulations, None);
     let first_beat = status_rx
         .wait_for(|status| status.is_some())
         .await
         .unwrap()
-        .clone();
-    assert!(first_beat.is_some());
+        .clone()
+        .unwrap();
+    assert_eq!(first_beat.get_is_synced(), &Some(false));
+    assert!(first_beat.get_enforcements().is_empty());
+    let process_reports = &first_beat.get_rule_reports()[0].process_reports;
+    assert_eq!(process_reports.len(), 1);
+    assert_eq!(process_reports[0].process_id, 13);
+    assert_eq!(process_reports[0].action, ProcessAction::Unsynced);
+    assert_eq!(
+        process_reports[0].observed_selections,
+        Some(CpuSelections::new_all_selected(12))
+    );
 
-    run_worker_logic(&mut worker_operations, &mut system_info, &status_tx).await;
+    worker_operations.enforcement_strategy = EnforcementStrategy::Both;
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
     assert_eq!(worker_operations.slept, 2);
     assert_eq!(
         worker_operations.spawners,
         Some(CpuSelections::new_all_selected(12))
     );
-    assert_eq!(worker_operations.simulations, None);
-    let second_beat = status_rx.wait_for(|status| status.is_some()).await.unwrap();
-    assert!(second_beat.is_some());
+    assert_eq!(
+        worker_operations.simulations,
+        Some(CpuSelections::new_evens_selected(12))
+    );
+    let second_beat = status_rx
+        .wait_for(|status| status.is_some())
+        .await
+        .unwrap()
+        .clone()
+        .unwrap();
+    assert_eq!(second_beat.get_is_synced(), &Some(true));
+    assert_eq!(
+        second_beat.get_enforcements(),
+        &[RuleEnforcement {
+            rule_name: String::from("iRacing"),
+            strategy: EnforcementStrategy::Direct,
+        }]
+    );
 
-    assert!(first_beat != *second_beat);
+    // Still credited to the direct apply once already synced.
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(worker_operations.slept, 3);
+    let third_beat = status_rx
+        .wait_for(|status| status.is_some())
+        .await
+        .unwrap()
+        .clone()
+        .unwrap();
+    assert_eq!(
+        third_beat.get_enforcements(),
+        second_beat.get_enforcements()
+    );
+
+    worker_operations.spawner_reset = SpawnerReset::Complement;
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    let odds = CpuSelections::new_preselected((1..12).step_by(2).collect(), 12);
+    assert_eq!(worker_operations.spawners, Some(odds));
+
+    assert!(first_beat != second_beat);
+}
+
+#[test]
+fn resolving_spawner_resets() {
+    let rule_selections = CpuSelections::new_evens_selected(8);
+    let original = CpuSelections::new_preselected(HashSet::from([6, 7]), 8);
+    let all = CpuSelections::new_all_selected(8);
+
+    assert_eq!(
+        SpawnerReset::Original.resolve(&rule_selections, Some(&original)),
+        original
+    );
+    assert_eq!(SpawnerReset::Original.resolve(&rule_selections, None), all);
+    assert_eq!(SpawnerReset::All.resolve(&rule_selections, None), all);
+    assert_eq!(
+        SpawnerReset::Complement.resolve(&rule_selections, None),
+        CpuSelections::new_preselected(HashSet::from([1, 3, 5, 7]), 8)
+    );
+    assert_eq!(SpawnerReset::Complement.resolve(&all, None), all);
+    assert_eq!(
+        SpawnerReset::Custom {
+            cpus: [4, 5, 64].into()
+        }
+        .resolve(&rule_selections, None),
+        CpuSelections::new_preselected(HashSet::from([4, 5]), 8)
+    );
+}
+
+#[test]
+fn converting_masks_past_64_cpus() {
+    let cpu_selections = HashSet::from([0, 63, 64, 100, 127]);
+    let cpu_mask = hashset_to_mask(&cpu_selections);
+    assert!(cpu_mask.get_is_set(127));
+    assert!(!cpu_mask.get_is_set(128));
+    assert_eq!(mask_to_hashset(&cpu_mask), cpu_selections);
+
+    let second_group = cpu_mask.get_range(64, 64);
+    assert_eq!(second_group, 1 | (1 << 36) | (1 << 63));
+    let mut cpu_mask_ = CpuMask::new();
+    cpu_mask_.set_range(0, cpu_mask.get_range(0, 64));
+    cpu_mask_.set_range(64, second_group);
+    assert_eq!(cpu_mask_, cpu_mask);
+
+    let cpu_selections = CpuSelections::new_all_selected(128);
+    assert_eq!(cpu_selections.to_mask().iter().count(), 128);
+}
+
+fn get_sysfs_fixture(name: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures/sysfs")
+        .join(name)
+        .join("devices/system/cpu")
+}
+
+#[test]
+fn reading_topology_of_dual_ccd_vcache_fixture() {
+    let topology = CpuTopology::from_sysfs(&get_sysfs_fixture("dual_ccd_vcache")).unwrap();
+    assert_eq!(topology.get_cpu_count(), 8);
+    assert_eq!(
+        topology.get_physical_cores(),
+        vec![vec![0, 4], vec![1, 5], vec![2, 6], vec![3, 7]]
+    );
+    assert_eq!(topology.get_siblings(2), vec![2, 6]);
+    assert!(!topology.get_is_hybrid());
+    assert_eq!(topology.get_numa_nodes(), vec![0]);
+
+    let cache_groups = topology.get_cache_groups();
+    assert_eq!(cache_groups.len(), 2);
+    assert_eq!(cache_groups[0].cpus, vec![0, 1, 4, 5]);
+    assert_eq!(cache_groups[0].size, Some(96 << 20));
+    assert_eq!(cache_groups[1].cpus, vec![2, 3, 6, 7]);
+    assert_eq!(cache_groups[1].size, Some(32 << 20));
+}
+
+#[test]
+fn reading_topology_of_hybrid_fixture() {
+    let topology = CpuTopology::from_sysfs(&get_sysfs_fixture("hybrid")).unwrap();
+    assert_eq!(topology.get_cpu_count(), 8);
+    assert_eq!(
+        topology.get_physical_cores(),
+        vec![vec![0, 1], vec![2, 3], vec![4], vec![5], vec![6], vec![7]]
+    );
+    assert!(topology.get_is_hybrid());
+    let efficiency_classes: Vec<_> = topology
+        .get_cpus()
+        .iter()
+        .map(|cpu| cpu.efficiency_class)
+        .collect();
+    assert_eq!(efficiency_classes, vec![1, 1, 1, 1, 0, 0, 0, 0]);
+    assert_eq!(topology.get_cache_groups().len(), 1);
+}
+
+#[test]
+fn resolving_presets_against_fixtures() {
+    let topology = CpuTopology::from_sysfs(&get_sysfs_fixture("dual_ccd_vcache")).unwrap();
+    let resolve = |preset: SelectionPreset| {
+        let mut cpu_selections: Vec<_> = preset.resolve(&topology).unwrap().into_iter().collect();
+        cpu_selections.sort();
+        cpu_selections
+    };
+    assert_eq!(
+        resolve(SelectionPreset::OnePerPhysicalCore),
+        vec![0, 1, 2, 3]
+    );
+    assert_eq!(
+        resolve(SelectionPreset::AllButFirstCore),
+        vec![1, 2, 3, 5, 6, 7]
+    );
+    assert_eq!(resolve(SelectionPreset::LargestCache), vec![0, 1, 4, 5]);
+    assert_eq!(
+        resolve(SelectionPreset::LastPhysicalCores { count: 1 }),
+        vec![3, 7]
+    );
+    assert_eq!(SelectionPreset::Custom.resolve(&topology), None);
+
+    let topology = CpuTopology::from_sysfs(&get_sysfs_fixture("hybrid")).unwrap();
+    let mut performance_cores: Vec<_> = SelectionPreset::PerformanceCores
+        .resolve(&topology)
+        .unwrap()
+        .into_iter()
+        .collect();
+    performance_cores.sort();
+    assert_eq!(performance_cores, vec![0, 1, 2, 3]);
+}
+
+#[tokio::test]
+async fn running_worker_logic_with_overlapping_rules() {
+    struct TestOperations {
+        applied: Vec<(u32, CpuSelections)>,
+    }
+    impl WorkerOperations_ for TestOperations {
+        async fn sleep(&mut self, watched_ids: &HashSet<u32>) -> Option<WorkerCommand> {
+            None
+        }
+
+        fn get_cooldown_period(&self) -> std::time::Duration {
+            std::time::Duration::from_secs(5)
+        }
+
+        fn set_is_event_driven(&mut self, is_event_driven: bool) {}
+
+        async fn load_store(&mut self, system_info: &System) -> ResultBtAny<PersistentStore> {
+            let topology = CpuTopology::new_flat(12);
+            let mut first_rule = Rule::new_custom(1, &topology);
+            first_rule.matcher = ProcessMatcher::new_exact_name("obs64.exe");
+            first_rule.selections = CpuSelections::new_evens_selected(12);
+            let mut second_rule = Rule::new_custom(2, &topology);
+            second_rule.matcher = ProcessMatcher::new_exact_name("obs64.exe");
+            let mut disabled_rule = Rule::new_custom(3, &topology);
+            disabled_rule.matcher = ProcessMatcher::new_exact_name("SimHubWPF.exe");
+            disabled_rule.is_enabled = false;
+            Ok(PersistentStore {
+                profile_id: Profile::DEFAULT_ID,
+                rules: vec![first_rule, second_rule, disabled_rule],
+                loaded_rules: vec![],
+                settings: Settings::default(),
+                loaded_settings: Settings::default(),
+            })
+        }
+
+        async fn load_profiles(&mut self) -> ResultBtAny<(Vec<Profile>, Vec<ProfileTrigger>)> {
+            Ok((vec![], vec![]))
+        }
+
+        async fn activate_profile(&mut self, profile_id: i64) -> ResultBtAny<()> {
+            Ok(())
+        }
+
+        fn get_processes_by_matcher(
+            &mut self,
+            process_scanner: &ProcessScanner,
+            matcher: &ProcessMatcher,
+        ) -> ResultBtAny<Vec<IrAProcess>> {
+            Ok(match matcher.conditions[0].value.as_str() {
+                "obs64.exe" => vec![IrAProcess {
+                    id: 21,
+                    start_time: 0,
+                }],
+                "SimHubWPF.exe" => vec![IrAProcess {
+                    id: 34,
+                    start_time: 0,
+                }],
+                _ => vec![],
+            })
+        }
+
+        async fn set_processes_affinity(
+            &mut self,
+            candidate_processes: &[IrAProcess],
+            cpu_selections: &CpuSelections,
+        ) -> ResultBtAny<()> {
+            for candidate_process in candidate_processes {
+                self.applied
+                    .push((candidate_process.id, cpu_selections.clone()));
+            }
+            Ok(())
+        }
+
+        async fn get_process_affinity(
+            &mut self,
+            candidate_process: &IrAProcess,
+            system_info: &System,
+        ) -> ResultBtAny<CpuSelections> {
+            Ok(CpuSelections::new_all_selected(12))
+        }
+
+        fn get_is_process_running(&mut self, process: &IrAProcess, system_info: &System) -> bool {
+            true
+        }
+
+        async fn load_original_affinities(&mut self) -> ResultBtAny<Vec<OriginalAffinity>> {
+            Ok(vec![])
+        }
+
+        async fn save_original_affinities(
+            &mut self,
+            original_affinities: &[OriginalAffinity],
+            restored_keys: &[ProcessKey],
+        ) -> ResultBtAny<()> {
+            Ok(())
+        }
+    }
+
+    let mut worker_operations = TestOperations { applied: vec![] };
+    let mut process_scanner = ProcessScanner::new(System::new());
+    let (status_tx, status_rx) = watch::channel(None);
+
+    run_worker_logic(
+        &mut worker_operations,
+        &mut WorkerState::default(),
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(
+        worker_operations.applied,
+        vec![(21, CpuSelections::new_evens_selected(12))]
+    );
+    let heartbeat = status_rx.borrow().clone().unwrap();
+    assert_eq!(heartbeat.get_is_synced(), &Some(true));
+}
+
+#[tokio::test]
+async fn restoring_original_affinities() {
+    struct TestOperations {
+        worker_command: Option<WorkerCommand>,
+        is_rule_enabled: bool,
+        affinities: HashMap<u32, CpuSelections>,
+        persisted: Vec<OriginalAffinity>,
+        watched_ids: HashSet<u32>,
+    }
+    impl WorkerOperations_ for TestOperations {
+        async fn sleep(&mut self, watched_ids: &HashSet<u32>) -> Option<WorkerCommand> {
+            self.watched_ids = watched_ids.clone();
+            self.worker_command.take()
+        }
+
+        fn get_cooldown_period(&self) -> std::time::Duration {
+            std::time::Duration::from_secs(5)
+        }
+
+        fn set_is_event_driven(&mut self, is_event_driven: bool) {}
+
+        async fn load_store(&mut self, system_info: &System) -> ResultBtAny<PersistentStore> {
+            let mut rule = Rule::new_custom(1, &CpuTopology::new_flat(12));
+            rule.matcher = ProcessMatcher::new_exact_name("obs64.exe");
+            rule.selections = CpuSelections::new_evens_selected(12);
+            rule.is_enabled = self.is_rule_enabled;
+            Ok(PersistentStore {
+                profile_id: Profile::DEFAULT_ID,
+                rules: vec![rule],
+                loaded_rules: vec![],
+                settings: Settings::default(),
+                loaded_settings: Settings::default(),
+            })
+        }
+
+        async fn load_profiles(&mut self) -> ResultBtAny<(Vec<Profile>, Vec<ProfileTrigger>)> {
+            Ok((vec![], vec![]))
+        }
+
+        async fn activate_profile(&mut self, profile_id: i64) -> ResultBtAny<()> {
+            Ok(())
+        }
+
+        fn get_processes_by_matcher(
+            &mut self,
+            process_scanner: &ProcessScanner,
+            matcher: &ProcessMatcher,
+        ) -> ResultBtAny<Vec<IrAProcess>> {
+            Ok(vec![IrAProcess {
+                id: 21,
+                start_time: 0,
+            }])
+        }
+
+        async fn set_processes_affinity(
+            &mut self,
+            candidate_processes: &[IrAProcess],
+            cpu_selections: &CpuSelections,
+        ) -> ResultBtAny<()> {
+            for candidate_process in candidate_processes {
+                self.affinities
+                    .insert(candidate_process.id, cpu_selections.clone());
+            }
+            Ok(())
+        }
+
+        async fn get_process_affinity(
+            &mut self,
+            candidate_process: &IrAProcess,
+            system_info: &System,
+        ) -> ResultBtAny<CpuSelections> {
+            Ok(self.affinities[&candidate_process.id].clone())
+        }
+
+        fn get_is_process_running(&mut self, process: &IrAProcess, system_info: &System) -> bool {
+            self.affinities.contains_key(&process.id)
+        }
+
+        async fn load_original_affinities(&mut self) -> ResultBtAny<Vec<OriginalAffinity>> {
+            Ok(self.persisted.clone())
+        }
+
+        async fn save_original_affinities(
+            &mut self,
+            original_affinities: &[OriginalAffinity],
+            restored_keys: &[ProcessKey],
+        ) -> ResultBtAny<()> {
+            self.persisted.retain(|persisted| {
+                let process_key = (persisted.process_id, persisted.start_time);
+                !restored_keys.contains(&process_key)
+                    && !original_affinities.iter().any(|original_affinity| {
+                        (original_affinity.process_id, original_affinity.start_time) == process_key
+                    })
+            });
+            self.persisted.extend_from_slice(original_affinities);
+            Ok(())
+        }
+    }
+
+    let all = CpuSelections::new_all_selected(12);
+    let evens = CpuSelections::new_evens_selected(12);
+    let crashed_original = CpuSelections::new_preselected(HashSet::from([0, 1]), 12);
+    // Left behind by a crash, one still running but no longer matched.
+    let mut worker_operations = TestOperations {
+        worker_command: None,
+        is_rule_enabled: true,
+        affinities: HashMap::from([(21, all.clone()), (34, evens.clone())]),
+        persisted: vec![
+            OriginalAffinity {
+                process_id: 34,
+                start_time: 0,
+                rule_id: 2,
+                selections: crashed_original.clone(),
+            },
+            OriginalAffinity {
+                process_id: 55,
+                start_time: 0,
+                rule_id: 2,
+                selections: crashed_original.clone(),
+            },
+        ],
+        watched_ids: HashSet::new(),
+    };
+    let mut worker_state = WorkerState::default();
+    let mut process_scanner = ProcessScanner::new(System::new());
+    let (status_tx, status_rx) = watch::channel(None);
+
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(worker_operations.affinities[&21], evens);
+    assert_eq!(worker_operations.affinities[&34], crashed_original);
+    assert!(!worker_operations.affinities.contains_key(&55));
+    assert_eq!(worker_operations.persisted.len(), 1);
+    assert_eq!(worker_operations.persisted[0].selections, all);
+
+    worker_operations.is_rule_enabled = false;
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    // Changed by the run before, so its exit would wake the worker.
+    assert_eq!(worker_operations.watched_ids, HashSet::from([21]));
+    assert_eq!(worker_operations.affinities[&21], all);
+    assert!(worker_operations.persisted.is_empty());
+
+    worker_operations.is_rule_enabled = true;
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(worker_operations.affinities[&21], evens);
+
+    worker_operations.worker_command = Some(WorkerCommand::RestoreOriginals);
+    for _ in 0..2 {
+        run_worker_logic(
+            &mut worker_operations,
+            &mut worker_state,
+            &mut process_scanner,
+            &status_tx,
+        )
+        .await;
+        assert_eq!(worker_operations.affinities[&21], all);
+        let heartbeat = status_rx.borrow().clone().unwrap();
+        assert!(heartbeat.get_is_paused());
+    }
+
+    worker_operations.worker_command = Some(WorkerCommand::Resume);
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(worker_operations.affinities[&21], evens);
+
+    worker_operations.worker_command = Some(WorkerCommand::Shutdown);
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(worker_operations.affinities[&21], all);
+    assert!(worker_operations.persisted.is_empty());
+    assert!(worker_state.get_is_shut_down());
+}
+
+#[tokio::test]
+async fn backing_off_failing_processes() {
+    struct TestOperations {
+        worker_command: Option<WorkerCommand>,
+        affinities: HashMap<u32, CpuSelections>,
+        denied_process_id: Option<u32>,
+        denied_count: usize,
+    }
+    impl WorkerOperations_ for TestOperations {
+        async fn sleep(&mut self, watched_ids: &HashSet<u32>) -> Option<WorkerCommand> {
+            self.worker_command.take()
+        }
+
+        fn get_cooldown_period(&self) -> std::time::Duration {
+            std::time::Duration::from_secs(5)
+        }
+
+        fn set_is_event_driven(&mut self, is_event_driven: bool) {}
+
+        async fn load_store(&mut self, system_info: &System) -> ResultBtAny<PersistentStore> {
+            let mut rule = Rule::new_custom(1, &CpuTopology::new_flat(12));
+            rule.matcher = ProcessMatcher::new_exact_name("obs64.exe");
+            rule.selections = CpuSelections::new_evens_selected(12);
+            Ok(PersistentStore {
+                profile_id: Profile::DEFAULT_ID,
+                rules: vec![rule],
+                loaded_rules: vec![],
+                settings: Settings::default(),
+                loaded_settings: Settings::default(),
+            })
+        }
+
+        async fn load_profiles(&mut self) -> ResultBtAny<(Vec<Profile>, Vec<ProfileTrigger>)> {
+            Ok((vec![], vec![]))
+        }
+
+        async fn activate_profile(&mut self, profile_id: i64) -> ResultBtAny<()> {
+            Ok(())
+        }
+
+        fn get_processes_by_matcher(
+            &mut self,
+            process_scanner: &ProcessScanner,
+            matcher: &ProcessMatcher,
+        ) -> ResultBtAny<Vec<IrAProcess>> {
+            Ok(vec![
+                IrAProcess {
+                    id: 21,
+                    start_time: 0,
+                },
+                IrAProcess {
+                    id: 34,
+                    start_time: 0,
+                },
+            ])
+        }
+
+        async fn set_processes_affinity(
+            &mut self,
+            candidate_processes: &[IrAProcess],
+            cpu_selections: &CpuSelections,
+        ) -> ResultBtAny<()> {
+            for candidate_process in candidate_processes {
+                if Some(candidate_process.id) == self.denied_process_id {
+                    self.denied_count += 1;
+                    Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))?;
+                }
+                self.affinities
+                    .insert(candidate_process.id, cpu_selections.clone());
+            }
+            Ok(())
+        }
+
+        async fn get_process_affinity(
+            &mut self,
+            candidate_process: &IrAProcess,
+            system_info: &System,
+        ) -> ResultBtAny<CpuSelections> {
+            Ok(self.affinities[&candidate_process.id].clone())
+        }
+
+        fn get_is_process_running(&mut self, process: &IrAProcess, system_info: &System) -> bool {
+            self.affinities.contains_key(&process.id)
+        }
+
+        async fn load_original_affinities(&mut self) -> ResultBtAny<Vec<OriginalAffinity>> {
+            Ok(vec![])
+        }
+
+        async fn save_original_affinities(
+            &mut self,
+            original_affinities: &[OriginalAffinity],
+            restored_keys: &[ProcessKey],
+        ) -> ResultBtAny<()> {
+            Ok(())
+        }
+    }
+
+    let all = CpuSelections::new_all_selected(12);
+    let evens = CpuSelections::new_evens_selected(12);
+    let mut worker_operations = TestOperations {
+        worker_command: None,
+        affinities: HashMap::from([(21, all.clone()), (34, all.clone())]),
+        denied_process_id: Some(34),
+        denied_count: 0,
+    };
+    let mut worker_state = WorkerState::default();
+    let mut process_scanner = ProcessScanner::new(System::new());
+    let (status_tx, status_rx) = watch::channel(None);
+
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(worker_operations.affinities[&21], evens);
+    assert_eq!(worker_operations.denied_count, 1);
+    let heartbeat = status_rx.borrow().clone().unwrap();
+    assert_eq!(*heartbeat.get_is_synced(), Some(false));
+    assert!(heartbeat.get_error().is_some());
+    let process_reports = &heartbeat.get_rule_reports()[0].process_reports;
+    assert_eq!(process_reports[0].action, ProcessAction::Applied);
+    assert_eq!(process_reports[0].observed_selections, Some(all.clone()));
+    assert_eq!(process_reports[1].action, ProcessAction::Failed);
+    assert_eq!(
+        process_reports[1].failure.as_ref().unwrap().kind,
+        FailureKind::PermissionDenied
+    );
+
+    // Not tried again until its delay is up, nor is the error repeated.
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(worker_operations.denied_count, 1);
+    let heartbeat = status_rx.borrow().clone().unwrap();
+    assert_eq!(*heartbeat.get_is_synced(), Some(false));
+    assert_eq!(*heartbeat.get_error(), None);
+    let process_reports = &heartbeat.get_rule_reports()[0].process_reports;
+    assert_eq!(process_reports[0].action, ProcessAction::Skipped);
+    assert_eq!(process_reports[1].action, ProcessAction::BackedOff);
+    let failure = heartbeat
+        .get_tracked_processes()
+        .iter()
+        .find(|tracked_process| tracked_process.process_id == 34)
+        .and_then(|tracked_process| tracked_process.failure.clone())
+        .unwrap();
+    assert_eq!(failure.kind, FailureKind::PermissionDenied);
+    assert_eq!(failure.count, 1);
+    assert!(!failure.get_is_quarantined());
+
+    worker_operations.denied_process_id = None;
+    worker_operations.worker_command = Some(WorkerCommand::RetryProcess((34, 0)));
+    run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await;
+    assert_eq!(worker_operations.affinities[&34], evens);
+    let heartbeat = status_rx.borrow().clone().unwrap();
+    assert_eq!(*heartbeat.get_is_synced(), Some(true));
+    assert!(
+        heartbeat
+            .get_tracked_processes()
+            .iter()
+            .all(|tracked_process| tracked_process.failure.is_none())
+    );
+
+    let mut tracker = ProcessTracker::default();
+    tracker.track((7, 100), "iRacing");
+    for _ in 0..5 {
+        tracker.record_failed((7, 100), FailureKind::PermissionDenied, String::new());
+    }
+    let failure = tracker.get_tracked_processes()[0].failure.clone().unwrap();
+    assert_eq!(failure.count, 5);
+    assert!(failure.get_is_quarantined());
+    assert!(tracker.get_is_backing_off((7, 100)));
+    tracker.retry((7, 100));
+    assert!(!tracker.get_is_backing_off((7, 100)));
+}
+
+#[test]
+fn forwarding_log_records() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let (log_sender, mut log_receiver) = tokio::sync::broadcast::channel(8);
+    let subscriber = tracing_subscriber::registry().with(BroadcastLayer::new(log_sender));
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info!(target: "ir_affinity::worker", process_id = 21, "Set CPU affinity.");
+        tracing::error!(target: "sqlx::query", "Couldn't save.");
+    });
+
+    let log_record = log_receiver.try_recv().unwrap();
+    assert_eq!(log_record.level, tracing::Level::INFO);
+    assert_eq!(log_record.target, "ir_affinity::worker");
+    assert_eq!(log_record.message, "Set CPU affinity. process_id=21");
+    assert!(log_record.get_is_match(tracing::Level::INFO, "ir_affinity", "cpu"));
+    assert!(!log_record.get_is_match(tracing::Level::WARN, "ir_affinity", ""));
+    assert!(!log_record.get_is_match(tracing::Level::INFO, "sqlx", ""));
+    assert!(!log_record.get_is_match(tracing::Level::INFO, "", "restored"));
+
+    let log_record = log_receiver.try_recv().unwrap();
+    assert!(log_record.get_is_match(tracing::Level::WARN, "sqlx", "save"));
+}
+
+#[test]
+fn parsing_process_events() {
+    let get_datagram = |what: u32, data: [u32; 4]| {
+        let mut datagram = vec![0; 36];
+        datagram.extend(what.to_ne_bytes());
+        datagram.extend([0; 12]);
+        for value in data {
+            datagram.extend(value.to_ne_bytes());
+        }
+        datagram
+    };
+
+    assert_eq!(
+        parse_process_event(&get_datagram(0x1, [5, 5, 8, 8])),
+        Some(ProcessEvent::Fork {
+            parent_id: 5,
+            process_id: 8
+        })
+    );
+    assert_eq!(parse_process_event(&get_datagram(0x1, [5, 5, 9, 8])), None);
+    assert_eq!(
+        parse_process_event(&get_datagram(0x2, [8, 8, 0, 0])),
+        Some(ProcessEvent::Exec { process_id: 8 })
+    );
+    assert_eq!(
+        parse_process_event(&get_datagram(0x80000000, [8, 8, 0, 9])),
+        Some(ProcessEvent::Exit { process_id: 8 })
+    );
+    assert_eq!(parse_process_event(&get_datagram(0x4, [8, 8, 0, 0])), None);
+    assert_eq!(parse_process_event(&[0; 40]), None);
+}
+
+#[test]
+fn tracking_processes_across_pid_reuse() {
+    let evens = CpuSelections::new_evens_selected(12);
+    let all = CpuSelections::new_all_selected(12);
+    let mut tracker = ProcessTracker::default();
+
+    tracker.track((7, 100), "iRacing");
+    assert!(!tracker.get_is_verified((7, 100), &evens));
+    tracker.record_applied((7, 100), &evens);
+    assert!(tracker.get_is_verified((7, 100), &evens));
+    assert!(!tracker.get_is_verified((7, 100), &all));
+    tracker.record_checked((7, 100), &evens, false);
+    assert!(!tracker.get_is_verified((7, 100), &evens));
+
+    // The first process exits and its PID is handed to another.
+    tracker.retain(
+        |process_key| process_key == (7, 200),
+        |process_key| process_key == (7, 200),
+    );
+    tracker.track((7, 200), "iRacing");
+    let tracked_processes = tracker.get_tracked_processes();
+    assert_eq!(tracked_processes.len(), 2);
+    assert_eq!(tracked_processes[0].start_time, 200);
+    assert_eq!(tracked_processes[0].drift_count, 0);
+    assert_eq!(tracked_processes[0].exited_at, None);
+    assert_eq!(tracked_processes[1].start_time, 100);
+    assert_eq!(tracked_processes[1].drift_count, 1);
+    assert!(tracked_processes[1].applied_at.is_some());
+    assert!(tracked_processes[1].exited_at.is_some());
+}
+
+#[test]
+fn matching_globs() {
+    assert!(get_is_glob_match(
+        "iRacingSim64*.exe",
+        "iRacingSim64DX11.exe"
+    ));
+    assert!(get_is_glob_match("*", ""));
+    assert!(get_is_glob_match("?bs64.exe", "obs64.exe"));
+    assert!(get_is_glob_match("*a*b*c", "xxaxxbxxbxxc"));
+    assert!(!get_is_glob_match("iRacingSim64*.exe", "iRacingUI.exe"));
+    assert!(!get_is_glob_match("?", ""));
+    assert!(!get_is_glob_match("obs64", "obs64.exe"));
+}
+
+#[test]
+fn matching_own_process() {
+    let own_process_id = sysinfo::get_current_pid().unwrap();
+    let mut process_scanner = ProcessScanner::new(System::new());
+    let scan_stats = process_scanner.scan();
+    assert!(scan_stats.is_full);
+    assert_eq!(scan_stats.refreshed_count, scan_stats.process_count);
+    let system_info = process_scanner.get_system();
+    let own_process = system_info.process(own_process_id).unwrap();
+    let own_name = own_process.name().to_string_lossy().to_string();
+    let own_exe = own_process.exe().unwrap().to_string_lossy().to_string();
+    let own_command = own_process.cmd()[0].to_string_lossy().to_string();
+    let own_parent = system_info
+        .process(own_process.parent().unwrap())
+        .unwrap()
+        .name()
+        .to_string_lossy()
+        .to_string();
+
+    let get_is_own_process_matched = |conditions: Vec<MatchCondition>| {
+        ProcessMatcher {
+            conditions,
+            ..ProcessMatcher::default()
+        }
+        .get_matching_processes(&process_scanner)
+        .unwrap()
+        .iter()
+        .any(|process| process.pid() == own_process_id)
+    };
+    assert!(get_is_own_process_matched(vec![
+        MatchCondition::new(
+            MatchConditionKind::NameCaseInsensitive,
+            &own_name.to_uppercase()
+        ),
+        MatchCondition::new(
+            MatchConditionKind::NameRegex,
+            &format!("^{}$", regex::escape(&own_name))
+        ),
+        MatchCondition::new(MatchConditionKind::ExePathPrefix, &own_exe),
+        MatchCondition::new(MatchConditionKind::CommandLineContains, &own_command),
+        MatchCondition::new(MatchConditionKind::ParentName, &own_parent),
+    ]));
+    assert!(!get_is_own_process_matched(vec![
+        MatchCondition::new(MatchConditionKind::ExactName, &own_name),
+        MatchCondition::new(
+            MatchConditionKind::CommandLineContains,
+            "not_on_the_command_line"
+        ),
+    ]));
+    assert!(!get_is_own_process_matched(vec![]));
+    assert!(
+        ProcessMatcher {
+            conditions: vec![MatchCondition::new(MatchConditionKind::NameRegex, "(")],
+            ..ProcessMatcher::default()
+        }
+        .get_matching_processes(&process_scanner)
+        .is_err()
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn scanning_processes_incrementally() {
+    let mut process_scanner = ProcessScanner::new(System::new());
+    assert!(process_scanner.scan().is_full);
+
+    let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+    let child_id = sysinfo::Pid::from_u32(child.id());
+    let get_is_child_scanned = |process_scanner: &ProcessScanner| {
+        process_scanner
+            .get_scanned_processes()
+            .any(|(process, scanned_process)| {
+                process.pid() == child_id && scanned_process.name.name == "sleep"
+            })
+    };
+    let scan_stats = process_scanner.scan();
+    assert!(!scan_stats.is_full);
+    assert!(scan_stats.refreshed_count < scan_stats.process_count);
+    assert!(get_is_child_scanned(&process_scanner));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    process_scanner.scan();
+    assert!(!get_is_child_scanned(&process_scanner));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn rescanning_reused_process_ids() {
+    let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+    let child_id = sysinfo::Pid::from_u32(child.id());
+    let mut process_scanner = ProcessScanner::new(System::new());
+    assert!(process_scanner.scan().is_full);
+    let start_time = process_scanner.scanned_processes[&child_id].start_time;
+
+    // As left by an earlier process with the same PID.
+    let scanned_process = process_scanner
+        .scanned_processes
+        .get_mut(&child_id)
+        .unwrap();
+    scanned_process.start_time = start_time - 1;
+    scanned_process.name.name = String::from("previous");
+    let scan_stats = process_scanner.scan();
+    assert!(!scan_stats.is_full);
+    let scanned_process = &process_scanner.scanned_processes[&child_id];
+    assert_eq!(scanned_process.start_time, start_time);
+    assert_eq!(scanned_process.name.name, "sleep");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn resolving_windows_image_names() {
+    let get_image_name = |process_name: &str, exe: &str, command_line: &[&str]| {
+        let command_line = command_line.iter().map(OsString::from).collect::<Vec<_>>();
+        get_windows_image_name(process_name, Some(Path::new(exe)), &command_line)
+    };
+    let get_full_name = |name: &str| ProcessName {
+        name: name.to_string(),
+        is_truncated: false,
+    };
+
+    assert_eq!(
+        get_image_name(
+            "iRacingSim64DX1",
+            "/home/racer/.steam/steam/steamapps/common/Proton 9.0/files/bin/wine64-preloader",
+            &["C:\\Program Files (x86)\\iRacing\\iRacingSim64DX11.exe"],
+        ),
+        Some(get_full_name("iRacingSim64DX11.exe"))
+    );
+    assert_eq!(
+        get_image_name(
+            "wine64-preloade",
+            "/usr/bin/wine64-preloader",
+            &[
+                "/usr/bin/wine64-preloader",
+                "/usr/bin/wine64",
+                "Z:\\games\\iRacing\\ui\\iRacingUI.exe",
+                "--no-sandbox",
+            ],
+        ),
+        Some(get_full_name("iRacingUI.exe"))
+    );
+    assert_eq!(
+        get_image_name(
+            "iRacingSim64DX1",
+            "/usr/bin/wine64-preloader",
+            &["/usr/bin/wine64-preloader"],
+        ),
+        Some(ProcessName {
+            name: String::from("iRacingSim64DX1"),
+            is_truncated: true,
+        })
+    );
+    assert_eq!(
+        get_image_name(
+            "reaper",
+            "/home/racer/.steam/steam/ubuntu12_32/reaper",
+            &[
+                "reaper",
+                "SteamLaunch",
+                "AppId=266410",
+                "--",
+                "iRacingLauncher64.exe"
+            ],
+        ),
+        None
+    );
+    assert_eq!(
+        get_image_name("bash", "/usr/bin/bash", &["bash", "-c", "echo"]),
+        None
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn reading_environment_of_own_process() {
+    let path = crate::wrappers::get_environment_variable(std::process::id(), "PATH").unwrap();
+    assert_eq!(path, std::env::var("PATH").ok());
+    let missing =
+        crate::wrappers::get_environment_variable(std::process::id(), "IR_AFFINITY_UNSET").unwrap();
+    assert_eq!(missing, None);
+}
+
+#[tokio::test]
+#[cfg(target_os = "linux")]
+async fn getting_and_setting_own_affinity() {
+    let process = IrAProcess {
+        id: std::process::id(),
+        start_time: 0,
+    };
+    let cpu_affinity = get_cpu_affinity_of_process(&process).unwrap();
+    let system_info = System::new_all();
+    let cpu_selections =
+        CpuSelections::new_preselected(mask_to_hashset(&cpu_affinity), system_info.cpus().len());
+    set_cpu_affinity_of_process(&process, &cpu_selections)
+        .await
+        .unwrap();
+    assert_eq!(get_cpu_affinity_of_process(&process).unwrap(), cpu_affinity);
+}
+
+/// One connection, as each would get its own database otherwise.
+async fn get_memory_pool() -> sqlx::SqlitePool {
+    let sqlite_pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    PersistentStore::create_ddl(&sqlite_pool).await.unwrap();
+    sqlite_pool
+}
+
+#[tokio::test]
+async fn persisting_original_affinities() {
+    let sqlite_pool = get_memory_pool().await;
+    let get_original_affinity = |process_id| OriginalAffinity {
+        process_id,
+        start_time: 7,
+        rule_id: Rule::BUILT_IN_ID,
+        selections: CpuSelections::new_evens_selected(12),
+    };
+    let load = || PersistentStore::load_original_affinities(12, &sqlite_pool);
+
+    PersistentStore::save_original_affinities(&[get_original_affinity(1)], &[], &sqlite_pool)
+        .await
+        .unwrap();
+    // Saved by another process using the same store.
+    PersistentStore::save_original_affinities(&[get_original_affinity(2)], &[], &sqlite_pool)
+        .await
+        .unwrap();
+    assert_eq!(load().await.unwrap().len(), 2);
+
+    PersistentStore::save_original_affinities(&[], &[(1, 7)], &sqlite_pool)
+        .await
+        .unwrap();
+    let original_affinities = load().await.unwrap();
+    assert_eq!(original_affinities.len(), 1);
+    assert_eq!(original_affinities[0].process_id, 2);
+}
+
+#[tokio::test]
+async fn persisting_worker_heartbeats() {
+    let sqlite_pool = get_memory_pool().await;
+    assert_eq!(
+        PersistentStore::load_heartbeat(&sqlite_pool).await.unwrap(),
+        None
+    );
+
+    let heartbeat = WorkerHeartbeat::now(
+        Some(false),
+        Some(String::from("iRacing: Couldn't set affinity.")),
+        vec![RuleEnforcement {
+            rule_name: String::from("iRacing"),
+            strategy: EnforcementStrategy::InheritViaSpawner,
+        }],
+        false,
+        ScanStats::default(),
+        vec![],
+        vec![],
+    );
+    PersistentStore::save_heartbeat(&heartbeat, &sqlite_pool)
+        .await
+        .unwrap();
+    PersistentStore::save_heartbeat(&heartbeat, &sqlite_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        PersistentStore::load_heartbeat(&sqlite_pool).await.unwrap(),
+        Some(heartbeat)
+    );
+}
+
+#[test]
+fn judging_heartbeats_stale_by_period() {
+    let heartbeat = WorkerHeartbeat::now(
+        None,
+        None,
+        vec![],
+        false,
+        ScanStats::default(),
+        vec![],
+        vec![],
+    );
+    let mut heartbeat = serde_json::to_value(heartbeat).unwrap();
+    let at = chrono::Utc::now() - chrono::Duration::seconds(20);
+    heartbeat["at"] = serde_json::to_value(at).unwrap();
+    let heartbeat: WorkerHeartbeat = serde_json::from_value(heartbeat).unwrap();
+    assert!(heartbeat.get_is_stale());
+
+    // Idle while process events wake the worker.
+    let heartbeat = heartbeat.with_period(std::time::Duration::from_secs(30));
+    assert!(!heartbeat.get_is_stale());
+}
+
+#[tokio::test]
+async fn switching_profiles() {
+    let sqlite_pool = get_memory_pool().await;
+    let topology = CpuTopology::new_flat(12);
+    let profiles = PersistentStore::load_profiles(&sqlite_pool).await.unwrap();
+    assert_eq!(profiles.len(), 1);
+    assert!(profiles[0].is_active);
+
+    let mut persistent_store = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap();
+    assert_eq!(persistent_store.profile_id, Profile::DEFAULT_ID);
+    persistent_store.rules.push(Rule::new_custom(1, &topology));
+    persistent_store.save(&sqlite_pool).await.unwrap();
+
+    let racing_id = PersistentStore::duplicate_profile(Profile::DEFAULT_ID, "Racing", &sqlite_pool)
+        .await
+        .unwrap();
+    assert!(
+        PersistentStore::duplicate_profile(Profile::DEFAULT_ID, "Racing", &sqlite_pool)
+            .await
+            .is_err()
+    );
+    PersistentStore::activate_profile(racing_id, &sqlite_pool)
+        .await
+        .unwrap();
+    let mut racing_store = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap();
+    assert_eq!(racing_store.profile_id, racing_id);
+    assert_eq!(racing_store.rules.len(), 2);
+    assert_eq!(
+        racing_store.rules[1].selections,
+        persistent_store.rules[1].selections
+    );
+    racing_store.rules.truncate(1);
+    racing_store.save(&sqlite_pool).await.unwrap();
+    assert!(
+        PersistentStore::delete_profile(racing_id, &sqlite_pool)
+            .await
+            .is_err()
+    );
+
+    PersistentStore::rename_profile(Profile::DEFAULT_ID, "Benchmark", &sqlite_pool)
+        .await
+        .unwrap();
+    PersistentStore::activate_profile(Profile::DEFAULT_ID, &sqlite_pool)
+        .await
+        .unwrap();
+    let profiles = PersistentStore::load_profiles(&sqlite_pool).await.unwrap();
+    assert_eq!(
+        profiles,
+        vec![
+            Profile {
+                id: Profile::DEFAULT_ID,
+                name: String::from("Benchmark"),
+                is_active: true,
+            },
+            Profile {
+                id: racing_id,
+                name: String::from("Racing"),
+                is_active: false,
+            },
+        ]
+    );
+    let persistent_store = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap();
+    assert_eq!(persistent_store.rules.len(), 2);
+
+    PersistentStore::delete_profile(racing_id, &sqlite_pool)
+        .await
+        .unwrap();
+    assert!(
+        PersistentStore::activate_profile(racing_id, &sqlite_pool)
+            .await
+            .is_err()
+    );
+    // Saving rules loaded before their profile was deleted.
+    assert!(racing_store.save(&sqlite_pool).await.is_err());
+}
+
+#[tokio::test]
+#[cfg(target_os = "linux")]
+async fn matching_migrated_built_in_rule_under_wine() {
+    let sqlite_pool = get_memory_pool().await;
+    let topology = CpuTopology::new_flat(12);
+    let built_in_rule = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap()
+        .loaded_rules
+        .remove(0);
+    assert!(built_in_rule.get_is_built_in());
+    assert!(built_in_rule.spawner_matcher.unwrap().is_wine_aware);
+
+    // Launched by Proton's loader, with `comm` cut short.
+    let directory = std::env::temp_dir().join(format!("ir_affinity_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let loader_path = directory.join("wine64-preloader");
+    std::fs::copy("/bin/sleep", &loader_path).unwrap();
+    // `comm` comes from the path run, and the exe from where it leads.
+    let image_path = directory.join("iRacingSim64DX11");
+    std::os::unix::fs::symlink(&loader_path, &image_path).unwrap();
+    let mut child = Command::new(&image_path).arg("30").spawn().unwrap();
+    let mut process_scanner = ProcessScanner::new(System::new());
+    process_scanner.scan();
+    let matching_processes = built_in_rule
+        .matcher
+        .get_matching_processes(&process_scanner)
+        .unwrap();
+    assert!(
+        matching_processes
+            .iter()
+            .any(|process| process.pid().as_u32() == child.id())
+    );
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn saving_only_changed_rules() {
+    let sqlite_pool = get_memory_pool().await;
+    let topology = CpuTopology::new_flat(12);
+    let mut persistent_store = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap();
+    let mut other_store = persistent_store.clone();
+
+    // Changed elsewhere after both were loaded.
+    PersistentStore::set_rule_enabled(Rule::BUILT_IN_ID, false, &sqlite_pool)
+        .await
+        .unwrap();
+    persistent_store.rules.push(Rule::new_custom(1, &topology));
+    persistent_store.save(&sqlite_pool).await.unwrap();
+    let rules = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap()
+        .rules;
+    assert_eq!(
+        rules.iter().map(|rule| rule.id).collect::<Vec<_>>(),
+        vec![Rule::BUILT_IN_ID, 1]
+    );
+    assert!(!rules[0].is_enabled);
+
+    // Added by both with the same ID.
+    other_store.rules.push(Rule::new_custom(1, &topology));
+    assert!(other_store.save(&sqlite_pool).await.is_err());
+
+    persistent_store.rules[1].name = String::from("Renamed");
+    persistent_store.rules.swap(0, 1);
+    persistent_store.save(&sqlite_pool).await.unwrap();
+    let rules = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap()
+        .rules;
+    assert_eq!(rules[0].id, 1);
+    assert_eq!(rules[0].name, "Renamed");
+    assert!(!rules[1].is_enabled);
+
+    persistent_store.rules.remove(0);
+    persistent_store.save(&sqlite_pool).await.unwrap();
+    let rules = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap()
+        .rules;
+    assert_eq!(rules.len(), 1);
+    assert!(!rules[0].is_enabled);
+}
+
+#[tokio::test]
+async fn saving_only_changed_settings() {
+    let sqlite_pool = get_memory_pool().await;
+    let topology = CpuTopology::new_flat(12);
+    let mut persistent_store = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap();
+    let mut other_store = persistent_store.clone();
+
+    // Changed elsewhere after both were loaded.
+    other_store.settings.log_level = LogLevel::Debug;
+    other_store.save(&sqlite_pool).await.unwrap();
+    persistent_store.settings.is_process_events_enabled = false;
+    persistent_store.save(&sqlite_pool).await.unwrap();
+    let settings = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap()
+        .settings;
+    assert_eq!(settings.log_level, LogLevel::Debug);
+    assert!(!settings.is_process_events_enabled);
+
+    // Saving rules alone.
+    persistent_store.rules.push(Rule::new_custom(1, &topology));
+    persistent_store.save(&sqlite_pool).await.unwrap();
+    let settings = PersistentStore::load(&topology, &sqlite_pool)
+        .await
+        .unwrap()
+        .settings;
+    assert_eq!(settings.log_level, LogLevel::Debug);
+}
+
+#[tokio::test]
+async fn saving_profile_triggers() {
+    let sqlite_pool = get_memory_pool().await;
+    let racing_id = PersistentStore::duplicate_profile(Profile::DEFAULT_ID, "Racing", &sqlite_pool)
+        .await
+        .unwrap();
+    let matcher = ProcessMatcher::new_exact_image_name(DEFAULT_IRACING_SIMULATOR);
+    assert!(
+        PersistentStore::add_profile_trigger(racing_id + 1, &matcher, 0, &sqlite_pool)
+            .await
+            .is_err()
+    );
+    let low_id =
+        PersistentStore::add_profile_trigger(Profile::DEFAULT_ID, &matcher, 0, &sqlite_pool)
+            .await
+            .unwrap();
+    let high_id = PersistentStore::add_profile_trigger(racing_id, &matcher, 10, &sqlite_pool)
+        .await
+        .unwrap();
+    PersistentStore::set_profile_trigger_enabled(low_id, false, &sqlite_pool)
+        .await
+        .unwrap();
+    let profile_triggers = PersistentStore::load_profile_triggers(&sqlite_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        profile_triggers,
+        vec![
+            ProfileTrigger {
+                id: high_id,
+                profile_id: racing_id,
+                matcher: matcher.clone(),
+                priority: 10,
+                is_enabled: true,
+            },
+            ProfileTrigger {
+                id: low_id,
+                profile_id: Profile::DEFAULT_ID,
+                matcher: matcher.clone(),
+                priority: 0,
+                is_enabled: false,
+            },
+        ]
+    );
+
+    PersistentStore::delete_profile(racing_id, &sqlite_pool)
+        .await
+        .unwrap();
+    PersistentStore::remove_profile_trigger(low_id, &sqlite_pool)
+        .await
+        .unwrap();
+    assert!(
+        PersistentStore::remove_profile_trigger(low_id, &sqlite_pool)
+            .await
+            .is_err()
+    );
+    let profile_triggers = PersistentStore::load_profile_triggers(&sqlite_pool)
+        .await
+        .unwrap();
+    assert!(profile_triggers.is_empty());
+}
+
+#[test]
+fn switching_profiles_by_triggers() {
+    let streaming_trigger = ProfileTrigger {
+        id: 1,
+        profile_id: 1,
+        matcher: ProcessMatcher::new_exact_name("obs64.exe"),
+        priority: 0,
+        is_enabled: true,
+    };
+    let racing_trigger = ProfileTrigger {
+        id: 2,
+        profile_id: 2,
+        matcher: ProcessMatcher::new_exact_image_name(DEFAULT_IRACING_SIMULATOR),
+        priority: 10,
+        is_enabled: true,
+    };
+    let profile_triggers = [streaming_trigger.clone(), racing_trigger.clone()];
+    let start = chrono::Utc::now();
+    let at = |seconds| start + chrono::Duration::seconds(seconds);
+    let mut profile_switcher = ProfileSwitcher::default();
+
+    let streaming = HashSet::from([1]);
+    assert_eq!(
+        profile_switcher.update(0, &profile_triggers, &streaming, at(0)),
+        Some(1)
+    );
+    assert_eq!(
+        profile_switcher.get_activation(1, &profile_triggers, at(0)),
+        ProfileActivation::Triggered {
+            trigger_id: 1,
+            matcher: streaming_trigger.matcher.to_string(),
+        }
+    );
+    // The higher priority wins while both match.
+    let both = HashSet::from([1, 2]);
+    assert_eq!(
+        profile_switcher.update(1, &profile_triggers, &both, at(5)),
+        Some(2)
+    );
+    // Held after it stops matching, so it doesn't flap.
+    assert_eq!(
+        profile_switcher.update(2, &profile_triggers, &streaming, at(10)),
+        None
+    );
+    assert_eq!(
+        profile_switcher.get_activation(2, &profile_triggers, at(10)),
+        ProfileActivation::Held {
+            trigger_id: 2,
+            matcher: racing_trigger.matcher.to_string(),
+            until: at(35),
+        }
+    );
+    assert_eq!(
+        profile_switcher.update(2, &profile_triggers, &streaming, at(40)),
+        Some(1)
+    );
+    let none = HashSet::new();
+    assert_eq!(
+        profile_switcher.update(1, &profile_triggers, &none, at(45)),
+        None
+    );
+    // Back to the profile from before the first switch once none hold.
+    assert_eq!(
+        profile_switcher.update(1, &profile_triggers, &none, at(80)),
+        Some(0)
+    );
+    assert_eq!(
+        profile_switcher.get_activation(0, &profile_triggers, at(80)),
+        ProfileActivation::Manual
+    );
+
+    let racing = HashSet::from([2]);
+    assert_eq!(
+        profile_switcher.update(0, &profile_triggers, &racing, at(85)),
+        Some(2)
+    );
+    // Picked by hand, which overrides the trigger until it's released.
+    assert_eq!(
+        profile_switcher.update(1, &profile_triggers, &racing, at(90)),
+        None
+    );
+    assert_eq!(
+        profile_switcher.get_activation(1, &profile_triggers, at(90)),
+        ProfileActivation::Manual
+    );
+    assert_eq!(
+        profile_switcher.update(1, &profile_triggers, &none, at(200)),
+        None
+    );
+    assert_eq!(
+        profile_switcher.update(1, &profile_triggers, &racing, at(205)),
+        Some(2)
+    );
+}
+
+#[tokio::test]
+async fn running_worker_logic_with_profile_triggers() {
+    struct TestOperations {
+        active_profile_id: i64,
+    };
+    impl WorkerOperations_ for TestOperations {
+        async fn sleep(&mut self, watched_ids: &HashSet<u32>) -> Option<WorkerCommand> {
+            None
+        }
+
+        fn get_cooldown_period(&self) -> std::time::Duration {
+            std::time::Duration::from_secs(5)
+        }
+
+        fn set_is_event_driven(&mut self, is_event_driven: bool) {}
+
+        async fn load_store(&mut self, system_info: &System) -> ResultBtAny<PersistentStore> {
+            Ok(PersistentStore {
+                profile_id: self.active_profile_id,
+                rules: vec![],
+                loaded_rules: vec![],
+                settings: Settings::default(),
+                loaded_settings: Settings::default(),
+            })
+        }
+
+        async fn load_profiles(&mut self) -> ResultBtAny<(Vec<Profile>, Vec<ProfileTrigger>)> {
+            let profiles = [(Profile::DEFAULT_ID, "Default"), (1, "Streaming")]
+                .into_iter()
+                .map(|(id, name)| Profile {
+                    id,
+                    name: String::from(name),
+                    is_active: id == self.active_profile_id,
+                })
+                .collect();
+            let profile_triggers = vec![
+                ProfileTrigger {
+                    id: 1,
+                    profile_id: 1,
+                    matcher: ProcessMatcher::new_exact_name("obs64.exe"),
+                    priority: 0,
+                    is_enabled: true,
+                },
+                // Left from a deleted profile.
+                ProfileTrigger {
+                    id: 2,
+                    profile_id: 2,
+                    matcher: ProcessMatcher::new_exact_name("obs64.exe"),
+                    priority: 10,
+                    is_enabled: true,
+                },
+            ];
+            Ok((profiles, profile_triggers))
+        }
+
+        async fn activate_profile(&mut self, profile_id: i64) -> ResultBtAny<()> {
+            self.active_profile_id = profile_id;
+            Ok(())
+        }
+
+        fn get_processes_by_matcher(
+            &mut self,
+            process_scanner: &ProcessScanner,
+            matcher: &ProcessMatcher,
+        ) -> ResultBtAny<Vec<IrAProcess>> {
+            Ok(vec![IrAProcess {
+                id: 7,
+                start_time: 0,
+            }])
+        }
+
+        async fn set_processes_affinity(
+            &mut self,
+            candidate_processes: &[IrAProcess],
+            cpu_selections: &CpuSelections,
+        ) -> ResultBtAny<()> {
+            Ok(())
+        }
+
+        async fn get_process_affinity(
+            &mut self,
+            candidate_process: &IrAProcess,
+            system_info: &System,
+        ) -> ResultBtAny<CpuSelections> {
+            Ok(CpuSelections::new_all_selected(12))
+        }
+
+        fn get_is_process_running(&mut self, process: &IrAProcess, system_info: &System) -> bool {
+            true
+        }
+
+        async fn load_original_affinities(&mut self) -> ResultBtAny<Vec<OriginalAffinity>> {
+            Ok(vec![])
+        }
+
+        async fn save_original_affinities(
+            &mut self,
+            original_affinities: &[OriginalAffinity],
+            restored_keys: &[ProcessKey],
+        ) -> ResultBtAny<()> {
+            Ok(())
+        }
+    }
+
+    let mut worker_operations = TestOperations {
+        active_profile_id: Profile::DEFAULT_ID,
+    };
+    let mut process_scanner = ProcessScanner::new(System::new());
+    let (status_tx, status_rx) = watch::channel(None);
+
+    run_worker_logic(
+        &mut worker_operations,
+        &mut WorkerState::default(),
+        &mut process_scanner,
+        &status_tx,
+    )
+    .await
+    .unwrap();
+    assert_eq!(worker_operations.active_profile_id, 1);
+    let heartbeat = status_rx.borrow().clone().unwrap();
+    assert_eq!(
+        heartbeat.get_profile(),
+        Some(&ProfileReport {
+            id: 1,
+            name: String::from("Streaming"),
+            activation: ProfileActivation::Triggered {
+                trigger_id: 1,
+                matcher: ProcessMatcher::new_exact_name("obs64.exe").to_string(),
+            },
+        })
+    );
+}
+
+#[tokio::test]
+async fn serving_ipc_requests() {
+    let (status_sender, status_receiver) = watch::channel(None);
+    let (command_sender, mut command_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let ipc_state = IpcState::new(
+        get_memory_pool().await,
+        CpuTopology::new_flat(12),
+        status_receiver,
+        command_sender,
+    );
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(serve_connection(server_stream, ipc_state.clone()));
+    let mut ipc_client = IpcClient::new(client_stream);
+
+    assert_eq!(ipc_client.get_heartbeat().await.unwrap(), None);
+    let rules = ipc_client.get_rules().await.unwrap();
+    assert_eq!(rules.len(), 1);
+    assert!(rules[0].is_enabled);
+
+    ipc_client
+        .set_rule_enabled(Rule::BUILT_IN_ID, false)
+        .await
+        .unwrap();
+    assert!(!ipc_client.get_rules().await.unwrap()[0].is_enabled);
+    assert!(ipc_client.set_rule_enabled(7, true).await.is_err());
+
+    ipc_client.send_command(WorkerCommand::Apply).await.unwrap();
+    assert_eq!(command_receiver.try_recv().unwrap(), WorkerCommand::Apply);
+    ipc_client
+        .send_command(WorkerCommand::RetryProcess((42, 7)))
+        .await
+        .unwrap();
+    assert_eq!(
+        command_receiver.try_recv().unwrap(),
+        WorkerCommand::RetryProcess((42, 7))
+    );
+    assert!(
+        ipc_client
+            .send_command(WorkerCommand::Shutdown)
+            .await
+            .is_err()
+    );
+
+    assert!(ipc_client.focus_window().await.is_err());
+    ipc_client.subscribe().await.unwrap();
+    let heartbeat = WorkerHeartbeat::now(
+        Some(true),
+        None,
+        vec![],
+        false,
+        ScanStats::default(),
+        vec![],
+        vec![],
+    );
+    status_sender.send_replace(Some(heartbeat.clone()));
+    assert_eq!(
+        ipc_client.next_notification().await.unwrap(),
+        Some(IpcNotification::Heartbeat(Box::new(heartbeat.clone())))
+    );
+    assert_eq!(ipc_client.get_heartbeat().await.unwrap(), Some(heartbeat));
+
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(serve_connection(server_stream, ipc_state.clone()));
+    IpcClient::new(client_stream).focus_window().await.unwrap();
+    assert_eq!(
+        ipc_client.next_notification().await.unwrap(),
+        Some(IpcNotification::FocusWindow)
+    );
+
+    // Requests without an ID are notifications, and get no response.
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(serve_connection(server_stream, ipc_state));
+    let (reader, mut writer) = tokio::io::split(client_stream);
+    writer
+        .write_all(
+            concat!(
+                r#"{"jsonrpc":"2.0","method":"apply"}"#,
+                "\n",
+                r#"{"jsonrpc":"2.0","id":1,"method":"resume"}"#,
+                "\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await
+        .unwrap()
+        .unwrap();
+    let ipc_response: IpcResponse = serde_json::from_str(&line).unwrap();
+    assert_eq!(ipc_response.id, 1);
+    assert_eq!(ipc_response.error, None);
+    assert_eq!(command_receiver.recv().await.unwrap(), WorkerCommand::Apply);
+    assert_eq!(
+        command_receiver.recv().await.unwrap(),
+        WorkerCommand::Resume
+    );
+}
+
+#[test]
+fn holding_instance_locks() {
+    let directory = std::env::temp_dir().join(format!("ir_affinity_{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let acquire = || InstanceLock::acquire_in(&directory, InstanceKind::Daemon).unwrap();
+    let lock_path = directory.join("daemon.lock");
+
+    let instance_lock = acquire().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&lock_path).unwrap(),
+        std::process::id().to_string()
+    );
+    assert!(acquire().is_none());
+    assert!(
+        InstanceLock::acquire_in(&directory, InstanceKind::Window)
+            .unwrap()
+            .is_some()
+    );
+    drop(instance_lock);
+    assert_eq!(std::fs::read_to_string(&lock_path).unwrap(), "");
+
+    // Left by an instance that's exited without releasing it.
+    std::fs::write(&lock_path, "4194304").unwrap();
+    drop(acquire().unwrap());
+
+    let barrier = std::sync::Barrier::new(8);
+    let instance_locks = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    acquire()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(instance_locks.iter().flatten().count(), 1);
+    drop(instance_locks);
+    drop(acquire().unwrap());
+
+    std::fs::remove_dir_all(&directory).unwrap();
 }
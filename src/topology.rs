@@ -0,0 +1,589 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::errors::ResultBtAny;
+#[cfg(target_os = "windows")]
+use crate::{
+    selections::CpuMask,
+    wrappers::{ProcessorGroups, SystemCpuSetInformation},
+};
+#[cfg(target_os = "windows")]
+use windows::Win32::Foundation::GetLastError;
+#[cfg(target_os = "windows")]
+use windows::Win32::System::SystemInformation::{
+    GROUP_AFFINITY, GetLogicalProcessorInformationEx, GetSystemCpuSetInformation,
+    LOGICAL_PROCESSOR_RELATIONSHIP, RelationCache, RelationProcessorPackage,
+    SYSTEM_CPU_SET_INFORMATION, SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX,
+};
+
+pub const SYSFS_CPU_DIRECTORY: &str = "/sys/devices/system/cpu";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogicalCpu {
+    pub id: usize,
+    pub package: usize,
+    /// Index of the physical core, unique across packages.
+    pub core: usize,
+    /// Index of the last level cache (L3 or CCD) the CPU sits behind.
+    pub cache: usize,
+    pub cache_size: Option<usize>,
+    pub numa_node: usize,
+    /// Higher is more performant, like Windows' `EfficiencyClass`.
+    pub efficiency_class: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheGroup {
+    pub size: Option<usize>,
+    pub cpus: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuTopology {
+    cpus: Vec<LogicalCpu>,
+}
+
+impl CpuTopology {
+    /// Every CPU its own core, for when the real layout can't be read.
+    pub fn new_flat(cpu_count: usize) -> Self {
+        let cpus = (0..cpu_count)
+            .map(|cpu_id| LogicalCpu {
+                id: cpu_id,
+                package: 0,
+                core: cpu_id,
+                cache: 0,
+                cache_size: None,
+                numa_node: 0,
+                efficiency_class: 0,
+            })
+            .collect();
+        Self { cpus }
+    }
+
+    pub fn load(cpu_count: usize) -> Self {
+        #[cfg(target_os = "linux")]
+        let topology = Self::from_sysfs(Path::new(SYSFS_CPU_DIRECTORY));
+
+        #[cfg(target_os = "windows")]
+        let topology = Self::from_cpu_sets();
+
+        #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+        let topology: ResultBtAny<Self> = Err("CPU topology is unsupported on this OS.".into());
+
+        match topology {
+            Ok(topology) if topology.get_cpu_count() == cpu_count => topology,
+            Ok(topology) => {
+                error!(
+                    "Topology has `{}` CPUs but expected `{}`.",
+                    topology.get_cpu_count(),
+                    cpu_count
+                );
+                Self::new_flat(cpu_count)
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                Self::new_flat(cpu_count)
+            }
+        }
+    }
+
+    /// Reads a `/sys/devices/system/cpu` tree, or a recorded copy of one.
+    pub fn from_sysfs(cpu_directory: &Path) -> ResultBtAny<Self> {
+        let cpu_ids = match read_sysfs_value(&cpu_directory.join("online")) {
+            Some(online) => parse_cpu_list(&online)?,
+            None => {
+                let mut cpu_ids = vec![];
+                for entry in fs::read_dir(cpu_directory)? {
+                    let cpu_id = entry?
+                        .file_name()
+                        .to_str()
+                        .and_then(|name| name.strip_prefix("cpu"))
+                        .and_then(|cpu_id| cpu_id.parse().ok());
+                    if let Some(cpu_id) = cpu_id {
+                        cpu_ids.push(cpu_id);
+                    }
+                }
+                cpu_ids.sort();
+                cpu_ids
+            }
+        };
+        let are_no_cpus = cpu_ids.is_empty();
+        if are_no_cpus {
+            Err(format!(
+                "No CPUs found under `{}`.",
+                cpu_directory.to_string_lossy()
+            ))?;
+        }
+
+        let efficiency_classes = get_sysfs_efficiency_classes(cpu_directory, &cpu_ids);
+
+        let mut core_keys = vec![];
+        let mut cache_keys = vec![];
+        let mut cpus = vec![];
+        for (cpu_index, &cpu_id) in cpu_ids.iter().enumerate() {
+            let cpu_path = cpu_directory.join(format!("cpu{cpu_id}"));
+
+            let package = read_sysfs_number(&cpu_path.join("topology/physical_package_id"))
+                .unwrap_or_default();
+            let core_id = read_sysfs_number(&cpu_path.join("topology/core_id")).unwrap_or(cpu_id);
+            let core = get_or_push_index(&mut core_keys, (package, core_id));
+
+            let (cache_key, cache_size) = get_sysfs_last_level_cache(&cpu_path)
+                .unwrap_or_else(|| (format!("package{package}"), None));
+            let cache = get_or_push_index(&mut cache_keys, cache_key);
+
+            let numa_node = fs::read_dir(&cpu_path)?
+                .filter_map(|entry| entry.ok())
+                .find_map(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()
+                        .and_then(|name| name.strip_prefix("node"))
+                        .and_then(|numa_node| numa_node.parse().ok())
+                })
+                .unwrap_or_default();
+
+            cpus.push(LogicalCpu {
+                id: cpu_id,
+                package,
+                core,
+                cache,
+                cache_size,
+                numa_node,
+                efficiency_class: efficiency_classes[cpu_index],
+            });
+        }
+        info!("Read CPU topology from sysfs.");
+
+        Ok(Self { cpus })
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn from_cpu_sets() -> ResultBtAny<Self> {
+        let processor_groups = ProcessorGroups::load();
+        let packages = get_package_masks(&processor_groups)?;
+        let cache_sizes = get_last_level_cache_sizes(&processor_groups)?;
+
+        let mut core_keys = vec![];
+        let mut cache_keys = vec![];
+        let mut cpus = vec![];
+        for cpu_set in get_cpu_sets()? {
+            let cpu_id =
+                processor_groups.get_logical_index(cpu_set.get_group(), cpu_set.get_logical());
+            let package = packages
+                .iter()
+                .position(|package| package.get_is_set(cpu_id))
+                .unwrap_or_default();
+            let core = get_or_push_index(
+                &mut core_keys,
+                (cpu_set.get_group(), cpu_set.get_physical()),
+            );
+            let cache =
+                get_or_push_index(&mut cache_keys, (cpu_set.get_group(), cpu_set.get_cache()));
+            let cache_size = cache_sizes
+                .iter()
+                .find(|(cache_mask, _)| cache_mask.get_is_set(cpu_id))
+                .map(|(_, cache_size)| *cache_size);
+
+            cpus.push(LogicalCpu {
+                id: cpu_id,
+                package,
+                core,
+                cache,
+                cache_size,
+                numa_node: cpu_set.get_numa_node() as usize,
+                efficiency_class: cpu_set.get_efficiency_class(),
+            });
+        }
+        cpus.sort_by_key(|cpu| cpu.id);
+        info!("Read CPU topology from CPU sets.");
+
+        Ok(Self { cpus })
+    }
+
+    pub fn get_cpus(&self) -> &[LogicalCpu] {
+        &self.cpus
+    }
+
+    pub fn get_cpu_count(&self) -> usize {
+        self.cpus.len()
+    }
+
+    pub fn get_cpu(&self, cpu_id: usize) -> Option<&LogicalCpu> {
+        self.cpus.iter().find(|cpu| cpu.id == cpu_id)
+    }
+
+    /// SMT siblings grouped per physical core, cores ordered by first CPU.
+    pub fn get_physical_cores(&self) -> Vec<Vec<usize>> {
+        let mut cores: Vec<(usize, Vec<usize>)> = vec![];
+        for cpu in self.cpus.iter() {
+            match cores.iter_mut().find(|(core, _)| *core == cpu.core) {
+                Some((_, siblings)) => siblings.push(cpu.id),
+                None => cores.push((cpu.core, vec![cpu.id])),
+            }
+        }
+        cores.into_iter().map(|(_, siblings)| siblings).collect()
+    }
+
+    pub fn get_siblings(&self, cpu_id: usize) -> Vec<usize> {
+        let Some(cpu) = self.get_cpu(cpu_id) else {
+            return vec![];
+        };
+        self.cpus
+            .iter()
+            .filter(|cpu_| cpu_.core == cpu.core)
+            .map(|cpu_| cpu_.id)
+            .collect()
+    }
+
+    pub fn get_cache_groups(&self) -> Vec<CacheGroup> {
+        let mut cache_groups: Vec<(usize, CacheGroup)> = vec![];
+        for cpu in self.cpus.iter() {
+            match cache_groups
+                .iter_mut()
+                .find(|(cache, _)| *cache == cpu.cache)
+            {
+                Some((_, cache_group)) => cache_group.cpus.push(cpu.id),
+                None => cache_groups.push((
+                    cpu.cache,
+                    CacheGroup {
+                        size: cpu.cache_size,
+                        cpus: vec![cpu.id],
+                    },
+                )),
+            }
+        }
+        cache_groups
+            .into_iter()
+            .map(|(_, cache_group)| cache_group)
+            .collect()
+    }
+
+    pub fn get_numa_nodes(&self) -> Vec<usize> {
+        let mut numa_nodes: Vec<_> = self
+            .cpus
+            .iter()
+            .map(|cpu| cpu.numa_node)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        numa_nodes.sort();
+        numa_nodes
+    }
+
+    pub fn get_is_hybrid(&self) -> bool {
+        self.cpus
+            .iter()
+            .any(|cpu| cpu.efficiency_class != self.cpus[0].efficiency_class)
+    }
+}
+
+fn get_or_push_index<T: PartialEq>(keys: &mut Vec<T>, key: T) -> usize {
+    match keys.iter().position(|key_| *key_ == key) {
+        Some(index) => index,
+        None => {
+            keys.push(key);
+            keys.len() - 1
+        }
+    }
+}
+
+fn read_sysfs_value(path: &Path) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+fn read_sysfs_number(path: &Path) -> Option<usize> {
+    read_sysfs_value(path).and_then(|value| value.parse().ok())
+}
+
+/// Parses kernel CPU lists such as `0-3,8,10-11`.
+pub fn parse_cpu_list(cpu_list: &str) -> ResultBtAny<Vec<usize>> {
+    let mut cpu_ids = vec![];
+    for range in cpu_list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => {
+                let start: usize = start
+                    .parse()
+                    .map_err(|_| format!("Bad CPU list `{cpu_list}`."))?;
+                let end: usize = end
+                    .parse()
+                    .map_err(|_| format!("Bad CPU list `{cpu_list}`."))?;
+                cpu_ids.extend(start..=end);
+            }
+            None => cpu_ids.push(
+                range
+                    .parse()
+                    .map_err(|_| format!("Bad CPU list `{cpu_list}`."))?,
+            ),
+        }
+    }
+    Ok(cpu_ids)
+}
+
+/// Parses cache sizes such as `32768K` into bytes.
+fn parse_cache_size(cache_size: &str) -> Option<usize> {
+    let (digits, multiplier) = match cache_size.chars().last()? {
+        'K' => (&cache_size[..cache_size.len() - 1], 1 << 10),
+        'M' => (&cache_size[..cache_size.len() - 1], 1 << 20),
+        'G' => (&cache_size[..cache_size.len() - 1], 1 << 30),
+        _ => (cache_size, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .map(|digits| digits * multiplier)
+}
+
+/// Keyed by the sharing CPUs, since `cache/indexN/id` is missing on older
+/// kernels.
+fn get_sysfs_last_level_cache(cpu_path: &Path) -> Option<(String, Option<usize>)> {
+    let mut last_level_cache = None;
+    for entry in fs::read_dir(cpu_path.join("cache")).ok()? {
+        let cache_path = entry.ok()?.path();
+        let Some(level) = read_sysfs_number(&cache_path.join("level")) else {
+            continue;
+        };
+        let Some(shared_cpus) = read_sysfs_value(&cache_path.join("shared_cpu_list")) else {
+            continue;
+        };
+        let is_higher = last_level_cache
+            .as_ref()
+            .is_none_or(|(level_, _, _)| level > *level_);
+        if is_higher {
+            let cache_size = read_sysfs_value(&cache_path.join("size"))
+                .and_then(|cache_size| parse_cache_size(&cache_size));
+            last_level_cache = Some((level, shared_cpus, cache_size));
+        }
+    }
+    last_level_cache.map(|(_, shared_cpus, cache_size)| (shared_cpus, cache_size))
+}
+
+/// Ranks `cpu_capacity` where the kernel exposes it, else falls back to the
+/// hybrid PMU split of `cpu_core` and `cpu_atom`.
+fn get_sysfs_efficiency_classes(cpu_directory: &Path, cpu_ids: &[usize]) -> Vec<u8> {
+    let capacities: Vec<_> = cpu_ids
+        .iter()
+        .map(|cpu_id| read_sysfs_number(&cpu_directory.join(format!("cpu{cpu_id}/cpu_capacity"))))
+        .collect();
+    let are_capacities = capacities.iter().all(Option::is_some);
+    if are_capacities {
+        let mut distinct_capacities: Vec<_> = capacities.iter().flatten().copied().collect();
+        distinct_capacities.sort();
+        distinct_capacities.dedup();
+        return capacities
+            .iter()
+            .flatten()
+            .map(|capacity| {
+                distinct_capacities
+                    .iter()
+                    .position(|capacity_| capacity_ == capacity)
+                    .unwrap_or_default() as u8
+            })
+            .collect();
+    }
+
+    let efficient_cpus = cpu_directory
+        .parent()
+        .and_then(Path::parent)
+        .and_then(|devices_directory| read_sysfs_value(&devices_directory.join("cpu_atom/cpus")))
+        .and_then(|cpu_list| parse_cpu_list(&cpu_list).ok())
+        .unwrap_or_default();
+    cpu_ids
+        .iter()
+        .map(|cpu_id| u8::from(!efficient_cpus.contains(cpu_id)))
+        .collect()
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn get_cpu_sets() -> ResultBtAny<Vec<SystemCpuSetInformation>> {
+    let payload_size = std::mem::size_of::<SYSTEM_CPU_SET_INFORMATION>();
+
+    let mut output_size: u32 = 0;
+    let subject_process = None;
+    let reserved_flag = 0;
+    unsafe {
+        _ = GetSystemCpuSetInformation(
+            None,
+            0,
+            &mut output_size,
+            subject_process,
+            Some(reserved_flag),
+        );
+    }
+    let are_no_sets = output_size == 0;
+    if are_no_sets {
+        Err("There are no CPU sets!?")?;
+    }
+
+    let mut cpu_sets =
+        vec![SYSTEM_CPU_SET_INFORMATION::default(); (output_size as usize).div_ceil(payload_size)];
+    unsafe {
+        let is_success = GetSystemCpuSetInformation(
+            Some(cpu_sets.as_mut_ptr()),
+            (cpu_sets.len() * payload_size) as u32,
+            &mut output_size,
+            subject_process,
+            Some(reserved_flag),
+        );
+        info!("Got CPU set info.");
+        if (!is_success).into() {
+            Err(format!(
+                "WinAPI call failed with code `{}`.",
+                GetLastError().0
+            ))?;
+        }
+    };
+
+    // Entries are sized by their own `Size` field rather than the struct.
+    let mut cpu_sets_ = vec![];
+    let mut offset = 0;
+    let cpu_sets_start = cpu_sets.as_ptr().cast::<u8>();
+    while offset + payload_size <= output_size as usize {
+        // Offsets from `Size` needn't keep the struct's alignment.
+        let cpu_set = unsafe {
+            std::ptr::read_unaligned(
+                cpu_sets_start
+                    .add(offset)
+                    .cast::<SYSTEM_CPU_SET_INFORMATION>(),
+            )
+        };
+        let is_malformed = cpu_set.Size == 0;
+        if is_malformed {
+            break;
+        }
+        offset += cpu_set.Size as usize;
+        cpu_sets_.push(SystemCpuSetInformation::new(cpu_set));
+    }
+
+    Ok(cpu_sets_)
+}
+
+/// Calls `GetLogicalProcessorInformationEx`, whose entries are variable length
+/// and each carry their own `Size`.
+#[cfg(target_os = "windows")]
+fn get_logical_processor_information(
+    relationship: LOGICAL_PROCESSOR_RELATIONSHIP,
+) -> ResultBtAny<Vec<u64>> {
+    let mut output_size: u32 = 0;
+    unsafe {
+        _ = GetLogicalProcessorInformationEx(relationship, None, &mut output_size);
+    }
+    let are_no_relations = output_size == 0;
+    if are_no_relations {
+        Err("There are no logical processor relations!?")?;
+    }
+
+    // Backed by `u64`s so entries keep their natural alignment.
+    let mut information = vec![0u64; (output_size as usize).div_ceil(size_of::<u64>())];
+    unsafe {
+        GetLogicalProcessorInformationEx(
+            relationship,
+            Some(
+                information
+                    .as_mut_ptr()
+                    .cast::<SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX>(),
+            ),
+            &mut output_size,
+        )?;
+    }
+    information.truncate((output_size as usize).div_ceil(size_of::<u64>()));
+    info!("Got logical processor info.");
+
+    Ok(information)
+}
+
+#[cfg(target_os = "windows")]
+fn get_group_masks(
+    processor_groups: &ProcessorGroups,
+    group_masks: *const GROUP_AFFINITY,
+    group_count: u16,
+) -> CpuMask {
+    let mut mask = CpuMask::new();
+    for group_index in 0..group_count.max(1) as usize {
+        let group_mask = unsafe { group_masks.add(group_index).read_unaligned() };
+        mask.union_with(&processor_groups.to_mask(group_mask.Group, group_mask.Mask));
+    }
+    mask
+}
+
+#[cfg(target_os = "windows")]
+fn for_each_logical_processor_information(
+    information: &[u64],
+    mut on_entry: impl FnMut(*const SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX),
+) {
+    let information_start = information.as_ptr().cast::<u8>();
+    let information_size = size_of_val(information);
+    let mut offset = 0;
+    while offset < information_size {
+        let entry = unsafe {
+            information_start
+                .add(offset)
+                .cast::<SYSTEM_LOGICAL_PROCESSOR_INFORMATION_EX>()
+        };
+        let entry_size = unsafe { (*entry).Size } as usize;
+        if entry_size == 0 {
+            break;
+        }
+        on_entry(entry);
+        offset += entry_size;
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn get_package_masks(processor_groups: &ProcessorGroups) -> ResultBtAny<Vec<CpuMask>> {
+    let information = get_logical_processor_information(RelationProcessorPackage)?;
+    let mut packages = vec![];
+    for_each_logical_processor_information(&information, |entry| unsafe {
+        let group_masks = std::ptr::addr_of!((*entry).Anonymous.Processor.GroupMask);
+        let group_count = (*entry).Anonymous.Processor.GroupCount;
+        packages.push(get_group_masks(
+            processor_groups,
+            group_masks.cast::<GROUP_AFFINITY>(),
+            group_count,
+        ));
+    });
+    Ok(packages)
+}
+
+/// CPU sets index caches but don't size them, which V-Cache detection needs.
+#[cfg(target_os = "windows")]
+fn get_last_level_cache_sizes(
+    processor_groups: &ProcessorGroups,
+) -> ResultBtAny<Vec<(CpuMask, usize)>> {
+    let information = get_logical_processor_information(RelationCache)?;
+    let mut caches = vec![];
+    let mut last_level = 0;
+    for_each_logical_processor_information(&information, |entry| unsafe {
+        let level = (*entry).Anonymous.Cache.Level;
+        if level < last_level {
+            return;
+        }
+        if level > last_level {
+            last_level = level;
+            caches.clear();
+        }
+        let group_masks = std::ptr::addr_of!((*entry).Anonymous.Cache.Anonymous.GroupMasks);
+        let group_count = (*entry).Anonymous.Cache.GroupCount;
+        caches.push((
+            get_group_masks(
+                processor_groups,
+                group_masks.cast::<GROUP_AFFINITY>(),
+                group_count,
+            ),
+            (*entry).Anonymous.Cache.CacheSize as usize,
+        ));
+    });
+    Ok(caches)
+}
+
+#[test]
+#[cfg(target_os = "windows")]
+fn getting_cpu_sets() {
+    let cpu_sets: Vec<_> = get_cpu_sets().unwrap();
+    let system_info = sysinfo::System::new_all();
+    assert_eq!(cpu_sets.len(), system_info.cpus().len());
+}
@@ -0,0 +1,271 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    time::Duration,
+};
+
+use backon::{BackoffBuilder, ExponentialBackoff, ExponentialBuilder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::persistence::CpuSelections;
+
+/// A PID alone can be reused by an unrelated process once the first exits.
+pub type ProcessKey = (u32, u64);
+
+/// Processes are trusted to stay put for this long after being checked.
+const VERIFICATION_PERIOD_SECONDS: i64 = 15;
+
+/// How many exited processes are kept around to be shown.
+const EXITED_PROCESS_COUNT: usize = 16;
+
+/// Failures are retried with growing delays starting from this.
+const FAILURE_MIN_DELAY_SECONDS: u64 = 5;
+
+const FAILURE_MAX_DELAY_SECONDS: u64 = 300;
+
+/// Failures in a row before a process is quarantined, after which it's only
+/// retried on request.
+const QUARANTINE_FAILURE_COUNT: usize = 5;
+
+/// Why a process couldn't be looked at or changed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// Like elevated or anti-cheat protected processes.
+    PermissionDenied,
+    ProcessGone,
+    InvalidMask,
+    Other,
+}
+
+impl Display for FailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let failure_kind = match self {
+            FailureKind::PermissionDenied => "permission denied",
+            FailureKind::ProcessGone => "process gone",
+            FailureKind::InvalidMask => "invalid mask",
+            FailureKind::Other => "other",
+        };
+        write!(f, "{failure_kind}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessFailure {
+    pub kind: FailureKind,
+    pub message: String,
+    /// In a row, since it last succeeded or was retried on request.
+    pub count: usize,
+    /// `None` once quarantined.
+    pub retry_at: Option<DateTime<Utc>>,
+}
+
+impl ProcessFailure {
+    pub fn get_is_quarantined(&self) -> bool {
+        self.retry_at.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackedProcess {
+    pub process_id: u32,
+    pub start_time: u64,
+    pub rule_name: String,
+    pub first_seen_at: DateTime<Utc>,
+    pub applied_at: Option<DateTime<Utc>>,
+    /// Times it was found off its CPUs after having been on them.
+    pub drift_count: u32,
+    pub exited_at: Option<DateTime<Utc>>,
+    pub failure: Option<ProcessFailure>,
+    verified_at: Option<DateTime<Utc>>,
+    verified_selections: Option<CpuSelections>,
+}
+
+impl TrackedProcess {
+    fn new(process_key: ProcessKey, rule_name: &str) -> Self {
+        Self {
+            process_id: process_key.0,
+            start_time: process_key.1,
+            rule_name: rule_name.to_string(),
+            first_seen_at: Utc::now(),
+            applied_at: None,
+            drift_count: 0,
+            exited_at: None,
+            failure: None,
+            verified_at: None,
+            verified_selections: None,
+        }
+    }
+}
+
+/// Remembers claimed processes across runs of the worker.
+#[derive(Debug, Default)]
+pub struct ProcessTracker {
+    tracked_processes: HashMap<ProcessKey, TrackedProcess>,
+    exited_processes: VecDeque<TrackedProcess>,
+    /// By PID and start time, delays left before quarantining failing ones.
+    backoffs: HashMap<ProcessKey, ExponentialBackoff>,
+}
+
+impl ProcessTracker {
+    pub fn track(&mut self, process_key: ProcessKey, rule_name: &str) {
+        self.tracked_processes
+            .entry(process_key)
+            .or_insert_with(|| TrackedProcess::new(process_key, rule_name));
+    }
+
+    /// Whether it was found on these CPUs recently enough to not look again.
+    pub fn get_is_verified(&self, process_key: ProcessKey, cpu_selections: &CpuSelections) -> bool {
+        self.tracked_processes
+            .get(&process_key)
+            .filter(|tracked_process| {
+                tracked_process.verified_selections.as_ref() == Some(cpu_selections)
+            })
+            .and_then(|tracked_process| tracked_process.verified_at)
+            .is_some_and(|verified_at| {
+                Utc::now() - verified_at < chrono::Duration::seconds(VERIFICATION_PERIOD_SECONDS)
+            })
+    }
+
+    pub fn record_checked(
+        &mut self,
+        process_key: ProcessKey,
+        cpu_selections: &CpuSelections,
+        is_synced: bool,
+    ) {
+        let Some(tracked_process) = self.tracked_processes.get_mut(&process_key) else {
+            return;
+        };
+        let was_synced = tracked_process.verified_selections.as_ref() == Some(cpu_selections);
+        if is_synced {
+            tracked_process.verified_at = Some(Utc::now());
+            tracked_process.verified_selections = Some(cpu_selections.clone());
+        } else {
+            if was_synced {
+                tracked_process.drift_count += 1;
+            }
+            tracked_process.verified_at = None;
+            tracked_process.verified_selections = None;
+        }
+    }
+
+    pub fn record_applied(&mut self, process_key: ProcessKey, cpu_selections: &CpuSelections) {
+        let Some(tracked_process) = self.tracked_processes.get_mut(&process_key) else {
+            return;
+        };
+        let now = Utc::now();
+        tracked_process.applied_at = Some(now);
+        tracked_process.verified_at = Some(now);
+        tracked_process.verified_selections = Some(cpu_selections.clone());
+        tracked_process.failure = None;
+        self.backoffs.remove(&process_key);
+    }
+
+    /// Whether it failed too recently to try again, or is quarantined.
+    pub fn get_is_backing_off(&self, process_key: ProcessKey) -> bool {
+        self.tracked_processes
+            .get(&process_key)
+            .and_then(|tracked_process| tracked_process.failure.as_ref())
+            .is_some_and(|failure| {
+                failure
+                    .retry_at
+                    .is_none_or(|retry_at| Utc::now() < retry_at)
+            })
+    }
+
+    pub fn record_failed(
+        &mut self,
+        process_key: ProcessKey,
+        failure_kind: FailureKind,
+        message: String,
+    ) {
+        let Some(tracked_process) = self.tracked_processes.get_mut(&process_key) else {
+            return;
+        };
+        let backoff = self.backoffs.entry(process_key).or_insert_with(|| {
+            ExponentialBuilder::default()
+                .with_min_delay(Duration::from_secs(FAILURE_MIN_DELAY_SECONDS))
+                .with_max_delay(Duration::from_secs(FAILURE_MAX_DELAY_SECONDS))
+                .with_max_times(QUARANTINE_FAILURE_COUNT - 1)
+                .build()
+        });
+        let retry_at = backoff
+            .next()
+            .and_then(|delay| chrono::Duration::from_std(delay).ok())
+            .map(|delay| Utc::now() + delay);
+        let count = tracked_process
+            .failure
+            .as_ref()
+            .map_or(0, |failure| failure.count)
+            + 1;
+        tracked_process.failure = Some(ProcessFailure {
+            kind: failure_kind,
+            message,
+            count,
+            retry_at,
+        });
+    }
+
+    pub fn get_failure(&self, process_key: ProcessKey) -> Option<&ProcessFailure> {
+        self.tracked_processes
+            .get(&process_key)
+            .and_then(|tracked_process| tracked_process.failure.as_ref())
+    }
+
+    /// Lets a failing or quarantined process be tried again on the next run.
+    pub fn retry(&mut self, process_key: ProcessKey) {
+        if let Some(tracked_process) = self.tracked_processes.get_mut(&process_key) {
+            tracked_process.failure = None;
+        }
+        self.backoffs.remove(&process_key);
+    }
+
+    /// Stops tracking those `should_keep` rejects, remembering the ones that
+    /// are no longer running as exited.
+    pub fn retain(
+        &mut self,
+        mut should_keep: impl FnMut(ProcessKey) -> bool,
+        mut get_is_running: impl FnMut(ProcessKey) -> bool,
+    ) {
+        let released_keys: Vec<_> = self
+            .tracked_processes
+            .keys()
+            .filter(|process_key| !should_keep(**process_key))
+            .copied()
+            .collect();
+        for process_key in released_keys {
+            self.backoffs.remove(&process_key);
+            let Some(mut tracked_process) = self.tracked_processes.remove(&process_key) else {
+                continue;
+            };
+            if get_is_running(process_key) {
+                continue;
+            }
+            tracked_process.exited_at = Some(Utc::now());
+            self.exited_processes.push_front(tracked_process);
+            self.exited_processes.truncate(EXITED_PROCESS_COUNT);
+        }
+    }
+
+    /// Forgets what's been applied and checked, as after restoring them.
+    pub fn clear(&mut self) {
+        self.tracked_processes.clear();
+        self.backoffs.clear();
+    }
+
+    pub fn get_running_process_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.tracked_processes
+            .keys()
+            .map(|&(process_id, _)| process_id)
+    }
+
+    /// Running ones by when they were first seen, then those that exited most
+    /// recently.
+    pub fn get_tracked_processes(&self) -> Vec<TrackedProcess> {
+        let mut tracked_processes: Vec<_> = self.tracked_processes.values().cloned().collect();
+        tracked_processes.sort_by_key(|tracked_process| tracked_process.first_seen_at);
+        tracked_processes.extend(self.exited_processes.iter().cloned());
+        tracked_processes
+    }
+}
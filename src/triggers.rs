@@ -0,0 +1,173 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::persistence::ProfileTrigger;
+
+/// Triggers keep holding for this long after they stop matching, so a program
+/// restarting or missed by a scan doesn't flip profiles back and forth.
+const TRIGGER_RELEASE_PERIOD_SECONDS: i64 = 30;
+
+/// Why the active profile is active.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProfileActivation {
+    /// Picked by hand, or left as it was by the triggers.
+    Manual,
+    Triggered {
+        trigger_id: i64,
+        matcher: String,
+    },
+    /// Its trigger stopped matching, and is released after `until`.
+    Held {
+        trigger_id: i64,
+        matcher: String,
+        until: DateTime<Utc>,
+    },
+}
+
+impl Display for ProfileActivation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Manual => write!(f, "not triggered"),
+            Self::Triggered {
+                trigger_id,
+                matcher,
+            } => write!(f, "trigger #{trigger_id} matches {matcher}"),
+            Self::Held {
+                trigger_id,
+                matcher,
+                until,
+            } => write!(
+                f,
+                "trigger #{trigger_id} matched {matcher}, held until {}",
+                until.with_timezone(&chrono::Local).format("%H:%M:%S")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileReport {
+    pub id: i64,
+    pub name: String,
+    pub activation: ProfileActivation,
+}
+
+/// Picks the profile from the triggers matching running processes, carried
+/// between worker runs. It's only kept in memory, so a profile switched to by
+/// a trigger stays active when the daemon restarts.
+#[derive(Debug, Default)]
+pub struct ProfileSwitcher {
+    /// By trigger ID, when those holding last matched.
+    matched_at: HashMap<i64, DateTime<Utc>>,
+    /// The trigger that last switched profiles, and the profile's ID.
+    switched_to: Option<(i64, i64)>,
+    /// Active before the triggers first switched from it, and switched back to
+    /// once none hold.
+    base_profile_id: Option<i64>,
+    /// Those holding when the profile was picked by hand, which are ignored
+    /// until they're released.
+    overridden_ids: HashSet<i64>,
+}
+
+impl ProfileSwitcher {
+    /// The profile to switch to, if any. `matched_ids` are the triggers
+    /// matching a running process now.
+    pub fn update(
+        &mut self,
+        active_profile_id: i64,
+        profile_triggers: &[ProfileTrigger],
+        matched_ids: &HashSet<i64>,
+        now: DateTime<Utc>,
+    ) -> Option<i64> {
+        for &trigger_id in matched_ids {
+            self.matched_at.insert(trigger_id, now);
+        }
+        let release_period = chrono::Duration::seconds(TRIGGER_RELEASE_PERIOD_SECONDS);
+        let enabled_ids: HashSet<_> = profile_triggers
+            .iter()
+            .filter(|profile_trigger| profile_trigger.is_enabled)
+            .map(|profile_trigger| profile_trigger.id)
+            .collect();
+        self.matched_at.retain(|trigger_id, matched_at| {
+            enabled_ids.contains(trigger_id) && now - *matched_at < release_period
+        });
+        let matched_at = &self.matched_at;
+        self.overridden_ids
+            .retain(|trigger_id| matched_at.contains_key(trigger_id));
+
+        let is_picked_by_hand = self
+            .switched_to
+            .is_some_and(|(_, profile_id)| profile_id != active_profile_id);
+        if is_picked_by_hand {
+            self.switched_to = None;
+            self.base_profile_id = None;
+            self.overridden_ids = self.matched_at.keys().copied().collect();
+            info!("Overrode profile triggers.");
+        }
+
+        let holding_trigger = profile_triggers
+            .iter()
+            .filter(|profile_trigger| {
+                self.matched_at.contains_key(&profile_trigger.id)
+                    && !self.overridden_ids.contains(&profile_trigger.id)
+            })
+            .max_by_key(|profile_trigger| (profile_trigger.priority, Reverse(profile_trigger.id)));
+        match holding_trigger {
+            Some(profile_trigger) => {
+                if self.switched_to.is_none() {
+                    self.base_profile_id = Some(active_profile_id);
+                }
+                self.switched_to = Some((profile_trigger.id, profile_trigger.profile_id));
+                (profile_trigger.profile_id != active_profile_id)
+                    .then_some(profile_trigger.profile_id)
+            }
+            None => {
+                self.switched_to.take()?;
+                self.base_profile_id
+                    .take()
+                    .filter(|&profile_id| profile_id != active_profile_id)
+            }
+        }
+    }
+
+    pub fn get_activation(
+        &self,
+        active_profile_id: i64,
+        profile_triggers: &[ProfileTrigger],
+        now: DateTime<Utc>,
+    ) -> ProfileActivation {
+        let profile_trigger = self
+            .switched_to
+            .filter(|&(_, profile_id)| profile_id == active_profile_id)
+            .and_then(|(trigger_id, _)| {
+                profile_triggers
+                    .iter()
+                    .find(|profile_trigger| profile_trigger.id == trigger_id)
+            });
+        let Some(profile_trigger) = profile_trigger else {
+            return ProfileActivation::Manual;
+        };
+
+        let trigger_id = profile_trigger.id;
+        let matcher = profile_trigger.matcher.to_string();
+        match self.matched_at.get(&trigger_id) {
+            Some(&matched_at) if matched_at < now => ProfileActivation::Held {
+                trigger_id,
+                matcher,
+                until: matched_at + chrono::Duration::seconds(TRIGGER_RELEASE_PERIOD_SECONDS),
+            },
+            _ => ProfileActivation::Triggered {
+                trigger_id,
+                matcher,
+            },
+        }
+    }
+}
@@ -1,13 +1,16 @@
 use iced::{
-    Alignment, Element, Font, Length, Task, font::Weight, widget::{column, scrollable, text}
+    Alignment, Element, Font, Length, Task, font::Weight, widget::{button, column, scrollable, text}
 };
+use tracing::error;
 
+use crate::logging::open_logs_directory;
 use crate::ui::{INITIAL_WINDOW_SIZE, IS_WINDOW_RESIZABLE, MAIN_WINDOW_NAME};
 
 pub fn run_error_ui(error: String) -> iced::Result {
     iced::application(
         move || IrAffinity {
             error: error.clone(),
+            logs_error: None,
         },
         IrAffinity::update,
         IrAffinity::view,
@@ -20,6 +23,7 @@ pub fn run_error_ui(error: String) -> iced::Result {
 
 struct IrAffinity {
     error: String,
+    logs_error: Option<String>,
 }
 
 impl IrAffinity {
@@ -31,7 +35,9 @@ impl IrAffinity {
         scrollable(
             column![
                 text("FAILED TO INITIALIZE!!!").font(bold),
-                text(&self.error).style(text::danger)
+                text(&self.error).style(text::danger),
+                button("Open Logs Folder").on_press(Message::ShouldOpenLogs),
+                self.logs_error.as_ref().map(|e| text(e).style(text::danger))
             ]
             .width(Length::Fill)
             .spacing(16)
@@ -41,10 +47,20 @@ impl IrAffinity {
         .into()
     }
 
-    fn update(&mut self, _message: Message) -> Task<Message> {
+    fn update(&mut self, message: Message) -> Task<Message> {
+        match message {
+            Message::ShouldOpenLogs => {
+                self.logs_error = open_logs_directory()
+                    .inspect_err(|e| error!("{:?}", e))
+                    .map_err(|e| e.get().to_string())
+                    .err();
+            }
+        }
         Task::none()
     }
 }
 
 #[derive(Debug, Clone)]
-enum Message {}
+enum Message {
+    ShouldOpenLogs,
+}
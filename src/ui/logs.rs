@@ -1,57 +1,147 @@
+use std::collections::VecDeque;
+
 use iced::{
-    Alignment, Element, Length,
-    widget::{column, container, rule, scrollable, text},
+    Alignment, Element, Length, Subscription,
+    widget::{column, container, pick_list, row, rule, scrollable, text, text_input},
 };
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+use tracing::Level;
+
+use crate::logging::LogRecord;
+
+/// Older records are dropped past this many.
+const LOG_RECORD_COUNT: usize = 500;
 
 #[derive(Debug, Clone)]
 pub struct MessageLog {
-    inner: Vec<String>,
+    /// Most recent first.
+    inner: VecDeque<LogRecord>,
+    level: Level,
+    /// Only records whose target starts with this are shown.
+    target: String,
+    search: String,
 }
 
 impl MessageLog {
     pub fn new() -> Self {
         Self {
-            inner: vec![
-                String::from("This is the message log."),
-                String::from("Hello There!"),
-            ],
+            inner: VecDeque::new(),
+            level: Level::INFO,
+            target: String::from(env!("CARGO_CRATE_NAME")),
+            search: String::new(),
         }
     }
 }
 
 impl MessageLog {
     pub fn view(&self) -> Element<'_, Message> {
+        let filters = row![
+            pick_list(
+                [Level::ERROR, Level::WARN, Level::INFO],
+                Some(self.level),
+                Message::ChangedLevel
+            )
+            .text_size(14),
+            text_input("Target", &self.target)
+                .on_input(Message::ChangedTarget)
+                .size(14),
+            text_input("Search", &self.search)
+                .on_input(Message::ChangedSearch)
+                .size(14)
+        ]
+        .spacing(4)
+        .align_y(Alignment::Center);
+
+        let log_records: Vec<_> = self
+            .inner
+            .iter()
+            .filter(|log_record| log_record.get_is_match(self.level, &self.target, &self.search))
+            .collect();
         let mut message_logs = column![];
-        for (message_index, message_log) in self.inner.iter().enumerate() {
-            message_logs = message_logs.push(text(message_log));
-            let is_last = message_index == self.inner.len() - 1;
+        for (message_index, log_record) in log_records.iter().enumerate() {
+            // Errors carry their backtrace after the first line.
+            let message = log_record.message.lines().next().unwrap_or_default();
+            let message_log = text(format!(
+                "{} {} {}: {message}",
+                log_record
+                    .at
+                    .with_timezone(&chrono::Local)
+                    .format("%H:%M:%S"),
+                log_record.level,
+                log_record.target
+            ))
+            .size(14);
+            message_logs = message_logs.push(match log_record.level {
+                Level::ERROR => message_log.style(text::danger),
+                Level::WARN => message_log.style(text::warning),
+                _ => message_log,
+            });
+            let is_last = message_index == log_records.len() - 1;
             if !is_last {
                 message_logs = message_logs.push(rule::horizontal(1));
             }
         }
 
         let logs_height = 85;
-        scrollable(
-            container(message_logs.spacing(8).wrap())
-                .width(Length::Fill)
-                .align_x(Alignment::Center)
-                .padding(8)
-                .style(container::secondary),
-        )
-        .width(Length::Fill)
-        .height(logs_height)
-        .auto_scroll(true)
+        column![
+            filters,
+            scrollable(
+                container(message_logs.spacing(8).wrap())
+                    .width(Length::Fill)
+                    .align_x(Alignment::Center)
+                    .padding(8)
+                    .style(container::secondary),
+            )
+            .width(Length::Fill)
+            .height(logs_height)
+            .auto_scroll(true)
+        ]
+        .spacing(4)
         .into()
     }
 
     pub fn update(&mut self, message: Message) {
         match message {
-            Message::Append(message) => self.inner.insert(0, message),
+            Message::Append(log_record) => {
+                self.inner.push_front(log_record);
+                self.inner.truncate(LOG_RECORD_COUNT);
+            }
+            Message::ChangedLevel(level) => self.level = level,
+            Message::ChangedTarget(target) => self.target = target,
+            Message::ChangedSearch(search) => self.search = search,
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
-    Append(String),
+    Append(LogRecord),
+    ChangedLevel(Level),
+    ChangedTarget(String),
+    ChangedSearch(String),
+}
+
+/// Records missed while lagging behind are skipped.
+fn watch_log_records(log_records: &SenderWrapper) -> impl Stream<Item = Message> + use<> {
+    BroadcastStream::new(log_records.1.subscribe())
+        .filter_map(|log_record| log_record.ok())
+        .map(Message::Append)
+}
+
+struct SenderWrapper(usize, broadcast::Sender<LogRecord>);
+
+impl std::hash::Hash for SenderWrapper {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+const LOG_RECORD_SENDER_SLOT: usize = 1;
+
+pub fn get_subscriptions(log_records: &broadcast::Sender<LogRecord>) -> Subscription<Message> {
+    Subscription::run_with(
+        SenderWrapper(LOG_RECORD_SENDER_SLOT, log_records.clone()),
+        watch_log_records,
+    )
 }
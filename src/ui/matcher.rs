@@ -0,0 +1,96 @@
+use crate::matcher::{MatchCondition, MatchConditionKind, ProcessMatcher};
+use iced::Alignment;
+use iced::Element;
+use iced::Length;
+use iced::widget::{button, checkbox, column, pick_list, row, text, text_input};
+
+pub fn view<'a>(matcher: &'a ProcessMatcher, placeholder: &'a str) -> Element<'a, Message> {
+    let mut condition_rows = column![].spacing(4);
+    for (index, condition) in matcher.conditions.iter().enumerate() {
+        condition_rows = condition_rows.push(
+            row![
+                pick_list(
+                    MatchConditionKind::get_all(),
+                    Some(condition.kind),
+                    move |kind| Message::ChangedKind { index, kind }
+                )
+                .text_size(14),
+                text_input(placeholder, &condition.value)
+                    .on_input(move |value| Message::ChangedValue { index, value })
+                    .size(14)
+                    .width(Length::Fill),
+                button(text("x").size(12)).on_press(Message::Remove(index))
+            ]
+            .spacing(4)
+            .align_y(Alignment::Center),
+        );
+    }
+    condition_rows =
+        condition_rows.push(button(text("Add Condition").size(14)).on_press(Message::Add));
+
+    // Wine and Steam's environment are only looked into on Linux.
+    let wine_section = cfg!(target_os = "linux").then(|| {
+        row![
+            checkbox(matcher.is_wine_aware)
+                .label("Wine/Proton")
+                .on_toggle(Message::ToggledWineAware)
+                .size(14)
+                .text_size(14),
+            text_input(
+                "Steam app ID",
+                matcher.steam_app_id.as_deref().unwrap_or_default()
+            )
+            .on_input(Message::ChangedSteamAppId)
+            .size(14)
+            .width(Length::Fill)
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center)
+    });
+
+    column![condition_rows, wine_section].spacing(4).into()
+}
+
+pub fn update(matcher: &mut ProcessMatcher, message: Message) {
+    match message {
+        Message::ChangedKind { index, kind } => {
+            if let Some(condition) = matcher.conditions.get_mut(index) {
+                condition.kind = kind;
+            }
+        }
+        Message::ChangedValue { index, value } => {
+            if let Some(condition) = matcher.conditions.get_mut(index) {
+                condition.value = value;
+            }
+        }
+        Message::Remove(index) => {
+            if index < matcher.conditions.len() {
+                matcher.conditions.remove(index);
+            }
+        }
+        Message::ToggledWineAware(is_wine_aware) => matcher.is_wine_aware = is_wine_aware,
+        Message::ChangedSteamAppId(steam_app_id) => {
+            let steam_app_id = steam_app_id.trim();
+            matcher.steam_app_id = (!steam_app_id.is_empty()).then(|| steam_app_id.to_string());
+        }
+        Message::Add => matcher
+            .conditions
+            .push(MatchCondition::new(MatchConditionKind::ExactName, "")),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    ChangedKind {
+        index: usize,
+        kind: MatchConditionKind,
+    },
+    ChangedValue {
+        index: usize,
+        value: String,
+    },
+    Remove(usize),
+    ToggledWineAware(bool),
+    ChangedSteamAppId(String),
+    Add,
+}
@@ -1,18 +1,21 @@
-use crate::ir::{DEFAULT_IRACING_SIMULATOR, DEFAULT_SIMULATOR_SPAWNERS};
-use crate::persistence::PersistentStore;
+use crate::errors::ResultBtAny;
+use crate::ipc::client::IpcClient;
+use crate::logging::{LogLevel, Logging, open_logs_directory};
+use crate::persistence::{PersistentStore, Profile, Rule, Settings};
+use crate::topology::CpuTopology;
 pub use crate::ui::errors::run_error_ui;
 use crate::ui::logs::MessageLog;
-use crate::worker::WorkerHeartbeat;
-use iced::font::Weight;
-use iced::widget::{button, column, rule, scrollable, text, text_input};
-use iced::{Alignment, Element, Font, Length, Subscription, Task};
+use crate::worker::WorkerCommand;
+use iced::widget::{button, checkbox, column, pick_list, row, rule, scrollable, text, text_input};
+use iced::{Alignment, Element, Length, Subscription, Task};
 use sqlx::SqlitePool;
 use status::WorkerStatus;
-use tokio::sync::watch;
 use tracing::error;
 
 mod errors;
 mod logs;
+mod matcher;
+mod rules;
 mod selection;
 mod status;
 
@@ -24,11 +27,22 @@ const IS_WINDOW_RESIZABLE: bool = false;
 
 pub fn run_initialized_ui(
     persistent_store: PersistentStore,
+    profiles: Vec<Profile>,
+    topology: CpuTopology,
     sqlite_pool: SqlitePool,
-    status_receiver: watch::Receiver<Option<WorkerHeartbeat>>,
+    logging: Logging,
 ) -> iced::Result {
+    let logging_2 = logging.clone();
     iced::application(
-        move || IrAffinity::new(&persistent_store, &sqlite_pool),
+        move || {
+            IrAffinity::new(
+                &persistent_store,
+                profiles.clone(),
+                &topology,
+                &sqlite_pool,
+                logging.clone(),
+            )
+        },
         IrAffinity::update,
         IrAffinity::view,
     )
@@ -37,8 +51,9 @@ pub fn run_initialized_ui(
     .resizable(IS_WINDOW_RESIZABLE)
     .subscription(move |_| {
         let subscriptions = vec![
-            selection::get_subscriptions().map(Message::CpuSelection),
-            status::get_subscriptions(&status_receiver).map(Message::WorkerStatus),
+            rules::get_subscriptions().map(Message::RuleList),
+            status::get_subscriptions().map(Message::WorkerStatus),
+            logs::get_subscriptions(logging_2.get_log_sender()).map(Message::Logs),
             get_subscriptions(),
         ];
         Subscription::batch(subscriptions)
@@ -47,11 +62,26 @@ pub fn run_initialized_ui(
 }
 
 struct IrAffinity {
-    spawner_name: String,
-    simulator_name: String,
-    cpu_selection: selection::CpuSelection,
+    /// That `rule_list` was loaded from.
+    profile_id: i64,
+    profiles: Vec<Profile>,
+    /// As typed, for duplicating into or renaming to.
+    profile_name: String,
+    /// Picked from those not active, the active profile can't be deleted.
+    deleted_profile: Option<Profile>,
+    topology: CpuTopology,
+    rule_list: rules::RuleList,
+    /// As last loaded or saved, what `rule_list` is saved over.
+    loaded_rules: Vec<Rule>,
+    /// Of a profile switched to while `rule_list` had unsaved edits, shown once
+    /// they're saved or discarded.
+    switched_store: Option<PersistentStore>,
+    settings: Settings,
+    /// As last loaded or saved, what `settings` is saved over.
+    loaded_settings: Settings,
     worker_status: status::WorkerStatus,
     message_log: logs::MessageLog,
+    logging: Logging,
     sqlite: SqlitePool,
     is_saving: bool,
     progress: usize,
@@ -59,13 +89,27 @@ struct IrAffinity {
 }
 
 impl IrAffinity {
-    fn new(persistent_store: &PersistentStore, sqlite_pool: &SqlitePool) -> Self {
+    fn new(
+        persistent_store: &PersistentStore,
+        profiles: Vec<Profile>,
+        topology: &CpuTopology,
+        sqlite_pool: &SqlitePool,
+        logging: Logging,
+    ) -> Self {
         Self {
-            spawner_name: persistent_store.spawner.clone(),
-            simulator_name: persistent_store.simulator.clone(),
-            cpu_selection: selection::CpuSelection::new(persistent_store.selections.clone()),
+            profile_id: persistent_store.profile_id,
+            profiles,
+            profile_name: String::new(),
+            deleted_profile: None,
+            topology: topology.clone(),
+            rule_list: rules::RuleList::new(&persistent_store.rules, topology.clone()),
+            loaded_rules: persistent_store.loaded_rules.clone(),
+            switched_store: None,
+            settings: persistent_store.settings.clone(),
+            loaded_settings: persistent_store.loaded_settings.clone(),
             worker_status: WorkerStatus::new(),
             message_log: MessageLog::new(),
+            logging,
             sqlite: sqlite_pool.clone(),
             progress: 0,
             is_saving: false,
@@ -73,35 +117,97 @@ impl IrAffinity {
         }
     }
 
-    fn view(&self) -> Element<'_, Message> {
-        let error_message = self.error.clone().map(|e| text(e).style(text::danger));
+    fn get_is_edited(&self) -> bool {
+        self.rule_list.to_rules() != self.loaded_rules
+    }
 
-        let bold = Font {
-            weight: Weight::Bold,
-            ..Font::default()
-        };
+    fn switch_to(&mut self, persistent_store: PersistentStore) {
+        self.profile_id = persistent_store.profile_id;
+        self.rule_list = rules::RuleList::new(&persistent_store.rules, self.topology.clone());
+        self.loaded_rules = persistent_store.loaded_rules;
+    }
 
-        let spawner_component = {
-            column![
-                text("iRacing UI").size(16).font(bold),
-                text_input(DEFAULT_SIMULATOR_SPAWNERS, &self.spawner_name)
-                    .on_input(Message::ChangedSpawner)
-                    .size(16)
+    fn get_profile_name(&self, profile_id: i64) -> &str {
+        self.profiles
+            .iter()
+            .find(|profile| profile.id == profile_id)
+            .map_or("", |profile| profile.name.as_str())
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let error_message = self.error.clone().map(|e| text(e).style(text::danger));
+        let switch_message = self.switched_store.as_ref().map(|switched_store| {
+            row![
+                text(format!(
+                    "Switched to {}, save or discard the edits to {}.",
+                    self.get_profile_name(switched_store.profile_id),
+                    self.get_profile_name(self.profile_id)
+                ))
+                .size(14)
+                .style(text::warning)
+                .width(Length::Fill),
+                button(text("Discard").size(12)).on_press(Message::ShouldDiscardEdits)
             ]
             .spacing(4)
-        };
+            .align_y(Alignment::Center)
+        });
 
-        let simulation_component = {
-            column![
-                text("iRacing Simulation").size(16).font(bold),
-                text_input(DEFAULT_IRACING_SIMULATOR, &self.simulator_name)
-                    .on_input(Message::ChangedSimulation)
-                    .size(16)
+        let active_profile = self.profiles.iter().find(|profile| profile.id == self.profile_id);
+        let profile_picker = row![
+            text("Profile: ").size(16),
+            pick_list(self.profiles.clone(), active_profile.cloned(), Message::SelectedProfile)
+                .text_size(16)
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center);
+        let is_profile_named = !self.profile_name.trim().is_empty();
+        let profile_editor = row![
+            text_input("Name", &self.profile_name)
+                .on_input(Message::ChangedProfileName)
+                .size(14),
+            button(text("Duplicate").size(12))
+                .on_press_maybe(is_profile_named.then_some(Message::ShouldDuplicateProfile)),
+            button(text("Rename").size(12))
+                .on_press_maybe(is_profile_named.then_some(Message::ShouldRenameProfile))
+        ]
+        .spacing(4)
+        .align_y(Alignment::Center);
+        let inactive_profiles: Vec<_> =
+            self.profiles.iter().filter(|profile| !profile.is_active).cloned().collect();
+        let is_deleted_profile_picked = self.deleted_profile.is_some();
+        let profile_deleter = (!inactive_profiles.is_empty()).then(|| {
+            row![
+                pick_list(inactive_profiles, self.deleted_profile.clone(), Message::PickedDeletedProfile)
+                    .placeholder("Another profile")
+                    .text_size(14),
+                button(text("Delete").size(12))
+                    .on_press_maybe(is_deleted_profile_picked.then_some(Message::ShouldDeleteProfile))
             ]
             .spacing(4)
-        };
+            .align_y(Alignment::Center)
+        });
 
-        let selection_component = self.cpu_selection.view().map(Message::CpuSelection);
+        let rules_component = self.rule_list.view().map(Message::RuleList);
+
+        // Process events come from a Linux only kernel interface.
+        let process_events_checkbox = cfg!(target_os = "linux").then(|| {
+            checkbox(self.settings.is_process_events_enabled)
+                .label("React to processes starting")
+                .on_toggle(Message::ToggledProcessEvents)
+                .size(16)
+        });
+
+        let log_level_picker = row![
+            text("Log level: ").size(16),
+            pick_list(
+                LogLevel::get_all(),
+                Some(self.settings.log_level),
+                Message::ChangedLogLevel
+            )
+            .text_size(16)
+        ]
+        .spacing(8)
+        .align_y(Alignment::Center);
 
         let ellipses = ".".repeat((self.progress / 5) % 3 + 1);
         let save_button = if self.is_saving {
@@ -112,19 +218,24 @@ impl IrAffinity {
 
         let status_component = self.worker_status.view().map(Message::WorkerStatus);
 
-        let message_log = self.message_log.view().map(Message::MessageLog);
+        let message_log = self.message_log.view().map(Message::Logs);
 
         scrollable(
             column![
                 error_message,
-                spawner_component,
-                simulation_component,
-                selection_component,
+                switch_message,
+                profile_picker,
+                profile_editor,
+                profile_deleter,
+                rules_component,
+                process_events_checkbox,
+                log_level_picker,
                 save_button,
                 rule::horizontal(2),
                 status_component,
                 rule::horizontal(2),
-                message_log
+                message_log,
+                button(text("Open Logs Folder").size(12)).on_press(Message::ShouldOpenLogs)
             ]
             .width(Length::Fill)
             .spacing(16)
@@ -136,33 +247,37 @@ impl IrAffinity {
 
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
-            Message::ChangedSpawner(spawner_name) => {
-                self.spawner_name = spawner_name;
+            Message::RuleList(message) => {
+                self.rule_list.update(message);
                 Task::none()
             }
-            Message::ChangedSimulation(simulator_name) => {
-                self.simulator_name = simulator_name;
+            Message::ToggledProcessEvents(is_process_events_enabled) => {
+                self.settings.is_process_events_enabled = is_process_events_enabled;
                 Task::none()
             }
-            Message::CpuSelection(message) => {
-                self.cpu_selection.update(message);
+            Message::ChangedLogLevel(log_level) => {
+                self.settings.log_level = log_level;
                 Task::none()
             }
             Message::ShouldSave => {
                 self.is_saving = true;
 
                 let sqlite_pool = self.sqlite.clone();
-                let spawner_name = self.spawner_name.clone();
-                let simulator_name = self.simulator_name.clone();
-                let cpu_selections = self.cpu_selection.get_inner().clone();
+                let profile_id = self.profile_id;
+                let rules = self.rule_list.to_rules();
+                let loaded_rules = self.loaded_rules.clone();
+                let settings = self.settings.clone();
+                let loaded_settings = self.loaded_settings.clone();
+                let logging = self.logging.clone();
                 Task::future(async move {
-                    let is_success = PersistentStore {
-                        spawner: spawner_name,
-                        simulator: simulator_name,
-                        selections: cpu_selections,
-                    }
+                    let log_level = settings.log_level;
+                    let mut persistent_store =
+                        PersistentStore { profile_id, rules, loaded_rules, settings, loaded_settings };
+                    let is_success = persistent_store
                     .save(&sqlite_pool)
                     .await
+                    .and_then(|_| logging.set_level(log_level))
+                    .map(|_| (persistent_store.loaded_rules, persistent_store.loaded_settings))
                     .inspect_err(|e| error!("{:?}", e))
                     // TODO: Update `bt_error` crate to have `Debug` and `Clone`.
                     .map_err(|e| e.get().to_string());
@@ -171,37 +286,207 @@ impl IrAffinity {
             }
             Message::ShouldSave_(is_success) => {
                 self.is_saving = false;
-                self.error = is_success.err();
+                match is_success {
+                    Ok((loaded_rules, loaded_settings)) => {
+                        self.loaded_rules = loaded_rules;
+                        self.loaded_settings = loaded_settings;
+                        if let Some(switched_store) = self.switched_store.take() {
+                            self.switch_to(switched_store);
+                        }
+                        self.error = None;
+                    }
+                    Err(e) => self.error = Some(e),
+                }
+                Task::none()
+            }
+            Message::SelectedProfile(profile) => {
+                let sqlite_pool = self.sqlite.clone();
+                let topology = self.topology.clone();
+                Task::future(async move {
+                    let is_success = async {
+                        PersistentStore::activate_profile(profile.id, &sqlite_pool).await?;
+                        let profiles = load_profiles(&topology, &sqlite_pool).await?;
+                        apply_now().await.map(|()| profiles)
+                    }
+                    .await
+                    .inspect_err(|e| error!("{:?}", e))
+                    .map_err(|e| e.get().to_string());
+                    Message::ShouldLoadProfiles_(is_success)
+                })
+            }
+            Message::ChangedProfileName(profile_name) => {
+                self.profile_name = profile_name;
+                Task::none()
+            }
+            Message::ShouldDuplicateProfile => {
+                let sqlite_pool = self.sqlite.clone();
+                let topology = self.topology.clone();
+                let profile_id = self.profile_id;
+                let profile_name = self.profile_name.trim().to_string();
+                Task::future(async move {
+                    let is_success = async {
+                        PersistentStore::duplicate_profile(profile_id, &profile_name, &sqlite_pool)
+                            .await?;
+                        load_profiles(&topology, &sqlite_pool).await
+                    }
+                    .await
+                    .inspect_err(|e| error!("{:?}", e))
+                    .map_err(|e| e.get().to_string());
+                    Message::ShouldLoadProfiles_(is_success)
+                })
+            }
+            Message::ShouldRenameProfile => {
+                let sqlite_pool = self.sqlite.clone();
+                let topology = self.topology.clone();
+                let profile_id = self.profile_id;
+                let profile_name = self.profile_name.trim().to_string();
+                Task::future(async move {
+                    let is_success = async {
+                        PersistentStore::rename_profile(profile_id, &profile_name, &sqlite_pool)
+                            .await?;
+                        load_profiles(&topology, &sqlite_pool).await
+                    }
+                    .await
+                    .inspect_err(|e| error!("{:?}", e))
+                    .map_err(|e| e.get().to_string());
+                    Message::ShouldLoadProfiles_(is_success)
+                })
+            }
+            Message::PickedDeletedProfile(profile) => {
+                self.deleted_profile = Some(profile);
+                Task::none()
+            }
+            Message::ShouldDeleteProfile => {
+                let Some(profile) = self.deleted_profile.take() else {
+                    return Task::none();
+                };
+                let sqlite_pool = self.sqlite.clone();
+                let topology = self.topology.clone();
+                Task::future(async move {
+                    let is_success = async {
+                        PersistentStore::delete_profile(profile.id, &sqlite_pool).await?;
+                        load_profiles(&topology, &sqlite_pool).await
+                    }
+                    .await
+                    .inspect_err(|e| error!("{:?}", e))
+                    .map_err(|e| e.get().to_string());
+                    Message::ShouldLoadProfiles_(is_success)
+                })
+            }
+            Message::ShouldLoadProfiles_(is_success) => {
+                match is_success {
+                    Ok((profiles, persistent_store)) => {
+                        // Unsaved edits are kept, and only left once saved or discarded.
+                        if persistent_store.profile_id == self.profile_id {
+                            self.switched_store = None;
+                        } else if self.get_is_edited() {
+                            self.switched_store = Some(persistent_store);
+                        } else {
+                            self.switched_store = None;
+                            self.switch_to(persistent_store);
+                        }
+                        self.profiles = profiles;
+                        self.profile_name.clear();
+                        self.deleted_profile = None;
+                        self.error = None;
+                    }
+                    Err(e) => self.error = Some(e),
+                }
                 Task::none()
             }
             Message::WorkerStatus(message) => {
-                self.worker_status.update(message);
+                // Follows profiles switched by triggers or from the CLI.
+                let is_profile_switched = match &message {
+                    status::Message::Connected(Some(worker_heartbeat))
+                    | status::Message::Heatbeat(Some(worker_heartbeat)) => {
+                        let active_profile_id = self
+                            .switched_store
+                            .as_ref()
+                            .map_or(self.profile_id, |switched_store| switched_store.profile_id);
+                        worker_heartbeat
+                            .get_profile()
+                            .is_some_and(|profile_report| profile_report.id != active_profile_id)
+                    }
+                    _ => false,
+                };
+                let status_task = self.worker_status.update(message).map(Message::WorkerStatus);
+                if !is_profile_switched {
+                    return status_task;
+                }
+
+                let sqlite_pool = self.sqlite.clone();
+                let topology = self.topology.clone();
+                let load_task = Task::future(async move {
+                    let is_success = load_profiles(&topology, &sqlite_pool)
+                        .await
+                        .inspect_err(|e| error!("{:?}", e))
+                        .map_err(|e| e.get().to_string());
+                    Message::ShouldLoadProfiles_(is_success)
+                });
+                Task::batch([status_task, load_task])
+            }
+            Message::ShouldDiscardEdits => {
+                if let Some(switched_store) = self.switched_store.take() {
+                    self.switch_to(switched_store);
+                }
                 Task::none()
             }
             Message::Progress => {
                 self.progress = self.progress.wrapping_add(1);
                 Task::none()
             }
-            Message::MessageLog(message) => {
+            Message::Logs(message) => {
                 self.message_log.update(message);
                 Task::none()
             }
+            Message::ShouldOpenLogs => {
+                self.error = open_logs_directory()
+                    .inspect_err(|e| error!("{:?}", e))
+                    .map_err(|e| e.get().to_string())
+                    .err();
+                Task::none()
+            }
         }
     }
 }
 
 #[derive(Debug, Clone)]
 enum Message {
-    ChangedSpawner(String),
-    ChangedSimulation(String),
-    CpuSelection(selection::Message),
+    RuleList(rules::Message),
+    SelectedProfile(Profile),
+    ChangedProfileName(String),
+    ShouldDuplicateProfile,
+    ShouldRenameProfile,
+    PickedDeletedProfile(Profile),
+    ShouldDeleteProfile,
+    ShouldLoadProfiles_(Result<(Vec<Profile>, PersistentStore), String>),
+    ToggledProcessEvents(bool),
+    ChangedLogLevel(LogLevel),
     ShouldSave,
-    ShouldSave_(Result<(), String>),
+    ShouldSave_(Result<(Vec<Rule>, Settings), String>),
+    ShouldDiscardEdits,
     WorkerStatus(status::Message),
-    MessageLog(logs::Message),
+    Logs(logs::Message),
+    ShouldOpenLogs,
     Progress,
 }
 
+/// With the rules of whichever is active.
+async fn load_profiles(
+    topology: &CpuTopology,
+    sqlite_pool: &SqlitePool,
+) -> ResultBtAny<(Vec<Profile>, PersistentStore)> {
+    let profiles = PersistentStore::load_profiles(sqlite_pool).await?;
+    let persistent_store = PersistentStore::load(topology, sqlite_pool).await?;
+    Ok((profiles, persistent_store))
+}
+
+/// So a switched profile is applied without waiting out the worker's cooldown. Starts the daemon
+/// if it's not running, like the status subscription.
+async fn apply_now() -> ResultBtAny<()> {
+    IpcClient::connect_or_start_daemon().await?.send_command(WorkerCommand::Apply).await
+}
+
 const PROGRESS_COOLDOWN_MILLISECONDS: u64 = 100;
 
 fn get_subscriptions() -> Subscription<Message> {
@@ -0,0 +1,297 @@
+use crate::ir::{DEFAULT_IRACING_SIMULATOR, DEFAULT_SIMULATOR_SPAWNERS};
+use crate::persistence::{EnforcementStrategy, Rule, SpawnerReset};
+use crate::topology::{CpuTopology, parse_cpu_list};
+use crate::ui::matcher;
+use crate::ui::selection::{self, CpuSelection};
+use iced::Alignment;
+use iced::Element;
+use iced::Font;
+use iced::Length;
+use iced::Subscription;
+use iced::font::Weight;
+use iced::widget::{button, checkbox, column, pick_list, row, text, text_input};
+
+struct RuleEditor {
+    rule: Rule,
+    cpu_selection: CpuSelection,
+    /// As typed, only applied to `SpawnerReset::Custom` once it parses.
+    spawner_reset_cpus: String,
+}
+
+impl RuleEditor {
+    fn new(rule: Rule, topology: &CpuTopology) -> Self {
+        let cpu_selection = CpuSelection::new(
+            rule.selections.clone(),
+            rule.preset.clone(),
+            topology.clone(),
+        );
+        let spawner_reset_cpus = match &rule.spawner_reset {
+            SpawnerReset::Custom { cpus } => cpus
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            _ => String::new(),
+        };
+        Self {
+            rule,
+            cpu_selection,
+            spawner_reset_cpus,
+        }
+    }
+
+    fn to_rule(&self) -> Rule {
+        Rule {
+            preset: self.cpu_selection.get_preset().clone(),
+            selections: self.cpu_selection.get_inner().clone(),
+            ..self.rule.clone()
+        }
+    }
+}
+
+pub struct RuleList {
+    editors: Vec<RuleEditor>,
+    selected_index: usize,
+    topology: CpuTopology,
+}
+
+impl RuleList {
+    pub fn new(rules: &[Rule], topology: CpuTopology) -> Self {
+        let editors = rules
+            .iter()
+            .map(|rule| RuleEditor::new(rule.clone(), &topology))
+            .collect();
+        Self {
+            editors,
+            selected_index: 0,
+            topology,
+        }
+    }
+}
+
+impl RuleList {
+    /// In evaluation order, earlier rules claim a process first.
+    pub fn to_rules(&self) -> Vec<Rule> {
+        self.editors.iter().map(RuleEditor::to_rule).collect()
+    }
+
+    fn get_next_id(&self) -> i64 {
+        self.editors
+            .iter()
+            .map(|editor| editor.rule.id)
+            .max()
+            .unwrap_or(Rule::BUILT_IN_ID)
+            + 1
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let bold = Font {
+            weight: Weight::Bold,
+            ..Font::default()
+        };
+
+        let mut rule_rows = column![text("Rules").size(16).font(bold)].spacing(4);
+        for (index, editor) in self.editors.iter().enumerate() {
+            let is_selected = index == self.selected_index;
+            let rule_name = if is_selected {
+                text(&editor.rule.name).size(16).font(bold)
+            } else {
+                text(&editor.rule.name).size(16)
+            };
+            let remove_button = (!editor.rule.get_is_built_in())
+                .then(|| button(text("x").size(12)).on_press(Message::Remove(index)));
+            rule_rows = rule_rows.push(
+                row![
+                    checkbox(editor.rule.is_enabled)
+                        .on_toggle(move |is_enabled| Message::ToggleEnabled { index, is_enabled })
+                        .size(16),
+                    button(rule_name)
+                        .on_press(Message::Select(index))
+                        .style(button::text)
+                        .width(Length::Fill),
+                    button(text("^").size(12))
+                        .on_press_maybe((index > 0).then_some(Message::MoveUp(index))),
+                    button(text("v").size(12)).on_press_maybe(
+                        (index + 1 < self.editors.len()).then_some(Message::MoveDown(index))
+                    ),
+                    remove_button
+                ]
+                .spacing(4)
+                .align_y(Alignment::Center),
+            );
+        }
+        rule_rows = rule_rows.push(button(text("Add Rule").size(16)).on_press(Message::Add));
+
+        let rule_editor = self.editors.get(self.selected_index).map(|editor| {
+            let spawner_component = editor.rule.spawner_matcher.as_ref().map(|spawner_matcher| {
+                column![
+                    text("Spawner").size(16).font(bold),
+                    matcher::view(spawner_matcher, DEFAULT_SIMULATOR_SPAWNERS)
+                        .map(Message::ChangedSpawner),
+                    row![
+                        text("Apply: ").size(16),
+                        pick_list(
+                            EnforcementStrategy::get_all(),
+                            Some(editor.rule.enforcement_strategy),
+                            Message::ChangedEnforcementStrategy
+                        )
+                        .text_size(16)
+                    ]
+                    .spacing(8)
+                    .align_y(Alignment::Center),
+                    row![
+                        text("Then reset to: ").size(16),
+                        pick_list(
+                            SpawnerReset::get_all(),
+                            Some(editor.rule.spawner_reset.clone()),
+                            Message::ChangedSpawnerReset
+                        )
+                        .text_size(16),
+                        matches!(editor.rule.spawner_reset, SpawnerReset::Custom { .. }).then(
+                            || {
+                                text_input("0-3,8", &editor.spawner_reset_cpus)
+                                    .on_input(Message::ChangedSpawnerResetCpus)
+                                    .size(16)
+                                    .width(Length::Fill)
+                            }
+                        )
+                    ]
+                    .spacing(8)
+                    .align_y(Alignment::Center)
+                ]
+                .spacing(4)
+            });
+
+            column![
+                text("Name").size(16).font(bold),
+                text_input("Rule", &editor.rule.name)
+                    .on_input(Message::ChangedName)
+                    .size(16),
+                spawner_component,
+                text("Process").size(16).font(bold),
+                matcher::view(&editor.rule.matcher, DEFAULT_IRACING_SIMULATOR)
+                    .map(Message::ChangedProcess),
+                editor.cpu_selection.view().map(Message::CpuSelection)
+            ]
+            .spacing(4)
+        });
+
+        column![rule_rows, rule_editor]
+            .width(Length::Fill)
+            .spacing(16)
+            .into()
+    }
+
+    pub fn update(&mut self, message: Message) {
+        match message {
+            Message::Select(index) => self.selected_index = index,
+            Message::ToggleEnabled { index, is_enabled } => {
+                if let Some(editor) = self.editors.get_mut(index) {
+                    editor.rule.is_enabled = is_enabled;
+                }
+            }
+            Message::MoveUp(index) => {
+                if index > 0 && index < self.editors.len() {
+                    self.editors.swap(index - 1, index);
+                    self.selected_index = index - 1;
+                }
+            }
+            Message::MoveDown(index) => {
+                if index + 1 < self.editors.len() {
+                    self.editors.swap(index, index + 1);
+                    self.selected_index = index + 1;
+                }
+            }
+            Message::Remove(index) => {
+                let is_removable = self
+                    .editors
+                    .get(index)
+                    .is_some_and(|editor| !editor.rule.get_is_built_in());
+                if is_removable {
+                    self.editors.remove(index);
+                    self.selected_index = self
+                        .selected_index
+                        .min(self.editors.len().saturating_sub(1));
+                }
+            }
+            Message::Add => {
+                let rule = Rule::new_custom(self.get_next_id(), &self.topology);
+                self.editors.push(RuleEditor::new(rule, &self.topology));
+                self.selected_index = self.editors.len() - 1;
+            }
+            Message::ChangedName(name) => {
+                if let Some(editor) = self.editors.get_mut(self.selected_index) {
+                    editor.rule.name = name;
+                }
+            }
+            Message::ChangedProcess(message) => {
+                if let Some(editor) = self.editors.get_mut(self.selected_index) {
+                    matcher::update(&mut editor.rule.matcher, message);
+                }
+            }
+            Message::ChangedSpawner(message) => {
+                let spawner_matcher = self
+                    .editors
+                    .get_mut(self.selected_index)
+                    .and_then(|editor| editor.rule.spawner_matcher.as_mut());
+                if let Some(spawner_matcher) = spawner_matcher {
+                    matcher::update(spawner_matcher, message);
+                }
+            }
+            Message::ChangedEnforcementStrategy(enforcement_strategy) => {
+                if let Some(editor) = self.editors.get_mut(self.selected_index) {
+                    editor.rule.enforcement_strategy = enforcement_strategy;
+                }
+            }
+            Message::ChangedSpawnerReset(spawner_reset) => {
+                if let Some(editor) = self.editors.get_mut(self.selected_index) {
+                    editor.rule.spawner_reset = match spawner_reset {
+                        SpawnerReset::Custom { .. } => SpawnerReset::Custom {
+                            cpus: parse_cpu_list(&editor.spawner_reset_cpus)
+                                .unwrap_or_default()
+                                .into_iter()
+                                .collect(),
+                        },
+                        spawner_reset => spawner_reset,
+                    };
+                }
+            }
+            Message::ChangedSpawnerResetCpus(cpu_list) => {
+                if let Some(editor) = self.editors.get_mut(self.selected_index) {
+                    if let Ok(cpus) = parse_cpu_list(&cpu_list) {
+                        editor.rule.spawner_reset = SpawnerReset::Custom {
+                            cpus: cpus.into_iter().collect(),
+                        };
+                    }
+                    editor.spawner_reset_cpus = cpu_list;
+                }
+            }
+            Message::CpuSelection(message) => {
+                if let Some(editor) = self.editors.get_mut(self.selected_index) {
+                    editor.cpu_selection.update(message);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Select(usize),
+    ToggleEnabled { index: usize, is_enabled: bool },
+    MoveUp(usize),
+    MoveDown(usize),
+    Remove(usize),
+    Add,
+    ChangedName(String),
+    ChangedProcess(matcher::Message),
+    ChangedSpawner(matcher::Message),
+    ChangedEnforcementStrategy(EnforcementStrategy),
+    ChangedSpawnerReset(SpawnerReset),
+    ChangedSpawnerResetCpus(String),
+    CpuSelection(selection::Message),
+}
+
+pub fn get_subscriptions() -> Subscription<Message> {
+    selection::get_subscriptions().map(Message::CpuSelection)
+}
@@ -1,4 +1,6 @@
 use crate::persistence::CpuSelections;
+use crate::selections::SelectionPreset;
+use crate::topology::CpuTopology;
 use iced::Alignment;
 use iced::Element;
 use iced::Font;
@@ -7,18 +9,26 @@ use iced::Subscription;
 use iced::alignment::Horizontal;
 use iced::font::Weight;
 use iced::widget::scrollable::Scrollbar;
-use iced::widget::{checkbox, column, container, scrollable, text};
+use iced::widget::{checkbox, column, container, pick_list, row, scrollable, text, text_input};
 
 pub struct CpuSelection {
     inner: CpuSelections,
+    preset: SelectionPreset,
+    topology: CpuTopology,
     progress: usize,
     error: Option<String>,
 }
 
 impl CpuSelection {
-    pub fn new(cpu_selections: CpuSelections) -> Self {
+    pub fn new(
+        cpu_selections: CpuSelections,
+        preset: SelectionPreset,
+        topology: CpuTopology,
+    ) -> Self {
         Self {
             inner: cpu_selections,
+            preset,
+            topology,
             progress: 0,
             error: None,
         }
@@ -30,6 +40,18 @@ impl CpuSelection {
         &self.inner
     }
 
+    pub fn get_preset(&self) -> &SelectionPreset {
+        &self.preset
+    }
+
+    fn apply_preset(&mut self, preset: SelectionPreset) {
+        if let Some(cpu_selections) = preset.resolve(&self.topology) {
+            self.inner = CpuSelections::new_preselected(cpu_selections, self.inner.get_cpu_count());
+        }
+        self.preset = preset;
+        self.error = None;
+    }
+
     pub fn view(&self) -> Element<'_, Message> {
         let error_message = self
             .error
@@ -46,16 +68,39 @@ impl CpuSelection {
                 .align_x(Horizontal::Left)
         };
 
-        // TODO: Link the numbers, also, two cores will look weird, etc.
+        let preset_section = {
+            let preset_picker = pick_list(
+                SelectionPreset::get_all(),
+                Some(self.preset.clone()),
+                Message::ChangedPreset,
+            )
+            .text_size(16);
+            let core_count_input = match &self.preset {
+                SelectionPreset::LastPhysicalCores { count } => Some(
+                    text_input("N", &count.to_string())
+                        .on_input(Message::ChangedLastCoreCount)
+                        .size(16)
+                        .width(48),
+                ),
+                _ => None,
+            };
+            row![text("Preset: ").size(16), preset_picker, core_count_input]
+                .spacing(8)
+                .align_y(Alignment::Center)
+                .width(Length::Fill)
+        };
+
+        // TODO: Two cores will look weird, etc.
         let controls_height = 16 * 2 + 8 * 2 + 32;
         let controls_section = {
             let mut cpu_checkboxes = column![];
             for cpu_id in 0..self.inner.get_cpu_count() {
                 let is_toggled = self.inner.get_is_selected(&cpu_id);
-                let mut cpu_checkbox = checkbox(is_toggled)
-                    .label(format!("CPU {cpu_id}"))
-                    .size(16)
-                    .text_size(16);
+                let cpu_label = match self.topology.get_cpu(cpu_id) {
+                    Some(cpu) => format!("CPU {cpu_id} (C{})", cpu.core),
+                    None => format!("CPU {cpu_id}"),
+                };
+                let mut cpu_checkbox = checkbox(is_toggled).label(cpu_label).size(16).text_size(16);
                 cpu_checkbox = cpu_checkbox.on_toggle(move |should_activate| Message::Toggle {
                     cpu_id,
                     should_activate,
@@ -76,11 +121,16 @@ impl CpuSelection {
             .direction(scrollable::Direction::Horizontal(Scrollbar::new()))
         };
 
-        column![error_message, title_section, controls_section]
-            .width(Length::Fill)
-            .spacing(8)
-            .align_x(Alignment::Center)
-            .into()
+        column![
+            error_message,
+            title_section,
+            preset_section,
+            controls_section
+        ]
+        .width(Length::Fill)
+        .spacing(8)
+        .align_x(Alignment::Center)
+        .into()
     }
 
     pub fn update(&mut self, message: Message) {
@@ -91,8 +141,15 @@ impl CpuSelection {
             } => {
                 if let Err(e) = self.inner.toggle_selection(cpu_id, should_activate) {
                     self.error = Some(e.get().to_string());
+                } else {
+                    self.preset = SelectionPreset::Custom;
                 }
             }
+            Message::ChangedPreset(preset) => self.apply_preset(preset),
+            Message::ChangedLastCoreCount(count) => match count.parse() {
+                Ok(count) => self.apply_preset(SelectionPreset::LastPhysicalCores { count }),
+                Err(_) => self.error = Some(format!("`{count}` isn't a core count.")),
+            },
             Message::Progress => self.progress = self.progress.wrapping_add(1),
         }
     }
@@ -104,6 +161,8 @@ pub enum Message {
         cpu_id: usize,
         should_activate: bool,
     },
+    ChangedPreset(SelectionPreset),
+    ChangedLastCoreCount(String),
     Progress,
 }
 
@@ -1,24 +1,48 @@
-use crate::worker::WorkerHeartbeat;
+use std::collections::VecDeque;
+
+use crate::errors::ResultBtAny;
+use crate::ipc::IpcNotification;
+use crate::ipc::client::IpcClient;
+use crate::tracking::ProcessKey;
+use crate::worker::{WorkerCommand, WorkerHeartbeat};
+use iced::Alignment;
 use iced::font::Weight;
-use iced::widget::{column, row, text};
-use iced::{Element, Font, Length, Subscription};
-use tokio::sync::watch;
-use tokio_stream::StreamExt;
-use tokio_stream::wrappers::WatchStream;
+use iced::futures::{SinkExt, Stream, channel::mpsc};
+use iced::widget::{button, column, row, text};
+use iced::{Element, Font, Length, Subscription, Task};
+use tracing::{error, info};
+
+/// How many distinct worker errors are remembered.
+const ERROR_HISTORY_COUNT: usize = 10;
+
+/// Heartbeats that carried the same error.
+#[derive(Debug, Clone)]
+struct ErrorRecord {
+    message: String,
+    first_at: chrono::DateTime<chrono::Utc>,
+    last_at: chrono::DateTime<chrono::Utc>,
+    count: usize,
+}
 
 #[derive(Debug, Clone)]
 pub struct WorkerStatus {
     last: Option<WorkerHeartbeat>,
+    /// To the daemon, which is what's running the worker.
+    is_connected: bool,
     progress: usize,
     error: Option<String>,
+    /// Most recent first.
+    error_history: VecDeque<ErrorRecord>,
 }
 
 impl WorkerStatus {
     pub fn new() -> Self {
         Self {
             last: None,
+            is_connected: false,
             progress: 0,
             error: None,
+            error_history: VecDeque::new(),
         }
     }
 }
@@ -28,97 +52,381 @@ impl WorkerStatus {
         ".".repeat(self.progress % 3 + 1)
     }
 
+    fn record_error(&mut self, message: &str, at: chrono::DateTime<chrono::Utc>) {
+        let error_index = self
+            .error_history
+            .iter()
+            .position(|error_record| error_record.message == message);
+        let error_record = match error_index.and_then(|index| self.error_history.remove(index)) {
+            Some(error_record) => ErrorRecord {
+                last_at: at,
+                count: error_record.count + 1,
+                ..error_record
+            },
+            None => ErrorRecord {
+                message: message.to_string(),
+                first_at: at,
+                last_at: at,
+                count: 1,
+            },
+        };
+        self.error_history.push_front(error_record);
+        self.error_history.truncate(ERROR_HISTORY_COUNT);
+    }
+
+    /// Plain text to paste into a bug report.
+    fn get_error_details(&self) -> String {
+        let mut error_details = format!(
+            "Ir Affinity {} on {}\nCurrent error: {}\n",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            self.error.as_deref().unwrap_or("none")
+        );
+        for error_record in &self.error_history {
+            error_details.push_str(&format!(
+                "{} to {} ({}x): {}\n",
+                error_record.first_at.to_rfc3339(),
+                error_record.last_at.to_rfc3339(),
+                error_record.count,
+                error_record.message
+            ));
+        }
+        error_details
+    }
+
     pub fn view(&self) -> Element<'_, Message> {
         let progress_ellipses = self.get_progress_ellipses();
 
-        let running_status = if let Some(last_heartbeat) = &self.last {
-            if !last_heartbeat.get_is_stale() {
-                text(format!("Running{}", progress_ellipses)).style(text::success)
-            } else {
+        let running_status = match (self.is_connected, &self.last) {
+            (true, Some(_)) => text(format!("Running{}", progress_ellipses)).style(text::success),
+            (false, Some(_)) => {
                 text(format!("Lost connection{}", progress_ellipses)).style(text::warning)
             }
-        } else {
-            text(format!("Starting{}", progress_ellipses))
+            (_, None) => text(format!("Starting{}", progress_ellipses)),
         };
 
+        let is_paused = self
+            .last
+            .as_ref()
+            .is_some_and(WorkerHeartbeat::get_is_paused);
         let configuration_status = if let Some(last_heartbeat) = &self.last {
-            match (
-                last_heartbeat.get_is_synced(),
-                last_heartbeat.get_is_stale(),
-            ) {
-                (Some(true), true) => text("Likely syncd"),
-                (Some(true), false) => text("Synced").style(text::success),
-                (Some(false), true) => text("Likely unsynced").style(text::warning),
-                (Some(false), false) => text("Unsynced").style(text::warning),
-                (None, true) | (None, false) => text("N/A"),
+            if is_paused {
+                text("Paused").style(text::warning)
+            } else {
+                match (
+                    last_heartbeat.get_is_synced(),
+                    last_heartbeat.get_is_stale(),
+                ) {
+                    (Some(true), true) => text("Likely syncd"),
+                    (Some(true), false) => text("Synced").style(text::success),
+                    (Some(false), true) => text("Likely unsynced").style(text::warning),
+                    (Some(false), false) => text("Unsynced").style(text::warning),
+                    (None, true) | (None, false) => text("N/A"),
+                }
             }
         } else {
             text("N/A")
         };
 
+        let enforcement_status = match &self.last {
+            Some(last_heartbeat) if !last_heartbeat.get_enforcements().is_empty() => text(
+                last_heartbeat
+                    .get_enforcements()
+                    .iter()
+                    .map(|enforcement| {
+                        format!("{} ({})", enforcement.rule_name, enforcement.strategy)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            _ => text("N/A"),
+        };
+
+        let profile_status = match self.last.as_ref().and_then(WorkerHeartbeat::get_profile) {
+            Some(profile_report) => text(format!(
+                "{} ({})",
+                profile_report.name, profile_report.activation
+            )),
+            None => text("N/A"),
+        };
+
+        let scan_status = match &self.last {
+            Some(last_heartbeat) => {
+                let scan_stats = last_heartbeat.get_scan_stats();
+                let scan_kind = if scan_stats.is_full {
+                    "full"
+                } else {
+                    "partial"
+                };
+                text(format!(
+                    "{} of {} processes in {:.1} ms ({scan_kind})",
+                    scan_stats.refreshed_count,
+                    scan_stats.process_count,
+                    scan_stats.duration.as_secs_f64() * 1000.0
+                ))
+            }
+            None => text("N/A"),
+        };
+
+        let format_time = |at: &chrono::DateTime<chrono::Utc>| {
+            at.with_timezone(&chrono::Local)
+                .format("%H:%M:%S")
+                .to_string()
+        };
+        let current_error = self
+            .error
+            .as_ref()
+            .map(|e| text(format!("Error: {e}")).style(text::danger));
+        let mut error_rows = column![];
+        for error_record in &self.error_history {
+            error_rows = error_rows.push(
+                text(format!(
+                    "{} ({}x): {}",
+                    format_time(&error_record.last_at),
+                    error_record.count,
+                    error_record.message
+                ))
+                .size(14)
+                .style(text::secondary),
+            );
+        }
+        let error_component = (!self.error_history.is_empty()).then(|| {
+            column![
+                current_error,
+                error_rows,
+                button(text("Copy Details").size(12)).on_press(Message::ShouldCopyErrors)
+            ]
+            .spacing(4)
+        });
+
+        let mut rule_rows = column![];
+        if let Some(last_heartbeat) = &self.last {
+            for rule_report in last_heartbeat.get_rule_reports() {
+                let rule_status = match rule_report.is_synced {
+                    Some(true) => text(format!("{}: synced", rule_report.rule_name)).size(14),
+                    Some(false) => text(format!("{}: unsynced", rule_report.rule_name))
+                        .size(14)
+                        .style(text::warning),
+                    None => text(format!("{}: N/A", rule_report.rule_name)).size(14),
+                };
+                rule_rows = rule_rows.push(rule_status);
+                for process_report in &rule_report.process_reports {
+                    let observed_selections = process_report
+                        .observed_selections
+                        .as_ref()
+                        .map_or(String::from("not read"), ToString::to_string);
+                    let mut process_status = format!(
+                        "    #{} {}: wants {}; had {observed_selections}",
+                        process_report.process_id,
+                        process_report.action,
+                        process_report.desired_selections
+                    );
+                    if let Some(failure) = &process_report.failure {
+                        process_status
+                            .push_str(&format!("; {}, {}", failure.kind, failure.message));
+                    }
+                    rule_rows = rule_rows.push(if process_report.action.get_is_synced() {
+                        text(process_status).size(14)
+                    } else {
+                        text(process_status).size(14).style(text::warning)
+                    });
+                }
+            }
+        }
+
+        let mut process_rows = column![];
+        if let Some(last_heartbeat) = &self.last {
+            for tracked_process in last_heartbeat.get_tracked_processes() {
+                let applied_at = tracked_process
+                    .applied_at
+                    .as_ref()
+                    .map_or(String::from("never"), format_time);
+                let mut process_status = format!(
+                    "{} #{}: seen {}, applied {applied_at}, drifted {}x",
+                    tracked_process.rule_name,
+                    tracked_process.process_id,
+                    format_time(&tracked_process.first_seen_at),
+                    tracked_process.drift_count
+                );
+                if let Some(failure) = &tracked_process.failure {
+                    process_status
+                        .push_str(&format!(", failed {}x ({})", failure.count, failure.kind));
+                    match &failure.retry_at {
+                        Some(retry_at) => process_status
+                            .push_str(&format!(", retrying {}", format_time(retry_at))),
+                        None => process_status.push_str(", quarantined"),
+                    }
+                }
+                if let Some(exited_at) = &tracked_process.exited_at {
+                    process_status.push_str(&format!(", exited {}", format_time(exited_at)));
+                }
+                let process_status = if tracked_process.exited_at.is_some() {
+                    text(process_status).size(14).style(text::secondary)
+                } else if tracked_process.failure.is_some() {
+                    text(process_status).size(14).style(text::warning)
+                } else {
+                    text(process_status).size(14)
+                };
+                let is_quarantined = tracked_process.exited_at.is_none()
+                    && tracked_process
+                        .failure
+                        .as_ref()
+                        .is_some_and(|failure| failure.get_is_quarantined());
+                let process_key = (tracked_process.process_id, tracked_process.start_time);
+                let retry_button = is_quarantined.then(|| {
+                    button(text("Retry Now").size(12)).on_press(Message::ShouldRetry(process_key))
+                });
+                process_rows = process_rows.push(
+                    row![process_status, retry_button]
+                        .spacing(4)
+                        .align_y(Alignment::Center),
+                );
+            }
+        }
+
         let bold = Font {
             weight: Weight::Bold,
             ..Font::default()
         };
         let view = column![
             row![text("Worker Status: ").font(bold), running_status],
-            row![text("Config Status: ").font(bold), configuration_status]
+            row![text("Config Status: ").font(bold), configuration_status],
+            row![text("Profile: ").font(bold), profile_status],
+            error_component,
+            row![text("Enforced: ").font(bold), enforcement_status],
+            row![text("Last Scan: ").font(bold), scan_status],
+            text("Rules").font(bold),
+            rule_rows,
+            text("Processes").font(bold),
+            process_rows,
+            if is_paused {
+                button("Resume").on_press(Message::ShouldResume)
+            } else {
+                button("Restore Originals").on_press(Message::ShouldRestoreOriginals)
+            }
         ]
+        .spacing(4)
         .width(Length::Fill);
 
         view.into()
     }
 
-    pub fn update(&mut self, message: Message) {
+    pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
+            Message::Connected(worker_heartbeat) => {
+                self.is_connected = true;
+                return self.update(Message::Heatbeat(worker_heartbeat));
+            }
+            Message::Disconnected => self.is_connected = false,
             Message::Heatbeat(worker_heartbeat) => {
                 self.last = worker_heartbeat.clone();
                 self.error = self.last.as_ref().and_then(|beat| beat.get_error().clone());
+                if let (Some(e), Some(last_heartbeat)) = (self.error.clone(), &self.last) {
+                    self.record_error(&e, *last_heartbeat.get_at());
+                }
             }
             Message::Progress => {
                 self.progress = self.progress.wrapping_add(1);
             }
+            Message::ShouldRestoreOriginals => return send(WorkerCommand::RestoreOriginals),
+            Message::ShouldResume => return send(WorkerCommand::Resume),
+            Message::ShouldRetry(process_key) => {
+                return send(WorkerCommand::RetryProcess(process_key));
+            }
+            Message::ShouldCopyErrors => return iced::clipboard::write(self.get_error_details()),
+            Message::ShouldFocus => {
+                info!("Bringing window forward.");
+                return iced::window::latest().and_then(|id| {
+                    Task::batch([
+                        iced::window::minimize(id, false),
+                        iced::window::gain_focus(id),
+                    ])
+                });
+            }
         }
+        Task::none()
     }
 }
 
+/// Over its own connection, so it doesn't wait on the subscription's.
+fn send(worker_command: WorkerCommand) -> Task<Message> {
+    Task::future(async move {
+        _ = async {
+            IpcClient::connect()
+                .await?
+                .send_command(worker_command)
+                .await
+        }
+        .await
+        .inspect_err(|e| error!("{:?}", e));
+    })
+    .discard()
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
+    /// With the daemon's last heartbeat.
+    Connected(Option<WorkerHeartbeat>),
+    Disconnected,
     Heatbeat(Option<WorkerHeartbeat>),
     Progress,
+    ShouldRestoreOriginals,
+    ShouldResume,
+    ShouldRetry(ProcessKey),
+    ShouldCopyErrors,
+    /// Another launch handed off to this window.
+    ShouldFocus,
 }
 
-#[allow(clippy::type_complexity)]
-fn watch_worker_status(
-    worker_status: &ReceiverWrapper,
-) -> tokio_stream::adapters::Map<
-    WatchStream<Option<WorkerHeartbeat>>,
-    fn(Option<WorkerHeartbeat>) -> Message,
-> {
-    WatchStream::from_changes(worker_status.1.clone()).map(Message::Heatbeat)
-}
+const PROGRESS_COOLDOWN_SECONDS: u64 = 1;
 
-struct ReceiverWrapper(usize, watch::Receiver<Option<WorkerHeartbeat>>);
+const RECONNECT_COOLDOWN_SECONDS: u64 = 2;
 
-impl std::hash::Hash for ReceiverWrapper {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.hash(state);
-    }
+/// Reconnects for as long as the window's open, starting the daemon when it's
+/// not running.
+fn watch_daemon() -> impl Stream<Item = Message> {
+    iced::stream::channel(1, async |mut output: mpsc::Sender<Message>| {
+        loop {
+            _ = watch_heartbeats(&mut output)
+                .await
+                .inspect_err(|e| error!("{:?}", e));
+            info!("Disconnected from daemon.");
+            if output.send(Message::Disconnected).await.is_err() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(RECONNECT_COOLDOWN_SECONDS)).await;
+        }
+    })
 }
 
-const PROGRESS_COOLDOWN_SECONDS: u64 = 1;
-
-const STATUS_HEARTBEAT_RECEIVER_SLOT: usize = 0;
+async fn watch_heartbeats(output: &mut mpsc::Sender<Message>) -> ResultBtAny<()> {
+    let mut ipc_client = IpcClient::connect_or_start_daemon().await?;
+    ipc_client.subscribe().await?;
+    let worker_heartbeat = ipc_client.get_heartbeat().await?;
+    info!("Connected to daemon.");
+    output
+        .send(Message::Connected(worker_heartbeat))
+        .await
+        .map_err(|e| format!("Couldn't update the status, {e}."))?;
+    while let Some(ipc_notification) = ipc_client.next_notification().await? {
+        let message = match ipc_notification {
+            IpcNotification::Heartbeat(worker_heartbeat) => {
+                Message::Heatbeat(Some(*worker_heartbeat))
+            }
+            IpcNotification::FocusWindow => Message::ShouldFocus,
+        };
+        output
+            .send(message)
+            .await
+            .map_err(|e| format!("Couldn't update the status, {e}."))?;
+    }
+    Ok(())
+}
 
-pub fn get_subscriptions(
-    worker_status: &watch::Receiver<Option<WorkerHeartbeat>>,
-) -> Subscription<Message> {
+pub fn get_subscriptions() -> Subscription<Message> {
     Subscription::batch([
         iced::time::every(std::time::Duration::from_secs(PROGRESS_COOLDOWN_SECONDS))
             .map(|_| Message::Progress),
-        Subscription::run_with(
-            ReceiverWrapper(STATUS_HEARTBEAT_RECEIVER_SLOT, worker_status.clone()),
-            watch_worker_status,
-        ),
+        Subscription::run(watch_daemon),
     ])
 }
@@ -1,44 +1,102 @@
-use std::ffi::OsStr;
+use std::{
+    collections::{HashMap, HashSet, hash_map::Entry},
+    fmt::Display,
+};
 
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
-use sysinfo::{Process, ProcessesToUpdate, System};
-use tokio::{sync::watch, task::JoinHandle};
+use sysinfo::{Pid, Process, System};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
 use tracing::{error, info};
 
-#[cfg(target_os = "windows")]
-use crate::wrappers::SystemCpuSetInformation;
+#[cfg(target_os = "linux")]
+use crate::wrappers::{CpuSet, get_is_thread_gone, get_thread_ids};
 use crate::{
-    errors::ResultBtAny,
-    persistence::{CpuSelections, PersistentStore},
-    selections::mask_to_hashset,
+    WithBacktrace,
+    errors::{AnyError, ResultBtAny},
+    events::{ProcessEvent, ProcessEvents},
+    matcher::ProcessMatcher,
+    persistence::{
+        CpuSelections, EnforcementStrategy, OriginalAffinity, PersistentStore, Profile,
+        ProfileTrigger, Rule,
+    },
+    scanner::{ProcessScanner, ScanStats},
+    selections::{CpuMask, mask_to_hashset},
+    topology::CpuTopology,
+    tracking::{FailureKind, ProcessFailure, ProcessKey, ProcessTracker, TrackedProcess},
+    triggers::{ProfileReport, ProfileSwitcher},
 };
 #[cfg(target_os = "windows")]
-use windows::Win32::Foundation::{CloseHandle, GetLastError};
-#[cfg(target_os = "windows")]
-use windows::Win32::System::SystemInformation::{
-    GetSystemCpuSetInformation, SYSTEM_CPU_SET_INFORMATION,
+use crate::{
+    topology::get_cpu_sets,
+    wrappers::{ProcessorGroups, SystemCpuSetInformation},
 };
 #[cfg(target_os = "windows")]
+use windows::Win32::Foundation::{CloseHandle, E_ACCESSDENIED, E_INVALIDARG, HANDLE};
+#[cfg(target_os = "windows")]
 use windows::Win32::System::Threading::{
-    GetProcessAffinityMask, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
-    PROCESS_SET_INFORMATION, SetProcessAffinityMask,
+    GetProcessAffinityMask, GetProcessDefaultCpuSets, GetProcessGroupAffinity, OpenProcess,
+    PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SET_INFORMATION, PROCESS_SET_LIMITED_INFORMATION,
+    SetProcessAffinityMask, SetProcessDefaultCpuSets,
 };
 
+/// Allowed past the worker's period before a heartbeat's stale.
 const HEARTBEAT_STALE_PERIOD_SECONDS: i64 = 10;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WorkerHeartbeat {
     at: chrono::DateTime<chrono::Utc>,
     is_synced: Option<bool>,
     error: Option<String>,
+    enforcements: Vec<RuleEnforcement>,
+    is_paused: bool,
+    scan_stats: ScanStats,
+    tracked_processes: Vec<TrackedProcess>,
+    rule_reports: Vec<RuleReport>,
+    /// `None` when profiles couldn't be loaded.
+    #[serde(default)]
+    profile: Option<ProfileReport>,
+    /// Until the next run at the latest, longer while process events wake the
+    /// worker.
+    #[serde(default)]
+    period_seconds: u64,
 }
 
 impl WorkerHeartbeat {
-    pub fn now(is_simulation_synced: Option<bool>, error: Option<String>) -> Self {
+    pub fn now(
+        is_simulation_synced: Option<bool>,
+        error: Option<String>,
+        enforcements: Vec<RuleEnforcement>,
+        is_paused: bool,
+        scan_stats: ScanStats,
+        tracked_processes: Vec<TrackedProcess>,
+        rule_reports: Vec<RuleReport>,
+    ) -> Self {
         Self {
             at: chrono::Utc::now(),
             is_synced: is_simulation_synced,
             error,
+            enforcements,
+            is_paused,
+            scan_stats,
+            tracked_processes,
+            rule_reports,
+            profile: None,
+            period_seconds: 0,
+        }
+    }
+
+    pub fn with_profile(self, profile: Option<ProfileReport>) -> Self {
+        Self { profile, ..self }
+    }
+
+    pub fn with_period(self, period: std::time::Duration) -> Self {
+        Self {
+            period_seconds: period.as_secs(),
+            ..self
         }
     }
 
@@ -54,170 +112,1190 @@ impl WorkerHeartbeat {
         &self.error
     }
 
+    pub fn get_enforcements(&self) -> &[RuleEnforcement] {
+        &self.enforcements
+    }
+
+    pub fn get_is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    pub fn get_scan_stats(&self) -> &ScanStats {
+        &self.scan_stats
+    }
+
+    pub fn get_tracked_processes(&self) -> &[TrackedProcess] {
+        &self.tracked_processes
+    }
+
+    pub fn get_rule_reports(&self) -> &[RuleReport] {
+        &self.rule_reports
+    }
+
+    pub fn get_profile(&self) -> Option<&ProfileReport> {
+        self.profile.as_ref()
+    }
+
     pub fn get_is_stale(&self) -> bool {
-        chrono::Utc::now() - self.at > chrono::Duration::seconds(HEARTBEAT_STALE_PERIOD_SECONDS)
+        let stale_period = self.period_seconds as i64 + HEARTBEAT_STALE_PERIOD_SECONDS;
+        chrono::Utc::now() - self.at > chrono::Duration::seconds(stale_period)
+    }
+}
+
+/// Which strategy got a rule's running target onto its CPUs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleEnforcement {
+    pub rule_name: String,
+    pub strategy: EnforcementStrategy,
+}
+
+/// What was done about a process in the last run.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessAction {
+    /// Verified recently enough to not look again.
+    Skipped,
+    /// Found on its CPUs.
+    Checked,
+    /// Found off its CPUs, and left to inherit them from its spawner.
+    Unsynced,
+    Applied,
+    /// Waiting out a delay after failing, or quarantined.
+    BackedOff,
+    Failed,
+}
+
+impl ProcessAction {
+    pub fn get_is_synced(&self) -> bool {
+        matches!(
+            self,
+            ProcessAction::Skipped | ProcessAction::Checked | ProcessAction::Applied
+        )
     }
 }
 
+impl Display for ProcessAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let process_action = match self {
+            ProcessAction::Skipped => "verified",
+            ProcessAction::Checked => "checked",
+            ProcessAction::Unsynced => "left unsynced",
+            ProcessAction::Applied => "applied",
+            ProcessAction::BackedOff => "backed off",
+            ProcessAction::Failed => "failed",
+        };
+        write!(f, "{process_action}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessReport {
+    pub process_id: u32,
+    pub start_time: u64,
+    pub desired_selections: CpuSelections,
+    /// As found before anything was applied, `None` if it wasn't looked at.
+    pub observed_selections: Option<CpuSelections>,
+    pub action: ProcessAction,
+    /// For those backed off, from the run that failed.
+    pub failure: Option<ProcessFailure>,
+}
+
+/// One per enabled rule, in evaluation order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleReport {
+    pub rule_name: String,
+    pub is_synced: Option<bool>,
+    pub error: Option<String>,
+    pub process_reports: Vec<ProcessReport>,
+}
+
+impl RuleReport {
+    fn new(is_synced: Option<bool>, rule: &Rule, e: Option<WithBacktrace<AnyError>>) -> Self {
+        Self {
+            rule_name: rule.name.clone(),
+            is_synced,
+            error: e.map(|e| format!("{}: {}", rule.name, e.get())),
+            process_reports: vec![],
+        }
+    }
+
+    fn with_process_reports(self, process_reports: Vec<ProcessReport>) -> Self {
+        Self {
+            process_reports,
+            ..self
+        }
+    }
+}
+
+/// Sent from the UI, handled at the start of the next run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkerCommand {
+    /// Puts every changed process back and stops enforcing until resumed.
+    RestoreOriginals,
+    Resume,
+    /// Puts every changed process back, then stops the worker.
+    Shutdown,
+    /// Tries a failing or quarantined process again, by PID and start time.
+    RetryProcess(ProcessKey),
+    /// Runs without waiting out the cooldown.
+    Apply,
+}
+
 const WORKER_COOLDOWN_PERIOD_SECONDS: u64 = 5;
 
+/// Polling is only a fallback while process events wake the worker.
+const WORKER_EVENT_FALLBACK_PERIOD_SECONDS: u64 = 30;
+
+/// Lets an exec'd process get its command line in place, and takes a burst of
+/// events in one run.
+const PROCESS_EVENT_SETTLE_PERIOD_MILLISECONDS: u64 = 100;
+
+/// Between runs woken by process events, so a burst like a build's is
+/// coalesced rather than running the worker back to back.
+const PROCESS_EVENT_MIN_INTERVAL_MILLISECONDS: u64 = 1000;
+
 pub fn spawn_worker_task(
     sqlite_pool: SqlitePool,
     worker_status: watch::Sender<Option<WorkerHeartbeat>>,
+    worker_commands: mpsc::UnboundedReceiver<WorkerCommand>,
 ) -> JoinHandle<ResultBtAny<()>> {
     tokio::task::spawn(async move {
         let mut system_info = System::new();
         system_info.refresh_all();
         info!("Refreshing all system info.");
 
-        let mut worker_operations = WorkerOperations {
-            sqlite: sqlite_pool,
-        };
-        loop {
-            _ = run_worker_logic(&mut worker_operations, &mut system_info, &worker_status).await;
+        let mut worker_operations =
+            WorkerOperations::new(sqlite_pool, &system_info, worker_commands);
+        let mut process_scanner = ProcessScanner::new(system_info);
+        let mut worker_state = WorkerState::default();
+        while !worker_state.get_is_shut_down() {
+            _ = run_worker_logic(
+                &mut worker_operations,
+                &mut worker_state,
+                &mut process_scanner,
+                &worker_status,
+            )
+            .await;
+            save_heartbeat(&worker_operations.sqlite, &worker_status).await;
         }
+        info!("Shut down worker.");
+
+        Ok(())
     })
 }
 
+/// A single run, leaving processes as they were applied rather than putting
+/// them back after.
+pub async fn run_worker_once(sqlite_pool: SqlitePool) -> ResultBtAny<WorkerHeartbeat> {
+    let mut system_info = System::new();
+    system_info.refresh_all();
+    info!("Refreshing all system info.");
+
+    let (worker_status, _) = watch::channel(None);
+    let (command_sender, worker_commands) = mpsc::unbounded_channel();
+    command_sender
+        .send(WorkerCommand::Apply)
+        .map_err(|e| format!("Couldn't start the run, {e}."))?;
+    let mut worker_operations = WorkerOperations::new(sqlite_pool, &system_info, worker_commands);
+    let mut process_scanner = ProcessScanner::new(system_info);
+    let mut worker_state = WorkerState::default();
+    let is_run = run_worker_logic(
+        &mut worker_operations,
+        &mut worker_state,
+        &mut process_scanner,
+        &worker_status,
+    )
+    .await;
+    save_heartbeat(&worker_operations.sqlite, &worker_status).await;
+    is_run?;
+
+    let worker_heartbeat = worker_status.borrow().clone();
+    Ok(worker_heartbeat.ok_or("The run didn't report anything.")?)
+}
+
+/// So the heartbeat can be read by other processes.
+async fn save_heartbeat(
+    sqlite_pool: &SqlitePool,
+    worker_status: &watch::Sender<Option<WorkerHeartbeat>>,
+) {
+    let worker_heartbeat = worker_status.borrow().clone();
+    if let Some(worker_heartbeat) = worker_heartbeat {
+        _ = PersistentStore::save_heartbeat(&worker_heartbeat, sqlite_pool)
+            .await
+            .inspect_err(|e| error!("{:?}", e));
+    }
+}
+
 pub(crate) async fn run_worker_logic<WOps: WorkerOperations_>(
     worker_operations: &mut WOps,
-    system_info: &mut System,
+    worker_state: &mut WorkerState,
+    process_scanner: &mut ProcessScanner,
     worker_status: &watch::Sender<Option<WorkerHeartbeat>>,
 ) -> ResultBtAny<()> {
-    worker_operations.sleep().await;
+    let worker_command = worker_operations
+        .sleep(&worker_state.get_watched_process_ids())
+        .await;
 
+    let scan_stats = process_scanner.scan();
+    let process_scanner = &*process_scanner;
+    let system_info = process_scanner.get_system();
+
+    if !worker_state.is_recovered {
+        _ = recover_original_affinities(worker_operations, worker_state)
+            .await
+            .inspect_err(|e| error!("{:?}", e));
+    }
+
+    match worker_command {
+        Some(WorkerCommand::RestoreOriginals) => {
+            restore_original_affinities(worker_operations, worker_state, system_info, |_| true)
+                .await;
+            worker_state.enforced_by.clear();
+            worker_state.tracker.clear();
+            worker_state.is_paused = true;
+        }
+        Some(WorkerCommand::Resume) => worker_state.is_paused = false,
+        Some(WorkerCommand::Apply) => info!("Applying now."),
+        Some(WorkerCommand::RetryProcess(process_key)) => {
+            worker_state.tracker.retry(process_key);
+            info!("Retrying process.");
+        }
+        Some(WorkerCommand::Shutdown) => {
+            restore_original_affinities(worker_operations, worker_state, system_info, |_| true)
+                .await;
+            worker_state.is_shut_down = true;
+            return Ok(());
+        }
+        None => {}
+    }
+    if worker_state.is_paused {
+        let worker_heartbeat = WorkerHeartbeat::now(
+            None,
+            None,
+            vec![],
+            true,
+            scan_stats,
+            worker_state.tracker.get_tracked_processes(),
+            vec![],
+        );
+        let period = worker_operations.get_cooldown_period();
+        worker_status.send_replace(Some(worker_heartbeat.with_period(period)));
+        return Ok(());
+    }
+
+    let profile_report = switch_profile(worker_operations, worker_state, process_scanner)
+        .await
+        .inspect_err(|e| error!("{:?}", e))
+        .ok()
+        .flatten();
+    let period = worker_operations.get_cooldown_period();
     let persistent_store = worker_operations
         .load_store(system_info)
         .await
         .inspect_err(|e| {
             let is_synced = None;
             let e = Some(e.get().to_string());
-            worker_status.send_replace(Some(WorkerHeartbeat::now(is_synced, e)));
+            let worker_heartbeat = WorkerHeartbeat::now(
+                is_synced,
+                e,
+                vec![],
+                false,
+                scan_stats.clone(),
+                vec![],
+                vec![],
+            );
+            let worker_heartbeat = worker_heartbeat
+                .with_profile(profile_report.clone())
+                .with_period(period);
+            worker_status.send_replace(Some(worker_heartbeat));
         })?;
+    worker_operations.set_is_event_driven(persistent_store.settings.is_process_events_enabled);
+
+    let mut claimed_processes = HashSet::new();
+    let mut rule_reports = vec![];
+    let mut enforcements = vec![];
+    for rule in persistent_store.rules.iter().filter(|rule| rule.is_enabled) {
+        let spawner_matcher = rule
+            .spawner_matcher
+            .as_ref()
+            .filter(|_| rule.enforcement_strategy.get_is_inheriting());
+        let rule_report = match spawner_matcher {
+            Some(spawner_matcher) => {
+                run_handoff_rule(
+                    worker_operations,
+                    worker_state,
+                    process_scanner,
+                    rule,
+                    spawner_matcher,
+                    &mut claimed_processes,
+                )
+                .await
+            }
+            None => {
+                run_direct_rule(
+                    worker_operations,
+                    worker_state,
+                    process_scanner,
+                    rule,
+                    &mut claimed_processes,
+                )
+                .await
+            }
+        };
+        rule_reports.push(rule_report);
+
+        if let Some(&strategy) = worker_state.enforced_by.get(&rule.id) {
+            enforcements.push(RuleEnforcement {
+                rule_name: rule.name.clone(),
+                strategy,
+            });
+        }
+    }
+
+    let is_synced = if rule_reports
+        .iter()
+        .any(|report| report.is_synced == Some(false))
+    {
+        Some(false)
+    } else if rule_reports
+        .iter()
+        .any(|report| report.is_synced == Some(true))
+    {
+        Some(true)
+    } else {
+        None
+    };
+    let e = rule_reports.iter().find_map(|report| report.error.clone());
+    worker_state.tracker.retain(
+        |process_key| claimed_processes.contains(&process_key),
+        |process_key| {
+            let process = IrAProcess {
+                id: process_key.0,
+                start_time: process_key.1,
+            };
+            worker_operations.get_is_process_running(&process, system_info)
+        },
+    );
+    // Covers rules that were disabled or removed, and processes that stopped
+    // matching.
+    restore_original_affinities(
+        worker_operations,
+        worker_state,
+        system_info,
+        |original_affinity| {
+            let process_key = (original_affinity.process_id, original_affinity.start_time);
+            !claimed_processes.contains(&process_key)
+        },
+    )
+    .await;
+    let worker_heartbeat = WorkerHeartbeat::now(
+        is_synced,
+        e,
+        enforcements,
+        false,
+        scan_stats,
+        worker_state.tracker.get_tracked_processes(),
+        rule_reports,
+    );
+    let worker_heartbeat = worker_heartbeat
+        .with_profile(profile_report)
+        .with_period(worker_operations.get_cooldown_period());
+    worker_status.send_replace(Some(worker_heartbeat));
+
+    Ok(())
+}
+
+/// Activates the profile picked by the triggers before its rules are loaded,
+/// `None` without an active profile.
+async fn switch_profile<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+    process_scanner: &ProcessScanner,
+) -> ResultBtAny<Option<ProfileReport>> {
+    let (profiles, profile_triggers) = worker_operations.load_profiles().await?;
+    let Some(active_profile) = profiles.iter().find(|profile| profile.is_active) else {
+        return Ok(None);
+    };
+    let profile_triggers: Vec<_> = profile_triggers
+        .into_iter()
+        .filter(|profile_trigger| {
+            profile_trigger.is_enabled
+                && profiles
+                    .iter()
+                    .any(|profile| profile.id == profile_trigger.profile_id)
+        })
+        .collect();
+
+    let mut matched_ids = HashSet::new();
+    for profile_trigger in &profile_triggers {
+        match worker_operations.get_processes_by_matcher(process_scanner, &profile_trigger.matcher)
+        {
+            Ok(processes) if !processes.is_empty() => _ = matched_ids.insert(profile_trigger.id),
+            Ok(_) => {}
+            Err(e) => error!("{:?}", e),
+        }
+    }
+
+    let now = chrono::Utc::now();
+    let profile_switcher = &mut worker_state.profile_switcher;
+    let mut active_profile_id = active_profile.id;
+    if let Some(profile_id) =
+        profile_switcher.update(active_profile_id, &profile_triggers, &matched_ids, now)
+    {
+        // Retried from scratch next run, rather than mistaken for a switch by
+        // hand.
+        worker_operations
+            .activate_profile(profile_id)
+            .await
+            .inspect_err(|_| *profile_switcher = ProfileSwitcher::default())?;
+        info!("Switched profile.");
+        active_profile_id = profile_id;
+    }
+
+    let name = profiles
+        .iter()
+        .find(|profile| profile.id == active_profile_id)
+        .map(|profile| profile.name.clone())
+        .unwrap_or_default();
+    Ok(Some(ProfileReport {
+        id: active_profile_id,
+        name,
+        activation: profile_switcher.get_activation(active_profile_id, &profile_triggers, now),
+    }))
+}
+
+/// Carried between runs of `run_worker_logic`.
+#[derive(Debug, Default)]
+pub(crate) struct WorkerState {
+    /// By rule ID, how its running target got synced.
+    enforced_by: HashMap<i64, EnforcementStrategy>,
+    /// By PID and start time, affinities from before they were first changed.
+    original_affinities: HashMap<ProcessKey, OriginalAffinity>,
+    /// Whether those left over from an earlier run have been loaded.
+    is_recovered: bool,
+    is_paused: bool,
+    is_shut_down: bool,
+    tracker: ProcessTracker,
+    profile_switcher: ProfileSwitcher,
+}
+
+impl WorkerState {
+    pub fn get_is_shut_down(&self) -> bool {
+        self.is_shut_down
+    }
+
+    /// Those whose exit wakes the worker, so they're released and their
+    /// spawners reset right away.
+    fn get_watched_process_ids(&self) -> HashSet<u32> {
+        let original_ids = self
+            .original_affinities
+            .keys()
+            .map(|&(process_id, _)| process_id);
+        self.tracker
+            .get_running_process_ids()
+            .chain(original_ids)
+            .collect()
+    }
+}
+
+/// Loads those left by a run that crashed, processes that have since exited
+/// are dropped once found unclaimed.
+async fn recover_original_affinities<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+) -> ResultBtAny<()> {
+    let original_affinities = worker_operations.load_original_affinities().await?;
+    for original_affinity in original_affinities {
+        let process_key = (original_affinity.process_id, original_affinity.start_time);
+        worker_state
+            .original_affinities
+            .entry(process_key)
+            .or_insert(original_affinity);
+    }
+    worker_state.is_recovered = true;
+    info!("Recovered original affinities.");
+
+    Ok(())
+}
+
+/// Puts back the selected processes that are still running, and forgets them.
+async fn restore_original_affinities<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+    system_info: &System,
+    should_restore: impl Fn(&OriginalAffinity) -> bool,
+) {
+    let original_affinities: Vec<_> = worker_state
+        .original_affinities
+        .values()
+        .filter(|original_affinity| should_restore(original_affinity))
+        .cloned()
+        .collect();
+    if original_affinities.is_empty() {
+        return;
+    }
+
+    let mut restored_keys = vec![];
+    for original_affinity in original_affinities {
+        let process = IrAProcess {
+            id: original_affinity.process_id,
+            start_time: original_affinity.start_time,
+        };
+        if worker_operations.get_is_process_running(&process, system_info) {
+            _ = worker_operations
+                .set_processes_affinity(
+                    std::slice::from_ref(&process),
+                    &original_affinity.selections,
+                )
+                .await
+                .inspect(|_| info!("Restored original affinity."))
+                .inspect_err(|e| error!("{:?}", e));
+        }
+        worker_state.original_affinities.remove(&process.get_key());
+        restored_keys.push(process.get_key());
+    }
+
+    save_original_affinities(worker_operations, worker_state, &[], &restored_keys).await;
+}
+
+/// Only those captured or restored are written, so the originals of another
+/// process using the same store are left alone.
+async fn save_original_affinities<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &WorkerState,
+    captured_keys: &[ProcessKey],
+    restored_keys: &[ProcessKey],
+) {
+    let original_affinities: Vec<_> = captured_keys
+        .iter()
+        .filter_map(|process_key| worker_state.original_affinities.get(process_key))
+        .cloned()
+        .collect();
+    _ = worker_operations
+        .save_original_affinities(&original_affinities, restored_keys)
+        .await
+        .inspect_err(|e| error!("{:?}", e));
+}
+
+/// Records each process's current affinity before it's first changed. Those
+/// that fail are backed off from, see `ProcessTracker::record_failed`.
+async fn apply_affinity<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+    system_info: &System,
+    rule: &Rule,
+    candidate_processes: &[IrAProcess],
+    cpu_selections: &CpuSelections,
+    process_reports: &mut Vec<ProcessReport>,
+) -> ResultBtAny<()> {
+    let mut captured_keys = vec![];
+    let mut first_error = None;
+    for candidate_process in candidate_processes {
+        let process_key = candidate_process.get_key();
+        if worker_state.tracker.get_is_backing_off(process_key) {
+            report_backed_off(
+                worker_state,
+                candidate_process,
+                cpu_selections,
+                process_reports,
+            );
+            continue;
+        }
+        let is_applied = apply_process_affinity(
+            worker_operations,
+            worker_state,
+            system_info,
+            rule,
+            candidate_process,
+            cpu_selections,
+            &mut captured_keys,
+        )
+        .await;
+        match is_applied {
+            Ok(()) => {
+                worker_state
+                    .tracker
+                    .record_applied(process_key, cpu_selections);
+                get_process_report(process_reports, candidate_process, cpu_selections).action =
+                    ProcessAction::Applied;
+            }
+            Err(e) => {
+                record_failed(
+                    worker_state,
+                    candidate_process,
+                    cpu_selections,
+                    &e,
+                    process_reports,
+                );
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    if !captured_keys.is_empty() {
+        save_original_affinities(worker_operations, worker_state, &captured_keys, &[]).await;
+    }
+
+    first_error.map_or(Ok(()), Err)
+}
+
+async fn apply_process_affinity<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+    system_info: &System,
+    rule: &Rule,
+    candidate_process: &IrAProcess,
+    cpu_selections: &CpuSelections,
+    captured_keys: &mut Vec<ProcessKey>,
+) -> ResultBtAny<()> {
+    let process_key = candidate_process.get_key();
+    if let Entry::Vacant(original_affinity) = worker_state.original_affinities.entry(process_key) {
+        let original_selections = worker_operations
+            .get_process_affinity(candidate_process, system_info)
+            .await?;
+        original_affinity.insert(OriginalAffinity {
+            process_id: candidate_process.id,
+            start_time: candidate_process.start_time,
+            rule_id: rule.id,
+            selections: original_selections,
+        });
+        captured_keys.push(process_key);
+    }
+
+    worker_operations
+        .set_processes_affinity(std::slice::from_ref(candidate_process), cpu_selections)
+        .await
+}
+
+/// Looks at each process not verified recently on its own, and returns those
+/// that are off their CPUs. Those backed off from are skipped, and the first
+/// error is returned alongside rather than stopping the rest.
+async fn get_unsynced_processes<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+    system_info: &System,
+    candidate_processes: &[IrAProcess],
+    cpu_selections: &CpuSelections,
+    process_reports: &mut Vec<ProcessReport>,
+) -> (Vec<IrAProcess>, Option<WithBacktrace<AnyError>>) {
+    let mut unsynced_processes = vec![];
+    let mut first_error = None;
+    for candidate_process in candidate_processes {
+        let process_key = candidate_process.get_key();
+        if worker_state
+            .tracker
+            .get_is_verified(process_key, cpu_selections)
+        {
+            get_process_report(process_reports, candidate_process, cpu_selections);
+            continue;
+        }
+        if worker_state.tracker.get_is_backing_off(process_key) {
+            report_backed_off(
+                worker_state,
+                candidate_process,
+                cpu_selections,
+                process_reports,
+            );
+            continue;
+        }
+        let observed_selections = match worker_operations
+            .get_process_affinity(candidate_process, system_info)
+            .await
+        {
+            Ok(observed_selections) => observed_selections,
+            Err(e) => {
+                record_failed(
+                    worker_state,
+                    candidate_process,
+                    cpu_selections,
+                    &e,
+                    process_reports,
+                );
+                first_error.get_or_insert(e);
+                continue;
+            }
+        };
+        let is_synced = observed_selections == *cpu_selections;
+        worker_state
+            .tracker
+            .record_checked(process_key, cpu_selections, is_synced);
+        let process_report = get_process_report(process_reports, candidate_process, cpu_selections);
+        process_report.observed_selections = Some(observed_selections);
+        if is_synced {
+            process_report.action = ProcessAction::Checked;
+        } else {
+            process_report.action = ProcessAction::Unsynced;
+            unsynced_processes.push(candidate_process.clone());
+        }
+    }
+    (unsynced_processes, first_error)
+}
+
+/// The one already made for this process this run, as it's looked at before
+/// it's applied to, otherwise a new one taken as verified.
+fn get_process_report<'a>(
+    process_reports: &'a mut Vec<ProcessReport>,
+    process: &IrAProcess,
+    cpu_selections: &CpuSelections,
+) -> &'a mut ProcessReport {
+    let index = process_reports
+        .iter()
+        .position(|process_report| {
+            (process_report.process_id, process_report.start_time) == process.get_key()
+                && process_report.desired_selections == *cpu_selections
+        })
+        .unwrap_or_else(|| {
+            process_reports.push(ProcessReport {
+                process_id: process.id,
+                start_time: process.start_time,
+                desired_selections: cpu_selections.clone(),
+                observed_selections: None,
+                action: ProcessAction::Skipped,
+                failure: None,
+            });
+            process_reports.len() - 1
+        });
+    &mut process_reports[index]
+}
+
+fn report_backed_off(
+    worker_state: &WorkerState,
+    process: &IrAProcess,
+    cpu_selections: &CpuSelections,
+    process_reports: &mut Vec<ProcessReport>,
+) {
+    let process_report = get_process_report(process_reports, process, cpu_selections);
+    process_report.action = ProcessAction::BackedOff;
+    process_report.failure = worker_state.tracker.get_failure(process.get_key()).cloned();
+}
 
-    system_info.refresh_processes(ProcessesToUpdate::All, true);
-    info!("Refreshing system process info.");
+fn record_failed(
+    worker_state: &mut WorkerState,
+    process: &IrAProcess,
+    cpu_selections: &CpuSelections,
+    e: &WithBacktrace<AnyError>,
+    process_reports: &mut Vec<ProcessReport>,
+) {
+    worker_state
+        .tracker
+        .record_failed(process.get_key(), get_failure_kind(e), e.get().to_string());
+    let process_report = get_process_report(process_reports, process, cpu_selections);
+    process_report.action = ProcessAction::Failed;
+    process_report.failure = worker_state.tracker.get_failure(process.get_key()).cloned();
+}
+
+/// Tells apart the failures worth retrying from those that likely aren't.
+fn get_failure_kind(e: &WithBacktrace<AnyError>) -> FailureKind {
+    #[cfg(target_os = "windows")]
+    if let Some(e) = e.get().downcast_ref::<windows_result::Error>() {
+        return match e.code() {
+            E_ACCESSDENIED => FailureKind::PermissionDenied,
+            E_INVALIDARG => FailureKind::InvalidMask,
+            _ => FailureKind::Other,
+        };
+    }
+
+    let Some(e) = e.get().downcast_ref::<std::io::Error>() else {
+        return FailureKind::Other;
+    };
+    #[cfg(target_os = "linux")]
+    if e.raw_os_error() == Some(libc::ESRCH) {
+        return FailureKind::ProcessGone;
+    }
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied => FailureKind::PermissionDenied,
+        std::io::ErrorKind::NotFound => FailureKind::ProcessGone,
+        std::io::ErrorKind::InvalidInput => FailureKind::InvalidMask,
+        _ => FailureKind::Other,
+    }
+}
+
+/// Earlier rules win when several match the same process.
+fn claim_processes(
+    candidate_processes: Vec<IrAProcess>,
+    claimed_processes: &mut HashSet<ProcessKey>,
+    tracker: &mut ProcessTracker,
+    rule: &Rule,
+) -> Vec<IrAProcess> {
+    let candidate_processes: Vec<_> = candidate_processes
+        .into_iter()
+        .filter(|candidate_process| claimed_processes.insert(candidate_process.get_key()))
+        .collect();
+    for candidate_process in &candidate_processes {
+        tracker.track(candidate_process.get_key(), &rule.name);
+    }
+    candidate_processes
+}
+
+async fn sync_processes<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+    system_info: &System,
+    rule: &Rule,
+    candidate_processes: &[IrAProcess],
+) -> RuleReport {
+    let are_any_processes = !candidate_processes.is_empty();
+    if !are_any_processes {
+        return RuleReport::new(None, rule, None);
+    }
+
+    let mut process_reports = vec![];
+    let (unsynced_processes, mut e) = get_unsynced_processes(
+        worker_operations,
+        worker_state,
+        system_info,
+        candidate_processes,
+        rule.into(),
+        &mut process_reports,
+    )
+    .await;
+    if !unsynced_processes.is_empty() {
+        let apply_error = apply_affinity(
+            worker_operations,
+            worker_state,
+            system_info,
+            rule,
+            &unsynced_processes,
+            rule.into(),
+            &mut process_reports,
+        )
+        .await
+        .err();
+        e = e.or(apply_error);
+    }
+    let are_any_backing_off = get_are_any_backing_off(worker_state, candidate_processes);
+    RuleReport::new(Some(e.is_none() && !are_any_backing_off), rule, e)
+        .with_process_reports(process_reports)
+}
 
+/// Those still failing keep their rule unsynced, without repeating the error.
+fn get_are_any_backing_off(worker_state: &WorkerState, candidate_processes: &[IrAProcess]) -> bool {
+    candidate_processes.iter().any(|candidate_process| {
+        worker_state
+            .tracker
+            .get_is_backing_off(candidate_process.get_key())
+    })
+}
+
+async fn run_direct_rule<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+    process_scanner: &ProcessScanner,
+    rule: &Rule,
+    claimed_processes: &mut HashSet<ProcessKey>,
+) -> RuleReport {
+    let system_info = process_scanner.get_system();
+    let candidate_processes =
+        match worker_operations.get_processes_by_matcher(process_scanner, &rule.matcher) {
+            Ok(candidate_processes) => claim_processes(
+                candidate_processes,
+                claimed_processes,
+                &mut worker_state.tracker,
+                rule,
+            ),
+            Err(e) => return RuleReport::new(None, rule, Some(e)),
+        };
+
+    let rule_report = sync_processes(
+        worker_operations,
+        worker_state,
+        system_info,
+        rule,
+        &candidate_processes,
+    )
+    .await;
+    if rule_report.is_synced == Some(true) {
+        worker_state
+            .enforced_by
+            .insert(rule.id, EnforcementStrategy::Direct);
+    } else {
+        worker_state.enforced_by.remove(&rule.id);
+    }
+    rule_report
+}
+
+/// The simulator inherits the spawner's affinity when launched, after which
+/// the spawner is released again. It's only corrected directly if the rule's
+/// strategy allows.
+async fn run_handoff_rule<WOps: WorkerOperations_>(
+    worker_operations: &mut WOps,
+    worker_state: &mut WorkerState,
+    process_scanner: &ProcessScanner,
+    rule: &Rule,
+    spawner_matcher: &ProcessMatcher,
+    claimed_processes: &mut HashSet<ProcessKey>,
+) -> RuleReport {
+    let system_info = process_scanner.get_system();
     let iracing_simulators =
-        worker_operations.get_processes_by_exact_name(system_info, &persistent_store.simulator);
+        match worker_operations.get_processes_by_matcher(process_scanner, &rule.matcher) {
+            Ok(iracing_simulators) => claim_processes(
+                iracing_simulators,
+                claimed_processes,
+                &mut worker_state.tracker,
+                rule,
+            ),
+            Err(e) => return RuleReport::new(None, rule, Some(e)),
+        };
     let are_any_simulators = !iracing_simulators.is_empty();
 
     let simulator_spawners =
-        worker_operations.get_processes_by_exact_name(system_info, &persistent_store.spawner);
+        match worker_operations.get_processes_by_matcher(process_scanner, spawner_matcher) {
+            Ok(simulator_spawners) => claim_processes(
+                simulator_spawners,
+                claimed_processes,
+                &mut worker_state.tracker,
+                rule,
+            ),
+            Err(e) => return RuleReport::new(None, rule, Some(e)),
+        };
     let are_any_spawners = !simulator_spawners.is_empty();
 
-    match (are_any_simulators, are_any_spawners) {
-        (false, false) => {
-            let is_synced = None;
-            let e = None;
-            worker_status.send_replace(Some(WorkerHeartbeat::now(is_synced, e)));
-        }
-        (false, true) => {
-            let are_spawners_synced = worker_operations
-                .get_are_processes_synced(
-                    &simulator_spawners,
-                    (&persistent_store).into(),
-                    &system_info,
-                )
-                .await
-                .inspect_err(|e| {
-                    let is_synced = None;
-                    let e = Some(e.get().to_string());
-                    worker_status.send_replace(Some(WorkerHeartbeat::now(is_synced, e)));
-                })?;
-            if are_spawners_synced {
-                let is_synced = Some(true);
-                let e = None;
-                worker_status.send_replace(Some(WorkerHeartbeat::now(is_synced, e)));
-            } else {
-                let e = worker_operations
-                    .set_processes_affinity(&simulator_spawners, (&persistent_store).into())
-                    .await
-                    .err()
-                    .map(|e| e.get().to_string());
-                let is_synced = Some(e.is_none());
-                worker_status.send_replace(Some(WorkerHeartbeat::now(is_synced, e)));
-            }
+    if !are_any_simulators {
+        worker_state.enforced_by.remove(&rule.id);
+        if !are_any_spawners {
+            return RuleReport::new(None, rule, None);
         }
-        (true, _) => {
-            let are_simulators_synced = worker_operations
-                .get_are_processes_synced(
-                    &iracing_simulators,
-                    (&persistent_store).into(),
-                    &system_info,
-                )
-                .await;
-            let is_synced = are_simulators_synced.as_ref().ok().copied();
-            let e = are_simulators_synced.err().map(|e| e.get().to_string());
-            worker_status.send_replace(Some(WorkerHeartbeat::now(is_synced, e)));
-
-            let is_synced = is_synced.unwrap_or(false);
-            if is_synced && are_any_spawners {
-                _ = worker_operations
-                    .set_processes_affinity(
-                        &simulator_spawners,
-                        &CpuSelections::new_all_selected(system_info.cpus().len()),
-                    )
-                    .await
-                    // TODO: Expand heartbeat to send multiple errors, change frequency too.
-                    .inspect_err(|e| error!("{:?}", e));
+        return sync_processes(
+            worker_operations,
+            worker_state,
+            system_info,
+            rule,
+            &simulator_spawners,
+        )
+        .await;
+    }
+
+    let mut process_reports = vec![];
+    let (unsynced_simulators, mut e) = get_unsynced_processes(
+        worker_operations,
+        worker_state,
+        system_info,
+        &iracing_simulators,
+        rule.into(),
+        &mut process_reports,
+    )
+    .await;
+    let mut is_synced = Some(
+        unsynced_simulators.is_empty()
+            && e.is_none()
+            && !get_are_any_backing_off(worker_state, &iracing_simulators),
+    );
+
+    let should_apply_directly =
+        is_synced == Some(false) && rule.enforcement_strategy.get_is_direct();
+    if should_apply_directly {
+        let apply_error = apply_affinity(
+            worker_operations,
+            worker_state,
+            system_info,
+            rule,
+            &unsynced_simulators,
+            rule.into(),
+            &mut process_reports,
+        )
+        .await
+        .err();
+        e = e.or(apply_error);
+        is_synced =
+            Some(e.is_none() && !get_are_any_backing_off(worker_state, &iracing_simulators));
+        if is_synced == Some(true) {
+            worker_state
+                .enforced_by
+                .insert(rule.id, EnforcementStrategy::Direct);
+        }
+    }
+
+    if is_synced == Some(true) {
+        worker_state
+            .enforced_by
+            .entry(rule.id)
+            .or_insert(EnforcementStrategy::InheritViaSpawner);
+    } else {
+        worker_state.enforced_by.remove(&rule.id);
+    }
+
+    if is_synced.unwrap_or(false) {
+        for simulator_spawner in &simulator_spawners {
+            let original_selections = worker_state
+                .original_affinities
+                .get(&simulator_spawner.get_key())
+                .map(|original_affinity| &original_affinity.selections);
+            let reset_selections = rule
+                .spawner_reset
+                .resolve(&rule.selections, original_selections);
+            if worker_state
+                .tracker
+                .get_is_verified(simulator_spawner.get_key(), &reset_selections)
+            {
+                get_process_report(&mut process_reports, simulator_spawner, &reset_selections);
+                continue;
             }
+            // Reported on its own, without holding the simulator up.
+            _ = apply_affinity(
+                worker_operations,
+                worker_state,
+                system_info,
+                rule,
+                std::slice::from_ref(simulator_spawner),
+                &reset_selections,
+                &mut process_reports,
+            )
+            .await
+            .inspect_err(|e| error!("{:?}", e));
         }
     }
 
-    Ok(())
+    RuleReport::new(is_synced, rule, e).with_process_reports(process_reports)
 }
 
 struct WorkerOperations {
     sqlite: SqlitePool,
+    topology: CpuTopology,
+    worker_commands: mpsc::UnboundedReceiver<WorkerCommand>,
+    process_events: Option<ProcessEvents>,
+    /// Not retried until the setting is turned off and on again.
+    is_process_events_unavailable: bool,
+    /// When the last run woken by a process event was.
+    event_woken_at: Option<tokio::time::Instant>,
+}
+
+impl WorkerOperations {
+    fn new(
+        sqlite_pool: SqlitePool,
+        system_info: &System,
+        worker_commands: mpsc::UnboundedReceiver<WorkerCommand>,
+    ) -> Self {
+        Self {
+            sqlite: sqlite_pool,
+            topology: CpuTopology::load(system_info.cpus().len()),
+            worker_commands,
+            process_events: None,
+            is_process_events_unavailable: false,
+            event_woken_at: None,
+        }
+    }
 }
 
 pub(crate) trait WorkerOperations_ {
-    async fn sleep(&mut self);
+    /// Cut short by a command, a process being exec'd, or one of `watched_ids`
+    /// exiting.
+    async fn sleep(&mut self, watched_ids: &HashSet<u32>) -> Option<WorkerCommand>;
+    /// The longest `sleep` waits for.
+    fn get_cooldown_period(&self) -> std::time::Duration;
+    fn set_is_event_driven(&mut self, is_event_driven: bool);
     async fn load_store(&mut self, system_info: &System) -> ResultBtAny<PersistentStore>;
-    fn get_processes_by_exact_name(&mut self, system_info: &System, name: &str) -> Vec<IrAProcess>;
-    async fn get_are_processes_synced(
+    /// Every profile, with the triggers that switch between them.
+    async fn load_profiles(&mut self) -> ResultBtAny<(Vec<Profile>, Vec<ProfileTrigger>)>;
+    async fn activate_profile(&mut self, profile_id: i64) -> ResultBtAny<()>;
+    fn get_processes_by_matcher(
         &mut self,
-        candidate_processes: &[IrAProcess],
-        cpu_selections: &CpuSelections,
-        system_info: &System,
-    ) -> ResultBtAny<bool>;
+        process_scanner: &ProcessScanner,
+        matcher: &ProcessMatcher,
+    ) -> ResultBtAny<Vec<IrAProcess>>;
     async fn set_processes_affinity(
         &mut self,
         candidate_processes: &[IrAProcess],
         cpu_selections: &CpuSelections,
     ) -> ResultBtAny<()>;
+    async fn get_process_affinity(
+        &mut self,
+        candidate_process: &IrAProcess,
+        system_info: &System,
+    ) -> ResultBtAny<CpuSelections>;
+    fn get_is_process_running(&mut self, process: &IrAProcess, system_info: &System) -> bool;
+    async fn load_original_affinities(&mut self) -> ResultBtAny<Vec<OriginalAffinity>>;
+    async fn save_original_affinities(
+        &mut self,
+        original_affinities: &[OriginalAffinity],
+        restored_keys: &[ProcessKey],
+    ) -> ResultBtAny<()>;
 }
 
 impl WorkerOperations_ for WorkerOperations {
-    async fn sleep(&mut self) {
-        let worker_period = std::time::Duration::from_secs(WORKER_COOLDOWN_PERIOD_SECONDS);
-        tokio::time::sleep(worker_period).await;
+    async fn sleep(&mut self, watched_ids: &HashSet<u32>) -> Option<WorkerCommand> {
+        let worker_period = self.get_cooldown_period();
+        let process_event = tokio::select! {
+            _ = tokio::time::sleep(worker_period) => return None,
+            Some(worker_command) = self.worker_commands.recv() => return Some(worker_command),
+            process_event = wait_for_event(self.process_events.as_mut(), watched_ids) => {
+                process_event
+            }
+        };
+
+        match (process_event, &mut self.process_events) {
+            (Some(_), Some(process_events)) => {
+                let settle_period =
+                    std::time::Duration::from_millis(PROCESS_EVENT_SETTLE_PERIOD_MILLISECONDS);
+                let min_interval =
+                    std::time::Duration::from_millis(PROCESS_EVENT_MIN_INTERVAL_MILLISECONDS);
+                let settled_at = tokio::time::Instant::now() + settle_period;
+                let resumed_at = self.event_woken_at.map_or(settled_at, |event_woken_at| {
+                    settled_at.max(event_woken_at + min_interval)
+                });
+                tokio::time::sleep_until(resumed_at).await;
+                while process_events.try_next().is_some() {}
+                self.event_woken_at = Some(tokio::time::Instant::now());
+            }
+            _ => {
+                error!("Lost process events, polling instead.");
+                self.process_events = None;
+                self.is_process_events_unavailable = true;
+            }
+        }
+        None
     }
 
-    async fn load_store(&mut self, system_info: &System) -> ResultBtAny<PersistentStore> {
-        PersistentStore::load(system_info.cpus().len(), &self.sqlite).await
+    fn get_cooldown_period(&self) -> std::time::Duration {
+        let cooldown_period_seconds = if self.process_events.is_some() {
+            WORKER_EVENT_FALLBACK_PERIOD_SECONDS
+        } else {
+            WORKER_COOLDOWN_PERIOD_SECONDS
+        };
+        std::time::Duration::from_secs(cooldown_period_seconds)
     }
 
-    fn get_processes_by_exact_name(
-        &mut self,
-        system_info: &System,
-        exact_name: &str,
-    ) -> Vec<IrAProcess> {
-        system_info
-            .processes_by_exact_name(OsStr::new(exact_name))
-            .map(|process| process.into())
-            .collect()
+    fn set_is_event_driven(&mut self, is_event_driven: bool) {
+        if !is_event_driven || !cfg!(target_os = "linux") {
+            self.process_events = None;
+            self.is_process_events_unavailable = false;
+            return;
+        }
+        if self.process_events.is_some() || self.is_process_events_unavailable {
+            return;
+        }
+
+        match ProcessEvents::subscribe() {
+            Ok(process_events) => self.process_events = Some(process_events),
+            Err(e) => {
+                error!("{:?}", e);
+                self.is_process_events_unavailable = true;
+            }
+        }
+    }
+
+    async fn load_store(&mut self, _system_info: &System) -> ResultBtAny<PersistentStore> {
+        PersistentStore::load(&self.topology, &self.sqlite).await
+    }
+
+    async fn load_profiles(&mut self) -> ResultBtAny<(Vec<Profile>, Vec<ProfileTrigger>)> {
+        let profiles = PersistentStore::load_profiles(&self.sqlite).await?;
+        let profile_triggers = PersistentStore::load_profile_triggers(&self.sqlite).await?;
+        Ok((profiles, profile_triggers))
     }
 
-    async fn get_are_processes_synced(
+    async fn activate_profile(&mut self, profile_id: i64) -> ResultBtAny<()> {
+        PersistentStore::activate_profile(profile_id, &self.sqlite).await
+    }
+
+    fn get_processes_by_matcher(
         &mut self,
-        candidate_processes: &[IrAProcess],
-        cpu_selections: &CpuSelections,
-        system_info: &System,
-    ) -> ResultBtAny<bool> {
-        let cpu_selections = cpu_selections.into();
-        get_are_processes_affinity_synced(candidate_processes, cpu_selections, system_info).await
+        process_scanner: &ProcessScanner,
+        matcher: &ProcessMatcher,
+    ) -> ResultBtAny<Vec<IrAProcess>> {
+        Ok(matcher
+            .get_matching_processes(process_scanner)?
+            .into_iter()
+            .map(|process| process.into())
+            .collect())
     }
 
     async fn set_processes_affinity(
@@ -225,116 +1303,236 @@ impl WorkerOperations_ for WorkerOperations {
         candidate_processes: &[IrAProcess],
         cpu_selections: &CpuSelections,
     ) -> ResultBtAny<()> {
-        let cpu_selections = cpu_selections.into();
         for candidate_process in candidate_processes {
             set_cpu_affinity_of_process(candidate_process, cpu_selections).await?;
         }
         Ok(())
     }
+
+    async fn get_process_affinity(
+        &mut self,
+        candidate_process: &IrAProcess,
+        system_info: &System,
+    ) -> ResultBtAny<CpuSelections> {
+        let cpu_affinity = get_cpu_affinity_of_process(candidate_process)?;
+        Ok(CpuSelections::new_preselected(
+            mask_to_hashset(&cpu_affinity),
+            system_info.cpus().len(),
+        ))
+    }
+
+    fn get_is_process_running(&mut self, process: &IrAProcess, system_info: &System) -> bool {
+        system_info
+            .process(Pid::from_u32(process.id))
+            .is_some_and(|running_process| running_process.start_time() == process.start_time)
+    }
+
+    async fn load_original_affinities(&mut self) -> ResultBtAny<Vec<OriginalAffinity>> {
+        PersistentStore::load_original_affinities(self.topology.get_cpu_count(), &self.sqlite).await
+    }
+
+    async fn save_original_affinities(
+        &mut self,
+        original_affinities: &[OriginalAffinity],
+        restored_keys: &[ProcessKey],
+    ) -> ResultBtAny<()> {
+        PersistentStore::save_original_affinities(original_affinities, restored_keys, &self.sqlite)
+            .await
+    }
+}
+
+/// Never resolves without a subscription, `None` once it's lost. Forks don't
+/// wake the worker, as a fork only gets its own name once it execs.
+async fn wait_for_event(
+    process_events: Option<&mut ProcessEvents>,
+    watched_ids: &HashSet<u32>,
+) -> Option<ProcessEvent> {
+    let Some(process_events) = process_events else {
+        return std::future::pending().await;
+    };
+    loop {
+        let process_event = process_events.next().await?;
+        let is_waking = match process_event {
+            ProcessEvent::Exec { .. } => true,
+            ProcessEvent::Exit { process_id } => watched_ids.contains(&process_id),
+            ProcessEvent::Fork { .. } => false,
+        };
+        if is_waking {
+            return Some(process_event);
+        }
+    }
 }
 
 #[derive(Clone)]
 pub(crate) struct IrAProcess {
     #[allow(dead_code)]
     pub id: u32,
+    /// Tells a reused PID apart from the original process.
+    pub start_time: u64,
+}
+
+impl IrAProcess {
+    pub fn get_key(&self) -> ProcessKey {
+        (self.id, self.start_time)
+    }
 }
 
 impl From<&Process> for IrAProcess {
     fn from(value: &Process) -> Self {
         Self {
             id: value.pid().as_u32(),
+            start_time: value.start_time(),
         }
     }
 }
 
-async fn get_are_processes_affinity_synced(
-    candidate_processes: &[IrAProcess],
-    cpu_selections: &CpuSelections,
-    system_info: &System,
-) -> ResultBtAny<bool> {
-    for simulator_spawner in candidate_processes.iter() {
-        let cpu_affinity = get_cpu_affinity_of_process(simulator_spawner)?;
-        let cpu_selections_ = CpuSelections::new_preselected(
-            mask_to_hashset(&cpu_affinity),
-            system_info.cpus().len(),
-        );
-        let isnt_synced = *cpu_selections != cpu_selections_;
-        if isnt_synced {
-            return Ok(false);
-        }
-    }
-
-    Ok(true)
-}
-
-fn get_cpu_affinity_of_process(
+pub(crate) fn get_cpu_affinity_of_process(
     #[allow(unused_variables)] process: &IrAProcess,
-) -> ResultBtAny<usize> {
+) -> ResultBtAny<CpuMask> {
     #[cfg(target_os = "windows")]
-    unsafe {
-        let should_inherit_handle = false;
-        let process = OpenProcess(
-            PROCESS_QUERY_LIMITED_INFORMATION,
-            should_inherit_handle,
-            process.id,
-        )?;
+    {
+        let process = unsafe {
+            let should_inherit_handle = false;
+            OpenProcess(
+                PROCESS_QUERY_LIMITED_INFORMATION,
+                should_inherit_handle,
+                process.id,
+            )?
+        };
         info!("Opened process.");
 
-        let mut process_mask: usize = 0;
-        let mut system_mask: usize = 0;
-        let is_success = GetProcessAffinityMask(
-            process,
-            &mut process_mask as *mut usize,
-            &mut system_mask as *mut usize,
-        );
-        CloseHandle(process)?;
+        let process_mask = get_group_aware_affinity(process);
+        unsafe {
+            CloseHandle(process)?;
+        }
         info!("Closed process.");
-        is_success?;
+        let process_mask = process_mask?;
+        info!("Got affinity mask.");
+
+        Ok(process_mask)
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let mut process_mask = CpuMask::new();
+        let mut are_any_threads = false;
+        for thread_id in get_thread_ids(process.id)? {
+            let cpu_set = match CpuSet::get_of_thread(thread_id) {
+                Ok(cpu_set) => cpu_set,
+                Err(e) if get_is_thread_gone(&e) => continue,
+                Err(e) => Err(e)?,
+            };
+            // Mirrors a Windows process mask, which is a superset of its threads'.
+            process_mask.union_with(&cpu_set.to_mask());
+            are_any_threads = true;
+        }
+        if !are_any_threads {
+            Err(format!("Process `{}` has no threads left.", process.id))?;
+        }
         info!("Got affinity mask.");
 
         Ok(process_mask)
     }
 
-    #[cfg(not(target_os = "windows"))]
-    unimplemented!()
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    Err("Reading affinities isn't supported on this platform.")?
 }
 
+/// Read back the way `set_group_aware_affinity` writes, so a process with
+/// default CPU sets on a single group machine is still seen as synced. On
+/// bigger machines, processes without default CPU sets are read as the whole
+/// of each group they run in.
 #[cfg(target_os = "windows")]
-async fn get_cpu_sets() -> ResultBtAny<Vec<SYSTEM_CPU_SET_INFORMATION>> {
-    let payload_size = std::mem::size_of::<SYSTEM_CPU_SET_INFORMATION>();
-
-    let mut cpu_sets: [SYSTEM_CPU_SET_INFORMATION; 64] =
-        [SYSTEM_CPU_SET_INFORMATION::default(); 64];
-    let mut output_size: u32 = 0;
-    let subject_process = None;
-    let reserved_flag = 0;
-    unsafe {
-        let is_success = GetSystemCpuSetInformation(
-            Some(cpu_sets.as_mut_ptr()),
-            (cpu_sets.len() * payload_size) as u32,
-            &mut output_size,
-            subject_process,
-            Some(reserved_flag),
-        );
-        info!("Got CPU set info.");
-        if (!is_success).into() {
-            Err(format!(
-                "WinAPI call failed with code `{}`.",
-                GetLastError().0
-            ))?;
+fn get_group_aware_affinity(process: HANDLE) -> ResultBtAny<CpuMask> {
+    let processor_groups = ProcessorGroups::load();
+
+    let is_single_group = processor_groups.get_group_count() == 1;
+    if is_single_group {
+        return get_group_affinity_mask(process, 0, &processor_groups);
+    }
+
+    let mut cpu_set_count: u32 = 0;
+    _ = unsafe { GetProcessDefaultCpuSets(process, None, &mut cpu_set_count) };
+    let are_cpu_sets = cpu_set_count > 0;
+    if are_cpu_sets {
+        let mut cpu_set_ids = vec![0u32; cpu_set_count as usize];
+        unsafe { GetProcessDefaultCpuSets(process, Some(&mut cpu_set_ids), &mut cpu_set_count) }
+            .ok()?;
+        info!("Got default CPU sets.");
+        return Ok(get_cpu_sets()?
+            .iter()
+            .filter(|cpu_set| cpu_set_ids.contains(&cpu_set.get_id()))
+            .map(|cpu_set| {
+                processor_groups.get_logical_index(cpu_set.get_group(), cpu_set.get_logical())
+            })
+            .collect());
+    }
+
+    let mut group_count = processor_groups.get_group_count() as u16;
+    let mut groups = vec![0u16; group_count as usize];
+    unsafe { GetProcessGroupAffinity(process, &mut group_count, groups.as_mut_ptr()) }.ok()?;
+    info!("Got group affinity.");
+    match &groups[..group_count as usize] {
+        [group] => get_group_affinity_mask(process, *group, &processor_groups),
+        groups => {
+            let mut process_mask = CpuMask::new();
+            for &group in groups {
+                process_mask.union_with(&processor_groups.get_all_of_group(group));
+            }
+            Ok(process_mask)
         }
-    };
-    let are_no_sets = output_size == 0;
-    if are_no_sets {
-        Err("There are no CPU sets!?")?;
     }
+}
 
-    let output_length = output_size as usize / payload_size;
+#[cfg(target_os = "windows")]
+fn get_group_affinity_mask(
+    process: HANDLE,
+    group: u16,
+    processor_groups: &ProcessorGroups,
+) -> ResultBtAny<CpuMask> {
+    let mut process_mask: usize = 0;
+    let mut system_mask: usize = 0;
+    unsafe {
+        GetProcessAffinityMask(
+            process,
+            &mut process_mask as *mut usize,
+            &mut system_mask as *mut usize,
+        )?;
+    }
+    info!("Got process affinity mask.");
+
+    Ok(processor_groups.to_mask(group, process_mask))
+}
+
+/// `SetProcessAffinityMask` is confined to a single processor group, so bigger
+/// machines are pinned through default CPU sets instead.
+#[cfg(target_os = "windows")]
+fn set_group_aware_affinity(process: HANDLE, cpu_selections: &CpuMask) -> ResultBtAny<()> {
+    let processor_groups = ProcessorGroups::load();
+
+    let is_single_group = processor_groups.get_group_count() == 1;
+    if is_single_group {
+        let group_mask = processor_groups.get_group_mask(cpu_selections, 0);
+        unsafe { SetProcessAffinityMask(process, group_mask)? };
+        return Ok(());
+    }
+
+    let cpu_set_ids: Vec<_> = get_cpu_sets()?
+        .iter()
+        .filter(|cpu_set| {
+            let cpu_id =
+                processor_groups.get_logical_index(cpu_set.get_group(), cpu_set.get_logical());
+            cpu_selections.get_is_set(cpu_id)
+        })
+        .map(SystemCpuSetInformation::get_id)
+        .collect();
+    unsafe { SetProcessDefaultCpuSets(process, Some(&cpu_set_ids)) }.ok()?;
+    info!("Set default CPU sets.");
 
-    Ok(cpu_sets[0..output_length].to_vec())
+    Ok(())
 }
 
-async fn set_cpu_affinity_of_process(
+pub(crate) async fn set_cpu_affinity_of_process(
     #[allow(unused_variables)] process: &IrAProcess,
     cpu_selections: &CpuSelections,
 ) -> ResultBtAny<()> {
@@ -345,30 +1543,41 @@ async fn set_cpu_affinity_of_process(
     {
         let process = unsafe {
             let should_inherit_handle = false;
-            let process = OpenProcess(PROCESS_SET_INFORMATION, should_inherit_handle, process.id)?;
+            let process = OpenProcess(
+                PROCESS_SET_INFORMATION | PROCESS_SET_LIMITED_INFORMATION,
+                should_inherit_handle,
+                process.id,
+            )?;
             info!("Got process handle.");
             process
         };
 
+        let is_set = set_group_aware_affinity(process, &cpu_selections);
         unsafe {
-            let is_set = SetProcessAffinityMask(process, cpu_selections);
             CloseHandle(process)?;
-            info!("Closed handle.");
-            is_set?;
-            info!("Set CPU affinity.");
         }
+        info!("Closed handle.");
+        is_set?;
+        info!("Set CPU affinity.");
 
         Ok(())
     }
 
-    #[cfg(not(target_os = "windows"))]
-    unimplemented!()
-}
+    #[cfg(target_os = "linux")]
+    {
+        let cpu_set = CpuSet::from_mask(&cpu_selections);
+        for thread_id in get_thread_ids(process.id)? {
+            match cpu_set.set_of_thread(thread_id) {
+                Ok(()) => {}
+                Err(e) if get_is_thread_gone(&e) => continue,
+                Err(e) => Err(e)?,
+            }
+        }
+        info!("Set CPU affinity.");
 
-#[tokio::test]
-#[cfg(target_os = "windows")]
-async fn getting_cpu_sets() {
-    let cpu_sets: Vec<_> = get_cpu_sets().await.unwrap();
-    let system_info = System::new_all();
-    assert_eq!(cpu_sets.len(), system_info.cpus().len());
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    Err("Setting affinities isn't supported on this platform.")?
 }
@@ -6,6 +6,9 @@ mod windows {
     use std::fmt::{Debug, Formatter, Error};
 
     use windows::Win32::System::SystemInformation::{SYSTEM_CPU_SET_INFORMATION, SYSTEM_CPU_SET_INFORMATION_0_0};
+    use windows::Win32::System::Threading::{GetActiveProcessorCount, GetActiveProcessorGroupCount};
+
+    use crate::selections::CpuMask;
 
     pub struct SystemCpuSetInformation(SYSTEM_CPU_SET_INFORMATION);
 
@@ -24,6 +27,10 @@ mod windows {
             self.get().Id
         }
 
+        pub fn get_group(&self) -> u16 {
+            self.get().Group
+        }
+
         pub fn get_logical(&self) -> u8 {
             self.get().LogicalProcessorIndex
         }
@@ -31,15 +38,335 @@ mod windows {
         pub fn get_physical(&self) -> u8 {
             self.get().CoreIndex
         }
+
+        pub fn get_cache(&self) -> u8 {
+            self.get().LastLevelCacheIndex
+        }
+
+        pub fn get_numa_node(&self) -> u8 {
+            self.get().NumaNodeIndex
+        }
+
+        pub fn get_efficiency_class(&self) -> u8 {
+            self.get().EfficiencyClass
+        }
     }
 
     impl Debug for SystemCpuSetInformation {
         fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
             f.debug_struct("SystemCpuSetInformation")
                 .field("id", &self.get().Id)
+                .field("group", &self.get().Group)
                 .field("logical", &self.get().LogicalProcessorIndex)
                 .field("physical", &self.get().CoreIndex)
                 .finish()
         }
     }
+
+    /// Maps Windows processor groups, each at most 64 CPUs wide, onto the flat
+    /// logical indices used by `CpuMask`.
+    #[derive(Debug, Clone)]
+    pub struct ProcessorGroups {
+        offsets: Vec<usize>,
+        counts: Vec<usize>,
+    }
+
+    impl ProcessorGroups {
+        pub fn load() -> Self {
+            let group_count = unsafe { GetActiveProcessorGroupCount() };
+            let mut offsets = vec![];
+            let mut counts = vec![];
+            let mut offset = 0;
+            for group in 0..group_count {
+                let count = unsafe { GetActiveProcessorCount(group) } as usize;
+                offsets.push(offset);
+                counts.push(count);
+                offset += count;
+            }
+            Self { offsets, counts }
+        }
+
+        pub fn get_group_count(&self) -> usize {
+            self.offsets.len()
+        }
+
+        pub fn get_logical_index(&self, group: u16, group_index: u8) -> usize {
+            self.offsets.get(group as usize).copied().unwrap_or_default() + group_index as usize
+        }
+
+        pub fn to_mask(&self, group: u16, group_mask: usize) -> CpuMask {
+            let mut mask = CpuMask::new();
+            if let Some(&offset) = self.offsets.get(group as usize) {
+                mask.set_range(offset, group_mask as u64);
+            }
+            mask
+        }
+
+        pub fn get_group_mask(&self, mask: &CpuMask, group: u16) -> usize {
+            let group = group as usize;
+            match (self.offsets.get(group), self.counts.get(group)) {
+                (Some(&offset), Some(&count)) => mask.get_range(offset, count) as usize,
+                _ => 0,
+            }
+        }
+
+        pub fn get_all_of_group(&self, group: u16) -> CpuMask {
+            let group = group as usize;
+            match (self.offsets.get(group), self.counts.get(group)) {
+                (Some(&offset), Some(&count)) => (offset..offset + count).collect(),
+                _ => CpuMask::new(),
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::*;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        fs, io,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd},
+        time::Duration,
+    };
+
+    use crate::selections::CpuMask;
+
+    /// Heap backed `cpu_set_t`, sized like `CPU_ALLOC` so machines past
+    /// `CPU_SETSIZE` CPUs still fit.
+    pub struct CpuSet(Vec<libc::c_ulong>);
+
+    impl CpuSet {
+        const WORD_BITS: usize = libc::c_ulong::BITS as usize;
+
+        const MAX_WORD_COUNT: usize = 1 << 16;
+
+        fn new(word_count: usize) -> Self {
+            Self(vec![0; word_count.max(1)])
+        }
+
+        fn get_size(&self) -> usize {
+            std::mem::size_of_val(self.0.as_slice())
+        }
+
+        pub fn from_mask(mask: &CpuMask) -> Self {
+            let cpu_count = mask.iter().last().map_or(0, |cpu_id| cpu_id + 1);
+            let mut cpu_set = Self::new(cpu_count.div_ceil(Self::WORD_BITS));
+            for cpu_id in mask.iter() {
+                cpu_set.0[cpu_id / Self::WORD_BITS] |= 1 << (cpu_id % Self::WORD_BITS);
+            }
+            cpu_set
+        }
+
+        pub fn to_mask(&self) -> CpuMask {
+            let mut mask = CpuMask::new();
+            for (word_index, word) in self.0.iter().enumerate() {
+                for bit_index in 0..Self::WORD_BITS {
+                    let is_selected = word & (1 << bit_index) != 0;
+                    if is_selected {
+                        mask.set(word_index * Self::WORD_BITS + bit_index);
+                    }
+                }
+            }
+            mask
+        }
+
+        pub fn get_of_thread(thread_id: libc::pid_t) -> io::Result<Self> {
+            let word_count = std::mem::size_of::<libc::cpu_set_t>() / size_of::<libc::c_ulong>();
+            let mut cpu_set = Self::new(word_count);
+            loop {
+                let is_error = unsafe {
+                    libc::sched_getaffinity(
+                        thread_id,
+                        cpu_set.get_size(),
+                        cpu_set.0.as_mut_ptr().cast::<libc::cpu_set_t>(),
+                    )
+                } != 0;
+                if !is_error {
+                    return Ok(cpu_set);
+                }
+
+                // The kernel rejects buffers smaller than its own CPU mask.
+                let e = io::Error::last_os_error();
+                let can_grow = cpu_set.0.len() < Self::MAX_WORD_COUNT;
+                if e.raw_os_error() != Some(libc::EINVAL) || !can_grow {
+                    return Err(e);
+                }
+                cpu_set = Self::new(cpu_set.0.len() * 2);
+            }
+        }
+
+        pub fn set_of_thread(&self, thread_id: libc::pid_t) -> io::Result<()> {
+            let is_error = unsafe {
+                libc::sched_setaffinity(
+                    thread_id,
+                    self.get_size(),
+                    self.0.as_ptr().cast::<libc::cpu_set_t>(),
+                )
+            } != 0;
+            if is_error {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    /// Affinity is per thread on Linux, so callers walk `/proc/<pid>/task` to
+    /// cover the whole process.
+    pub fn get_thread_ids(process_id: u32) -> io::Result<Vec<libc::pid_t>> {
+        let mut thread_ids = vec![];
+        for task in fs::read_dir(format!("/proc/{process_id}/task"))? {
+            let thread_id = task?.file_name().to_str().and_then(|id| id.parse().ok());
+            if let Some(thread_id) = thread_id {
+                thread_ids.push(thread_id);
+            }
+        }
+        Ok(thread_ids)
+    }
+
+    pub fn get_process_ids() -> io::Result<Vec<u32>> {
+        let mut process_ids = vec![];
+        for entry in fs::read_dir("/proc")? {
+            let process_id = entry?.file_name().to_str().and_then(|id| id.parse().ok());
+            if let Some(process_id) = process_id {
+                process_ids.push(process_id);
+            }
+        }
+        Ok(process_ids)
+    }
+
+    pub fn get_is_thread_gone(e: &io::Error) -> bool {
+        e.raw_os_error() == Some(libc::ESRCH)
+    }
+
+    /// Reads one variable from `/proc/<pid>/environ`, which is the environment
+    /// the process was exec'd with.
+    pub fn get_environment_variable(process_id: u32, key: &str) -> io::Result<Option<String>> {
+        let environment = fs::read(format!("/proc/{process_id}/environ"))?;
+        let prefix = format!("{key}=");
+        let value = environment
+            .split(|&byte| byte == 0)
+            .find_map(|variable| variable.strip_prefix(prefix.as_bytes()))
+            .map(|value| String::from_utf8_lossy(value).into_owned());
+        Ok(value)
+    }
+
+    /// A netlink socket subscribed to the kernel's process connector, which
+    /// needs `CAP_NET_ADMIN`.
+    pub struct ProcessConnector(OwnedFd);
+
+    impl ProcessConnector {
+        const CN_IDX_PROC: u32 = 1;
+
+        const CN_VAL_PROC: u32 = 1;
+
+        const PROC_CN_MCAST_LISTEN: u32 = 1;
+
+        /// Receives give up after `receive_timeout`, so a reader can notice
+        /// it's no longer wanted.
+        pub fn open(receive_timeout: Duration) -> io::Result<Self> {
+            let socket = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_CONNECTOR,
+                )
+            };
+            if socket < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let connector = Self(unsafe { OwnedFd::from_raw_fd(socket) });
+
+            let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups = Self::CN_IDX_PROC;
+            let is_error = unsafe {
+                libc::bind(
+                    socket,
+                    (&address as *const libc::sockaddr_nl).cast::<libc::sockaddr>(),
+                    size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            } != 0;
+            if is_error {
+                return Err(io::Error::last_os_error());
+            }
+
+            let timeout = libc::timeval {
+                tv_sec: receive_timeout.as_secs() as libc::time_t,
+                tv_usec: receive_timeout.subsec_micros() as libc::suseconds_t,
+            };
+            let is_error = unsafe {
+                libc::setsockopt(
+                    socket,
+                    libc::SOL_SOCKET,
+                    libc::SO_RCVTIMEO,
+                    (&timeout as *const libc::timeval).cast::<libc::c_void>(),
+                    size_of::<libc::timeval>() as libc::socklen_t,
+                )
+            } != 0;
+            if is_error {
+                return Err(io::Error::last_os_error());
+            }
+
+            connector.send_listen()?;
+            Ok(connector)
+        }
+
+        /// An `nlmsghdr`, then a `cn_msg`, then the `proc_cn_mcast_op`.
+        fn send_listen(&self) -> io::Result<()> {
+            let operation = Self::PROC_CN_MCAST_LISTEN.to_ne_bytes();
+            let message_length = 16 + 20 + operation.len();
+            let mut message = Vec::with_capacity(message_length);
+            message.extend((message_length as u32).to_ne_bytes());
+            message.extend((libc::NLMSG_DONE as u16).to_ne_bytes());
+            message.extend(0u16.to_ne_bytes());
+            message.extend(0u32.to_ne_bytes());
+            message.extend(std::process::id().to_ne_bytes());
+            message.extend(Self::CN_IDX_PROC.to_ne_bytes());
+            message.extend(Self::CN_VAL_PROC.to_ne_bytes());
+            message.extend(0u32.to_ne_bytes());
+            message.extend(0u32.to_ne_bytes());
+            message.extend((operation.len() as u16).to_ne_bytes());
+            message.extend(0u16.to_ne_bytes());
+            message.extend(operation);
+
+            let sent_length = unsafe {
+                libc::send(
+                    self.0.as_raw_fd(),
+                    message.as_ptr().cast::<libc::c_void>(),
+                    message.len(),
+                    0,
+                )
+            };
+            if sent_length < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        pub fn receive(&self, buffer: &mut [u8]) -> io::Result<usize> {
+            let received_length = unsafe {
+                libc::recv(
+                    self.0.as_raw_fd(),
+                    buffer.as_mut_ptr().cast::<libc::c_void>(),
+                    buffer.len(),
+                    0,
+                )
+            };
+            if received_length < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(received_length as usize)
+        }
+    }
+
+    pub fn get_is_timed_out(e: &io::Error) -> bool {
+        matches!(e.raw_os_error(), Some(libc::EAGAIN | libc::EINTR))
+    }
+
+    /// Events were dropped because the socket's buffer filled up.
+    pub fn get_is_overrun(e: &io::Error) -> bool {
+        e.raw_os_error() == Some(libc::ENOBUFS)
+    }
 }